@@ -1,3 +1,4 @@
+use crate::interval::Interval;
 use crate::ray::Ray;
 use crate::vec3::Point3;
 
@@ -20,44 +21,21 @@ impl Aabb {
         &self.maximum
     }
 
-    pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
-        // the manual loop unroll LMAO
-        let intervals = [
-            (
-                self.minimum.get_x(),
-                self.maximum.get_x(),
-                r.get_origin().get_x(),
-                r.get_direction().get_x(),
-            ),
-            (
-                self.minimum.get_y(),
-                self.maximum.get_y(),
-                r.get_origin().get_y(),
-                r.get_direction().get_y(),
-            ),
-            (
-                self.minimum.get_z(),
-                self.maximum.get_z(),
-                r.get_origin().get_z(),
-                r.get_direction().get_z(),
-            ),
-        ];
-
-        for (min, max, origin, direction) in intervals {
-            // x
-            let inv_d = 1.0 / direction;
-            let mut t0 = (min - origin) * inv_d;
-            let mut t1 = (max - origin) * inv_d;
-            if inv_d < 0.0 {
-                (t0, t1) = (t1, t0)
-            }
-            t_min = if t0 > t_min { t0 } else { t_min };
-            t_max = if t1 < t_max { t1 } else { t_max };
-            if t_max <= t_min {
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut ray_t = Interval::new(t_min, t_max);
+        let bounds = [&self.minimum, &self.maximum];
+        let (origin, inv_d, sign) = (r.get_origin(), r.get_inv_direction(), r.get_sign());
+        for axis in 0..3 {
+            // the ray's sign picks which plane of the slab it meets first
+            let near = component(bounds[sign[axis]], axis);
+            let far = component(bounds[1 - sign[axis]], axis);
+            let (o, inv) = (component(origin, axis), component(inv_d, axis));
+            ray_t = ray_t.intersect(&Interval::new((near - o) * inv, (far - o) * inv));
+            if ray_t.size() <= 0.0 {
                 return false;
             }
         }
-        return true;
+        true
     }
 
     pub fn surrounding_box(box0: &Aabb, box1: &Aabb) -> Aabb {
@@ -76,3 +54,72 @@ impl Aabb {
         Aabb::new(small, big)
     }
 }
+
+pub(crate) fn component(v: &Point3, axis: usize) -> f64 {
+    match axis {
+        0 => v.get_x(),
+        1 => v.get_y(),
+        _ => v.get_z(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mutil::{self, rng};
+    use crate::vec3::{random_range, Vec3};
+    use rand::Rng;
+
+    // the textbook slab test, swapping each axis's hits when the direction is negative
+    fn hit_swapping(b: &Aabb, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut ray_t = Interval::new(t_min, t_max);
+        for axis in 0..3 {
+            let inv_d = 1.0 / component(r.get_direction(), axis);
+            let origin = component(r.get_origin(), axis);
+            let mut t0 = (component(b.get_min(), axis) - origin) * inv_d;
+            let mut t1 = (component(b.get_max(), axis) - origin) * inv_d;
+            if inv_d < 0.0 {
+                (t0, t1) = (t1, t0)
+            }
+            ray_t = ray_t.intersect(&Interval::new(t0, t1));
+            if ray_t.size() <= 0.0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    #[test]
+    fn sign_lookup_matches_swapping() {
+        mutil::seed(3);
+        let b = Aabb::new(Point3::new(-1, -0.5, 0), Point3::new(1, 2, 0.25));
+        let mut hits = 0;
+        for n in 0..20000 {
+            let origin = random_range(-4.0, 4.0);
+            // aimed roughly at the box, so a good share hit
+            let mut direction = (random_range(-1.5, 1.5) - origin).unit();
+            // now and then run exactly along an axis plane, either way
+            if n % 10 == 0 {
+                direction.set_y(if n % 20 == 0 { 0.0 } else { -0.0 });
+            }
+            let r = Ray::new(&origin, &direction, 0.0);
+            let t_max = rng().gen_range(0.5..10.0);
+            let expected = hit_swapping(&b, &r, 0.001, t_max);
+            assert_eq!(
+                b.hit(&r, 0.001, t_max),
+                expected,
+                "{} {}",
+                origin,
+                direction
+            );
+            hits += expected as usize;
+        }
+        // plenty of both outcomes
+        assert!(hits > 2000 && hits < 18000, "{}", hits);
+
+        let down = Ray::new(&Point3::new(0, 5, 0.1), &Vec3::new(0, -1, 0), 0.0);
+        assert_eq!(down.get_sign(), [0, 1, 0]);
+        assert!(b.hit(&down, 0.001, f64::INFINITY));
+        assert!(!b.hit(&down, 0.001, 2.0));
+    }
+}
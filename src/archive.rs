@@ -0,0 +1,160 @@
+//! Renders saved with a JSON sidecar recording how they were made, so they can be redone later.
+
+use crate::screen::ImageError;
+use crate::world::{get_world_cam, render_to_screen, Config};
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// 64-bit FNV-1a, so a sidecar's hash means the same thing to every build that reads it.
+/// Integers are fed in little-endian at a fixed width; `DefaultHasher` promises neither.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The sidecar for a render of scene `scene` with `config` that took `render_time`.
+/// `inputs_hash` covers the rest of the settings that change the traced radiance; post
+/// effects such as exposure and tone mapping aren't recorded, and a custom sky or filter
+/// only hashes the same within one run.
+pub fn sidecar_json(scene: usize, config: &Config, render_time: Duration) -> String {
+    let mut inputs = Fnv1a::new();
+    config.hash_inputs(&mut inputs);
+    let seed = config
+        .get_seed()
+        .map_or("null".to_string(), |seed| seed.to_string());
+    let fields = [
+        ("scene", scene.to_string()),
+        ("seed", seed),
+        ("width", config.get_image_width().to_string()),
+        ("height", config.get_image_height().to_string()),
+        (
+            "samples_per_pixel",
+            config.get_samples_per_pixel().to_string(),
+        ),
+        ("max_depth", config.get_max_depth().to_string()),
+        ("threads", config.get_threads().to_string()),
+        (
+            "light_sampling",
+            format!("\"{:?}\"", config.get_light_sampling()),
+        ),
+        // as a string: JSON readers often keep numbers as doubles, which can't hold a u64
+        ("inputs_hash", format!("\"{:016x}\"", inputs.finish())),
+        (
+            "render_seconds",
+            format!("{:.3}", render_time.as_secs_f64()),
+        ),
+    ];
+    let body: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("  \"{}\": {}", key, value))
+        .collect();
+    format!("{{\n{}\n}}\n", body.join(",\n"))
+}
+
+/// Render scene `scene` to `image` as PNG and write its sidecar next to it, with the
+/// extension swapped for `.json`. Returns the sidecar's path.
+pub fn render(scene: usize, image: &Path, config: &Config) -> Result<PathBuf, ImageError> {
+    let (world, cam, background) = get_world_cam(scene)?;
+    let start = Instant::now();
+    let screen = render_to_screen(world, cam, background, config);
+    let render_time = start.elapsed();
+    screen
+        .write_to_png_file(&image.to_string_lossy())
+        .map_err(ImageError::Io)?;
+    let sidecar = image.with_extension("json");
+    std::fs::write(&sidecar, sidecar_json(scene, config, render_time)).map_err(ImageError::Io)?;
+    Ok(sidecar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::LightSampling;
+    use std::sync::Arc;
+
+    #[test]
+    fn render_writes_image_and_matching_sidecar() {
+        let dir = std::env::temp_dir().join("archive_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("cornell.png");
+        let config = Config::new(1.0, 12, 2, 3, 1)
+            .with_seed(42)
+            .with_light_sampling(LightSampling::Mis)
+            .with_progress(Arc::new(|_| ()));
+        let sidecar = render(4, &image, &config).unwrap();
+
+        assert_eq!(sidecar, dir.join("cornell.json"));
+        assert!(std::fs::read(&image).unwrap().starts_with(b"\x89PNG"));
+        let json = std::fs::read_to_string(&sidecar).unwrap();
+        let field = |key: &str| {
+            let prefix = format!("\"{}\": ", key);
+            json.lines()
+                .find_map(|line| line.trim().strip_prefix(prefix.as_str()))
+                .map(|value| value.trim_end_matches(',').to_string())
+                .unwrap_or_else(|| panic!("no {} in {}", key, json))
+        };
+        assert_eq!(field("scene"), "4");
+        assert_eq!(field("seed"), "42");
+        assert_eq!(field("width"), "12");
+        assert_eq!(field("height"), "12");
+        assert_eq!(field("samples_per_pixel"), "2");
+        assert_eq!(field("max_depth"), "3");
+        assert_eq!(field("threads"), "1");
+        assert_eq!(field("light_sampling"), "\"Mis\"");
+        assert!(field("render_seconds").parse::<f64>().unwrap() >= 0.0);
+        assert!(json.starts_with("{\n") && json.ends_with("}\n"));
+
+        // the hash tells apart configs that differ in unlisted settings
+        let other = sidecar_json(4, &config.with_stop_at_emission(), Duration::ZERO);
+        assert_ne!(
+            other.lines().find(|l| l.contains("inputs_hash")).unwrap(),
+            json.lines().find(|l| l.contains("inputs_hash")).unwrap()
+        );
+    }
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = Fnv1a::new();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(b"foobar"), 0x85944171f73967e8);
+        // integers go in as fixed-width little-endian bytes on every target
+        let mut wide = Fnv1a::new();
+        wide.write_usize(7);
+        assert_eq!(wide.finish(), hash(&7u64.to_le_bytes()));
+    }
+}
@@ -0,0 +1,93 @@
+//! Fixed renders for tracking performance over time. Each scene prints one `key=value` line.
+
+use crate::screen::ImageError;
+use crate::stats::RenderStats;
+use crate::world::{get_world_cam, render_hdr, Config};
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Scenes the `--bench` run renders: checkered spheres, simple light, cornell box, benchmark scene.
+pub const SCENES: [usize; 4] = [0, 3, 4, 9];
+pub const SEED: u64 = 1;
+
+/// Settings the `--bench` run uses, so numbers stay comparable between commits.
+pub fn config(threads: usize) -> Config {
+    Config::new(16.0 / 9.0, 160, 8, 10, threads).with_seed(SEED)
+}
+
+#[derive(Debug)]
+pub enum BenchError {
+    Scene(ImageError),
+    Io(io::Error),
+}
+
+/// Render each of `scenes` with `config` and write a metrics line per scene, then a total.
+pub fn run(out: &mut impl Write, scenes: &[usize], config: &Config) -> Result<(), BenchError> {
+    let start = Instant::now();
+    let mut total_rays = 0;
+    for &scene in scenes {
+        let (world, cam, background) = get_world_cam(scene).map_err(BenchError::Scene)?;
+        let stats = Arc::new(RenderStats::new());
+        let scene_start = Instant::now();
+        render_hdr(
+            world,
+            cam,
+            background,
+            &config.clone().with_stats(stats.clone()),
+        );
+        let seconds = scene_start.elapsed().as_secs_f64();
+        total_rays += stats.get_rays();
+        writeln!(
+            out,
+            "bench scene={} samples={} rays={} seconds={:.4} rays_per_second={:.0}",
+            scene,
+            stats.get_samples(),
+            stats.get_rays(),
+            seconds,
+            stats.get_rays() as f64 / seconds
+        )
+        .map_err(BenchError::Io)?;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    writeln!(
+        out,
+        "bench total rays={} seconds={:.4} rays_per_second={:.0}",
+        total_rays,
+        seconds,
+        total_rays as f64 / seconds
+    )
+    .map_err(BenchError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emits_metrics_per_scene() {
+        let mut out = Vec::new();
+        run(
+            &mut out,
+            &[0, 9],
+            &Config::new(2.0, 8, 1, 3, 1).with_seed(SEED),
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        for line in &lines[..2] {
+            for key in [
+                "scene=",
+                "samples=32 ",
+                "rays=",
+                "seconds=",
+                "rays_per_second=",
+            ] {
+                assert!(line.contains(key), "{:?} is missing {:?}", line, key);
+            }
+        }
+        assert!(lines[2].starts_with("bench total rays="));
+        assert!(lines[2].contains("rays_per_second="));
+    }
+}
@@ -1,13 +1,33 @@
 use crate::aabb::Aabb;
-use crate::hit::{HitRecord, Hittable, HittableList};
-use rand::{thread_rng, Rng};
+use crate::hit::{gather_lights, HitRecord, Hittable, HittableList};
+use crate::mutil::rng;
+use crate::ray::Ray;
+use crate::vec3::Point3;
+use rand::Rng;
 use std::cmp::Ordering;
+use std::fmt::Write;
 use std::sync::Arc;
 
+enum BvhChild {
+    Node(Box<BvhNode>),
+    Leaf(Arc<Box<dyn Hittable + Sync>>),
+}
+
+impl BvhChild {
+    fn as_hittable(&self) -> &dyn Hittable {
+        match self {
+            BvhChild::Node(node) => node.as_ref(),
+            BvhChild::Leaf(obj) => obj.as_ref().as_ref(),
+        }
+    }
+}
+
 pub struct BvhNode {
-    left: Arc<Box<dyn Hittable + Sync>>,
-    right: Arc<Box<dyn Hittable + Sync>>,
+    left: BvhChild,
+    right: BvhChild,
     bbox: Aabb,
+    // the children were split along this axis, the left one holding the lower boxes
+    axis: usize,
 }
 
 impl BvhNode {
@@ -18,14 +38,15 @@ impl BvhNode {
         time0: f64,
         time1: f64,
     ) -> BvhNode {
-        let mut rng = thread_rng();
+        let mut rng = rng();
 
         let mut objects = src_objects.clone();
         let axis: u8 = rng.gen_range(0..2);
         let box_compare = move |a: &Arc<Box<dyn Hittable + Sync>>,
                                 b: &Arc<Box<dyn Hittable + Sync>>| {
-            let box_a = a.bounding_box(0.0, 0.0).unwrap();
-            let box_b = b.bounding_box(0.0, 0.0).unwrap();
+            // sort by the space swept over the whole shutter, not just the opening pose
+            let box_a = a.bounding_box(time0, time1).unwrap();
+            let box_b = b.bounding_box(time0, time1).unwrap();
             match axis {
                 0 => match box_a.get_min().get_x() < box_b.get_min().get_x() {
                     true => Ordering::Less,
@@ -51,27 +72,29 @@ impl BvhNode {
 
         // consider adding case == 3 to reduce recursive base cases
         if object_span == 1 {
-            left = objects[start].clone();
-            right = objects[start].clone();
+            left = BvhChild::Leaf(objects[start].clone());
+            right = BvhChild::Leaf(objects[start].clone());
         } else if object_span == 2 {
             if box_compare(&objects[start], &objects[start + 1]) == Ordering::Less {
-                left = objects[start].clone();
-                right = objects[start + 1].clone();
+                left = BvhChild::Leaf(objects[start].clone());
+                right = BvhChild::Leaf(objects[start + 1].clone());
             } else {
-                left = objects[start + 1].clone();
-                right = objects[start].clone();
+                left = BvhChild::Leaf(objects[start + 1].clone());
+                right = BvhChild::Leaf(objects[start].clone());
             }
         } else {
             objects[start..start + object_span].sort_by(box_compare);
             let mid = start + object_span / 2;
-            left = Arc::new(Box::new(BvhNode::new(&objects, start, mid, time0, time1)));
-            right = Arc::new(Box::new(BvhNode::new(&objects, mid, end, time0, time1)));
+            left = BvhChild::Node(Box::new(BvhNode::new(&objects, start, mid, time0, time1)));
+            right = BvhChild::Node(Box::new(BvhNode::new(&objects, mid, end, time0, time1)));
         }
 
         let left_box = left
+            .as_hittable()
             .bounding_box(time0, time1)
             .expect("No bounding box in bvh node constructor..");
         let right_box = right
+            .as_hittable()
             .bounding_box(time0, time1)
             .expect("No bounding box in bvh node constructor..");
 
@@ -79,7 +102,25 @@ impl BvhNode {
 
         //eprintln!("{} {}", bbox.get_min(), bbox.get_max());
 
-        BvhNode { left, right, bbox }
+        BvhNode {
+            left,
+            right,
+            bbox,
+            axis: axis as usize,
+        }
+    }
+
+    // the child `r` reaches first along the split axis, then the other one; a node holding a
+    // single object has no other one
+    fn ordered(&self, r: &Ray) -> (&BvhChild, Option<&BvhChild>) {
+        let (near, far) = match r.get_sign()[self.axis] {
+            0 => (&self.left, &self.right),
+            _ => (&self.right, &self.left),
+        };
+        match (near, far) {
+            (BvhChild::Leaf(a), BvhChild::Leaf(b)) if Arc::ptr_eq(a, b) => (near, None),
+            _ => (near, Some(far)),
+        }
     }
 
     pub fn from_list(list: &HittableList, time0: f64, time1: f64) -> BvhNode {
@@ -91,27 +132,301 @@ impl BvhNode {
             time1,
         )
     }
+
+    /// Same result as `hit`, but walks the tree with an explicit stack instead of recursing.
+    pub fn hit_iterative(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut closest: Option<HitRecord> = None;
+        let mut closest_so_far = t_max;
+        let mut stack: Vec<&BvhNode> = vec![self];
+
+        while let Some(node) = stack.pop() {
+            if !node.bbox.hit(r, t_min, closest_so_far) {
+                continue;
+            }
+            // leaves are tested as soon as their parent is popped, nearer one first, and the
+            // nearer inner node goes on top so its hits can cut the other one short
+            let (near, far) = node.ordered(r);
+            for child in [Some(near), far].into_iter().flatten() {
+                if let BvhChild::Leaf(obj) = child {
+                    if let Some(rec) = obj.hit(r, t_min, closest_so_far) {
+                        closest_so_far = rec.get_t();
+                        closest = Some(rec);
+                    }
+                }
+            }
+            for child in [far, Some(near)].into_iter().flatten() {
+                if let BvhChild::Node(inner) = child {
+                    stack.push(inner);
+                }
+            }
+        }
+        closest
+    }
+
+    /// Graphviz description of the tree: one box per node labeled with its bounds, one
+    /// ellipse per leaf. Render with `dot -Tsvg` to eyeball balance and overlap.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph bvh {\n");
+        self.write_dot(&mut dot, &mut 0);
+        dot += "}\n";
+        dot
+    }
+
+    // writes this subtree and returns the id of its root
+    fn write_dot(&self, dot: &mut String, next_id: &mut usize) -> usize {
+        let point = |p: &Point3| format!("({:.2}, {:.2}, {:.2})", p.get_x(), p.get_y(), p.get_z());
+        let id = *next_id;
+        *next_id += 1;
+        writeln!(
+            dot,
+            "  n{} [shape=box, label=\"{}\\n{}\"];",
+            id,
+            point(self.bbox.get_min()),
+            point(self.bbox.get_max())
+        )
+        .unwrap();
+        let children = match (&self.left, &self.right) {
+            (BvhChild::Leaf(a), BvhChild::Leaf(b)) if Arc::ptr_eq(a, b) => vec![&self.left],
+            _ => vec![&self.left, &self.right],
+        };
+        for child in children {
+            let child_id = match child {
+                BvhChild::Node(node) => node.write_dot(dot, next_id),
+                BvhChild::Leaf(_) => {
+                    let leaf = *next_id;
+                    *next_id += 1;
+                    writeln!(dot, "  n{} [label=\"leaf\"];", leaf).unwrap();
+                    leaf
+                }
+            };
+            writeln!(dot, "  n{} -> n{};", id, child_id).unwrap();
+        }
+        id
+    }
 }
 
 impl Hittable for BvhNode {
-    fn hit(&self, r: &crate::ray::Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        // TODO: wtf is this lol
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         if !self.bbox.hit(r, t_min, t_max) {
             return None;
         }
-        let leftside_hit = self.left.hit(r, t_min, t_max);
-        if leftside_hit.is_some() {
-            let left = leftside_hit.unwrap();
-            match self.right.hit(r, t_min, left.get_t()) {
-                Some(rec) => return Some(rec),
-                None => (),
-            }
-            return Some(left);
+        // nearer child first, so a hit there shrinks the range the other one is tested over
+        let (near, far) = self.ordered(r);
+        let near_hit = near.as_hittable().hit(r, t_min, t_max);
+        let Some(far) = far else {
+            return near_hit;
+        };
+        let t_max = near_hit.as_ref().map_or(t_max, |rec| rec.get_t());
+        far.as_hittable().hit(r, t_min, t_max).or(near_hit)
+    }
+    fn hit_into(&self, r: &Ray, t_min: f64, t_max: f64, out: &mut HitRecord) -> bool {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return false;
         }
-        self.right.hit(r, t_min, t_max)
+        let (near, far) = self.ordered(r);
+        let hit_near = near.as_hittable().hit_into(r, t_min, t_max, out);
+        let Some(far) = far else {
+            return hit_near;
+        };
+        let t_max = if hit_near { out.get_t() } else { t_max };
+        let hit_far = far.as_hittable().hit_into(r, t_min, t_max, out);
+        hit_near || hit_far
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         // TODO don't clone?
         Some(self.bbox.clone())
     }
+    fn leaf_count(&self) -> usize {
+        // a single-object node stores the same leaf on both sides
+        match (&self.left, &self.right) {
+            (BvhChild::Leaf(a), BvhChild::Leaf(b)) if Arc::ptr_eq(a, b) => a.leaf_count(),
+            (left, right) => left.as_hittable().leaf_count() + right.as_hittable().leaf_count(),
+        }
+    }
+    fn depth(&self) -> usize {
+        1 + usize::max(
+            self.left.as_hittable().depth(),
+            self.right.as_hittable().depth(),
+        )
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut gather = |child: &BvhChild| match child {
+            BvhChild::Leaf(obj) => gather_lights(obj, lights),
+            BvhChild::Node(node) => node.collect_lights(lights),
+        };
+        gather(&self.left);
+        match (&self.left, &self.right) {
+            (BvhChild::Leaf(a), BvhChild::Leaf(b)) if Arc::ptr_eq(a, b) => (),
+            (_, right) => gather(right),
+        }
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.bbox.hit(r, t_min, t_max)
+            && (self.left.as_hittable().occluded(r, t_min, t_max)
+                || self.right.as_hittable().occluded(r, t_min, t_max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::{Lambertian, MovingSphere, Sphere};
+    use crate::vec3::{Color, Point3, Vec3};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn spheres(n: usize) -> HittableList {
+        let mut list = HittableList::new();
+        for i in 0..n {
+            list.add(Arc::new(Box::new(Sphere::new(
+                Point3::new(i as f64 * 3.0, 0, 0),
+                1.0,
+                Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+            ))));
+        }
+        list
+    }
+
+    #[test]
+    fn balanced_tree_introspection() {
+        let bvh = BvhNode::from_list(&spheres(8), 0.0, 1.0);
+        assert_eq!(bvh.leaf_count(), 8);
+        assert_eq!(bvh.depth(), 3);
+    }
+
+    #[test]
+    fn dot_lists_every_node_and_edge() {
+        let dot = BvhNode::from_list(&spheres(3), 0.0, 1.0).to_dot();
+        assert!(dot.starts_with("digraph bvh {"));
+        // three inner nodes (one of them holding a single sphere) and three leaves
+        assert_eq!(dot.matches("shape=box").count(), 3);
+        assert_eq!(dot.matches("label=\"leaf\"").count(), 3);
+        assert_eq!(dot.matches(" -> ").count(), 5);
+        assert!(dot.contains("(-1.00, -1.00, -1.00)\\n(7.00, 1.00, 1.00)"));
+    }
+
+    #[test]
+    fn single_object_counted_once() {
+        let bvh = BvhNode::from_list(&spheres(1), 0.0, 1.0);
+        assert_eq!(bvh.leaf_count(), 1);
+        assert_eq!(bvh.depth(), 1);
+    }
+
+    #[test]
+    fn list_introspection() {
+        let mut list = spheres(3);
+        list.add(Arc::new(Box::new(BvhNode::from_list(
+            &spheres(4),
+            0.0,
+            1.0,
+        ))));
+        assert_eq!(list.leaf_count(), 7);
+        assert_eq!(list.depth(), 3);
+    }
+
+    struct Counting {
+        inner: Sphere,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Hittable for Counting {
+        fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.inner.hit(r, t_min, t_max)
+        }
+        fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+            self.inner.bounding_box(time0, time1)
+        }
+    }
+
+    #[test]
+    fn occluded_stops_at_first_hit() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut list = HittableList::new();
+        for i in 0..16 {
+            list.add(Arc::new(Box::new(Counting {
+                inner: Sphere::new(
+                    Point3::new(i as f64 * 3.0, 0, 0),
+                    1.0,
+                    Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+                ),
+                calls: calls.clone(),
+            })));
+        }
+        let bvh = BvhNode::from_list(&list, 0.0, 1.0);
+        // runs through the middle of every sphere
+        let r = Ray::new(&Point3::new(-5, 0, 0), &Vec3::new(1, 0, 0), 0.0);
+
+        for world in [&list as &dyn Hittable, &bvh] {
+            calls.store(0, Ordering::Relaxed);
+            assert!(world.hit(&r, 0.001, f64::INFINITY).is_some());
+            let hit_calls = calls.swap(0, Ordering::Relaxed);
+            assert!(world.occluded(&r, 0.001, f64::INFINITY));
+            let occluded_calls = calls.load(Ordering::Relaxed);
+            assert!(occluded_calls < hit_calls);
+            assert!(!world.occluded(&r, 0.001, 2.0));
+        }
+    }
+
+    #[test]
+    fn nearer_hits_prune_overlapping_children() {
+        crate::mutil::seed(5);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut list = HittableList::new();
+        // a diagonal row of overlapping spheres, so either split axis sorts them the same way
+        for i in 0..16 {
+            list.add(Arc::new(Box::new(Counting {
+                inner: Sphere::new(
+                    Point3::new(i as f64 * 1.5, i as f64 * 1.5, 0),
+                    1.2,
+                    Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+                ),
+                calls: calls.clone(),
+            })));
+        }
+        let bvh = BvhNode::from_list(&list, 0.0, 1.0);
+
+        for (origin, direction) in [((-5.0, -5.0), (1, 1)), ((30.0, 30.0), (-1, -1))] {
+            let r = Ray::new(
+                &Point3::new(origin.0, origin.1, 0.0),
+                &Vec3::new(direction.0, direction.1, 0),
+                0.0,
+            );
+            calls.store(0, Ordering::Relaxed);
+            let expected = list.hit(&r, 0.001, f64::INFINITY).unwrap();
+            assert_eq!(calls.swap(0, Ordering::Relaxed), 16);
+
+            let rec = bvh.hit(&r, 0.001, f64::INFINITY).unwrap();
+            let recursive = calls.swap(0, Ordering::Relaxed);
+            let iterative = bvh.hit_iterative(&r, 0.001, f64::INFINITY).unwrap();
+            let iterative_calls = calls.swap(0, Ordering::Relaxed);
+            assert_eq!(rec.get_t(), expected.get_t());
+            assert_eq!(iterative.get_t(), expected.get_t());
+            // the first sphere and the neighbours it overlaps, whichever end the ray starts from
+            assert!(recursive <= 4, "{}", recursive);
+            assert!(iterative_calls <= 4, "{}", iterative_calls);
+        }
+    }
+
+    #[test]
+    fn moving_sphere_bounds_cover_shutter() {
+        let mut list = spheres(3);
+        list.add(Arc::new(Box::new(MovingSphere::new(
+            Point3::new(0, 5, 0),
+            Point3::new(0, 5, 20),
+            0.0,
+            1.0,
+            1.0,
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+        ))));
+        let bvh = BvhNode::from_list(&list, 0.0, 1.0);
+        let bbox = bvh.bounding_box(0.0, 1.0).unwrap();
+        assert!(bbox.get_min().get_z() <= -1.0 && bbox.get_max().get_z() >= 21.0);
+
+        // found at either end of its path
+        for (time, z) in [(0.0, 0.0), (1.0, 20.0)] {
+            let r = Ray::new(&Point3::new(0, 5, z - 10.0), &Vec3::new(0, 0, 1), time);
+            let rec = bvh.hit(&r, 0.001, f64::INFINITY).unwrap();
+            assert!((rec.get_t() - 9.0).abs() < 1e-9);
+        }
+    }
 }
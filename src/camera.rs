@@ -1,6 +1,9 @@
-use rand::{thread_rng, Rng};
+use crate::mutil::rng;
+use rand::Rng;
+use std::f64::consts::PI;
+use std::hash::{Hash, Hasher};
 
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::vec3::{random_in_unit_disk, Point3, Vec3};
 
 pub struct Camera {
@@ -56,8 +59,63 @@ impl Camera {
         }
     }
 
+    /// A camera `radius` away from `target`, looking at it with y up and focused on it.
+    /// Angles are in degrees: azimuth 0 is along +x and turns counter-clockwise seen from
+    /// above (towards -z), elevation lifts the camera towards +y. Elevation must stay
+    /// short of ±90, where the view direction would be parallel to up.
+    #[allow(clippy::too_many_arguments)]
+    pub fn orbit(
+        target: Point3,
+        radius: f64,
+        azimuth: f64,
+        elevation: f64,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        time1: f64,
+        time2: f64,
+    ) -> Camera {
+        let (az, el) = (f64::to_radians(azimuth), f64::to_radians(elevation));
+        let offset = Vec3::new(
+            f64::cos(el) * f64::cos(az),
+            f64::sin(el),
+            -f64::cos(el) * f64::sin(az),
+        );
+        Camera::new(
+            target + radius * offset,
+            target,
+            Vec3::new(0, 1, 0),
+            vfov,
+            aspect_ratio,
+            aperture,
+            radius,
+            time1,
+            time2,
+        )
+    }
+
+    /// Width over height of the viewport, as passed to the constructor.
+    pub fn get_aspect_ratio(&self) -> f64 {
+        self.horizontal.length() / self.vertical.length()
+    }
+
+    /// Distance from the lens to the plane that's in perfect focus.
+    pub fn get_focus_distance(&self) -> f64 {
+        (self.lower_left_corner + self.horizontal / 2 + self.vertical / 2 - self.origin)
+            .dot(&-self.w)
+    }
+
+    /// Diameter of the blur a point `object_distance` in front of the lens makes, measured on
+    /// the focus plane (the viewport is `vertical` tall there), so 0 means perfectly sharp.
+    /// Thin lens: the aperture's cone narrows to a point at the focus plane and opens up again
+    /// behind it.
+    pub fn circle_of_confusion(&self, object_distance: f64) -> f64 {
+        let focus = self.get_focus_distance();
+        2.0 * self.lens_radius * (object_distance - focus).abs() / object_distance
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let mut rng = thread_rng();
+        let mut rng = rng();
         let rd = self.lens_radius * random_in_unit_disk();
         let offset = self.u * rd.get_x() + self.v * rd.get_y();
 
@@ -69,4 +127,156 @@ impl Camera {
             rng.gen_range(self.time1..self.time2),
         )
     }
+
+    /// How rays change between neighboring pixels that are `ds` and `dt` apart in `s` and
+    /// `t` (one over the image width and height less one). Neighbors share a lens sample,
+    /// so only the direction changes.
+    pub fn differential(&self, ds: f64, dt: f64) -> RayDifferential {
+        RayDifferential::new(
+            Vec3::zero(),
+            ds * self.horizontal,
+            Vec3::zero(),
+            dt * self.vertical,
+        )
+    }
+
+    /// Like `get_ray`, but with the lens position and shutter time chosen by the caller:
+    /// `lens` and `time` are in [0, 1) and are mapped onto the aperture disk and shutter interval.
+    pub fn get_ray_with(&self, s: f64, t: f64, lens: (f64, f64), time: f64) -> Ray {
+        let radius = self.lens_radius * f64::sqrt(lens.0);
+        let theta = 2.0 * PI * lens.1;
+        let offset = radius * (f64::cos(theta) * self.u + f64::sin(theta) * self.v);
+
+        Ray::new(
+            &(self.origin + offset),
+            &(self.lower_left_corner + s * self.horizontal + t * self.vertical
+                - self.origin
+                - offset),
+            self.time1 + time * (self.time2 - self.time1),
+        )
+    }
+}
+
+// bitwise, so any change to the view at all gives a different hash
+impl Hash for Camera {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for v in [
+            self.origin,
+            self.lower_left_corner,
+            self.horizontal,
+            self.vertical,
+            self.u,
+            self.v,
+            self.w,
+        ] {
+            hash_vec(&v, state);
+        }
+        for x in [self.lens_radius, self.time1, self.time2] {
+            x.to_bits().hash(state);
+        }
+    }
+}
+
+pub(crate) fn hash_vec<H: Hasher>(v: &Vec3, state: &mut H) {
+    for x in [v.get_x(), v.get_y(), v.get_z()] {
+        x.to_bits().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_vec3_eq;
+
+    #[test]
+    fn circle_of_confusion_matches_lens_rays() {
+        let cam = Camera::new(
+            Point3::new(0, 0, 0),
+            Point3::new(0, 0, -1),
+            Vec3::new(0, 1, 0),
+            40.0,
+            1.0,
+            0.5,
+            5.0,
+            0.0,
+            1.0,
+        );
+        assert!((cam.get_focus_distance() - 5.0).abs() < 1e-9);
+        assert!(cam.circle_of_confusion(5.0).abs() < 1e-9);
+        assert!(cam.circle_of_confusion(10.0) > cam.circle_of_confusion(7.0));
+        assert!(cam.circle_of_confusion(2.0) > cam.circle_of_confusion(4.0));
+
+        // rays through opposite rims of the lens, aimed at the image center
+        for distance in [2.0, 5.0, 10.0, 40.0] {
+            let at_plane = |theta: f64| {
+                let r = cam.get_ray_with(0.5, 0.5, (1.0, theta), 0.0);
+                r.at((distance - r.get_origin().dot(&-cam.w)) / r.get_direction().dot(&-cam.w))
+            };
+            let spread = (at_plane(0.0) - at_plane(0.5)).length();
+            // scaled back onto the focus plane, as seen from the lens center
+            let on_focus_plane = spread * 5.0 / distance;
+            assert!(
+                (on_focus_plane - cam.circle_of_confusion(distance)).abs() < 1e-9,
+                "{} vs {}",
+                on_focus_plane,
+                cam.circle_of_confusion(distance)
+            );
+        }
+    }
+
+    #[test]
+    fn orbit_places_camera_on_sphere() {
+        let target = Point3::new(1, 2, 3);
+        let at = |azimuth, elevation| {
+            Camera::orbit(target, 5.0, azimuth, elevation, 40.0, 1.0, 0.0, 0.0, 1.0).origin
+        };
+        assert_vec3_eq!(at(0.0, 0.0), target + Vec3::new(5, 0, 0));
+        assert_vec3_eq!(at(90.0, 0.0), target + Vec3::new(0, 0, -5));
+        let quarter_turn =
+            Vec3::new(5, 0, 0).rotate_around(&Vec3::new(0, 1, 0), f64::to_radians(90.0));
+        assert_vec3_eq!(at(90.0, 0.0), target + quarter_turn);
+        assert_vec3_eq!(
+            at(0.0, 30.0),
+            target + Vec3::new(5.0 * f64::sqrt(3.0) / 2.0, 2.5, 0)
+        );
+    }
+
+    #[test]
+    fn differentials_match_neighboring_rays_and_scale_with_pixel_size() {
+        let cam = Camera::new(
+            Point3::new(0, 0, 0),
+            Point3::new(0, 0, -1),
+            Vec3::new(0, 1, 0),
+            40.0,
+            2.0,
+            0.5,
+            5.0,
+            0.0,
+            1.0,
+        );
+        let (width, height) = (400.0, 200.0);
+        let (ds, dt) = (1.0 / (width - 1.0), 1.0 / (height - 1.0));
+        let d = cam.differential(ds, dt);
+        let lens = (0.3, 0.7);
+        let at = |s: f64, t: f64| cam.get_ray_with(s, t, lens, 0.0);
+        let (s, t) = (0.4, 0.6);
+        assert_vec3_eq!(
+            *d.get_dx_direction(),
+            *at(s + ds, t).get_direction() - *at(s, t).get_direction()
+        );
+        assert_vec3_eq!(
+            *d.get_dy_direction(),
+            *at(s, t + dt).get_direction() - *at(s, t).get_direction()
+        );
+        assert_vec3_eq!(*d.get_dx_origin(), Vec3::zero());
+
+        let r = at(s, t).with_differential(Some(d));
+        let near = r.footprint(1.0).unwrap();
+        assert!((r.footprint(3.0).unwrap() - 3.0 * near).abs() < 1e-12);
+        assert!(at(s, t).footprint(1.0).is_none());
+
+        let finer = cam.differential(1.0 / (2.0 * width - 1.0), 1.0 / (2.0 * height - 1.0));
+        let finer_near = r.with_differential(Some(finer)).footprint(1.0).unwrap();
+        assert!((finer_near / near - 0.5).abs() < 0.01);
+    }
 }
@@ -0,0 +1,192 @@
+//! Reconstruction filters: how much a camera sample counts towards each pixel near it.
+//! Every sample is splatted onto the pixels whose centers lie within `radius` of it, and
+//! each pixel is normalized by the weight it gathered, so filters need not integrate to 1.
+
+/// A weighting of samples by their offset `(dx, dy)` from a pixel center, in pixels.
+/// Weights are zero outside `[-radius, radius]` on both axes and may be negative.
+pub trait PixelFilter: Send + Sync {
+    fn weight(&self, dx: f64, dy: f64) -> f64;
+    fn radius(&self) -> f64;
+}
+
+/// Every sample within `radius` counts the same; with 0.5 it's the plain per-pixel average.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoxFilter {
+    radius: f64,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> BoxFilter {
+        assert!(radius > 0.0);
+        BoxFilter { radius }
+    }
+}
+
+impl PixelFilter for BoxFilter {
+    fn weight(&self, dx: f64, dy: f64) -> f64 {
+        match dx.abs() <= self.radius && dy.abs() <= self.radius {
+            true => 1.0,
+            false => 0.0,
+        }
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+}
+
+/// Falls off linearly from the center to nothing at `radius`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TentFilter {
+    radius: f64,
+}
+
+impl TentFilter {
+    pub fn new(radius: f64) -> TentFilter {
+        assert!(radius > 0.0);
+        TentFilter { radius }
+    }
+}
+
+impl PixelFilter for TentFilter {
+    fn weight(&self, dx: f64, dy: f64) -> f64 {
+        f64::max(0.0, self.radius - dx.abs()) * f64::max(0.0, self.radius - dy.abs())
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+}
+
+/// A bell curve `exp(-alpha * x^2)` per axis, shifted down to reach zero at `radius`.
+/// Larger `alpha` is sharper.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GaussianFilter {
+    radius: f64,
+    alpha: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, alpha: f64) -> GaussianFilter {
+        assert!(radius > 0.0 && alpha > 0.0);
+        GaussianFilter { radius, alpha }
+    }
+
+    fn gaussian(&self, x: f64) -> f64 {
+        f64::max(
+            0.0,
+            f64::exp(-self.alpha * x * x) - f64::exp(-self.alpha * self.radius * self.radius),
+        )
+    }
+}
+
+impl PixelFilter for GaussianFilter {
+    fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.gaussian(dx) * self.gaussian(dy)
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+}
+
+/// The Mitchell-Netravali cubic, sharper than a Gaussian at the cost of slight ringing from
+/// its negative lobes. `b = c = 1 / 3` is their recommended balance; any `b + 2c = 1`
+/// reproduces flat areas exactly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f64, b: f64, c: f64) -> MitchellFilter {
+        assert!(radius > 0.0);
+        MitchellFilter { radius, b, c }
+    }
+
+    // the cubic over [-2, 2], with `x` already scaled from [-radius, radius]
+    fn mitchell(&self, x: f64) -> f64 {
+        let (b, c) = (self.b, self.c);
+        let x = x.abs();
+        let y = if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            (-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c)
+        } else {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)
+        };
+        y / 6.0
+    }
+}
+
+impl Default for MitchellFilter {
+    fn default() -> MitchellFilter {
+        MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)
+    }
+}
+
+impl PixelFilter for MitchellFilter {
+    fn weight(&self, dx: f64, dy: f64) -> f64 {
+        let scale = 2.0 / self.radius;
+        self.mitchell(dx * scale) * self.mitchell(dy * scale)
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // total weight a sample at a pixel center hands out over the whole pixel grid
+    fn centered_total(filter: &dyn PixelFilter) -> f64 {
+        let r = filter.radius().ceil() as i32;
+        let mut total = 0.0;
+        for y in -r..=r {
+            for x in -r..=r {
+                total += filter.weight(x as f64, y as f64);
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn mitchell_rings_but_keeps_flat_areas_flat() {
+        let mitchell = MitchellFilter::default();
+        assert!(mitchell.weight(0.0, 0.0) > 0.0);
+        // the negative lobe sits between half the radius and the radius
+        assert!(mitchell.weight(1.5, 0.0) < 0.0);
+        assert!(mitchell.weight(0.0, -1.5) < 0.0);
+        assert!(mitchell.weight(1.5, 1.5) > 0.0);
+        assert_eq!(mitchell.weight(2.5, 0.0), 0.0);
+        // b + 2c = 1, so the weights a centered sample gives out sum to exactly one
+        assert!((centered_total(&mitchell) - 1.0).abs() < 1e-12);
+        assert!((centered_total(&MitchellFilter::new(2.0, 0.0, 0.5)) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn filters_peak_at_the_center_and_vanish_past_the_radius() {
+        let filters: [Box<dyn PixelFilter>; 4] = [
+            Box::new(BoxFilter::new(0.5)),
+            Box::new(TentFilter::new(1.0)),
+            Box::new(GaussianFilter::new(1.5, 2.0)),
+            Box::new(MitchellFilter::default()),
+        ];
+        for filter in &filters {
+            let r = filter.radius();
+            let center = filter.weight(0.0, 0.0);
+            assert!(center > 0.0);
+            assert!(filter.weight(0.3 * r, 0.2 * r) <= center);
+            assert_eq!(filter.weight(1.01 * r, 0.0), 0.0);
+            assert_eq!(filter.weight(0.0, -1.01 * r), 0.0);
+        }
+        // a half-pixel box or a one-pixel tent keeps a centered sample to its own pixel
+        assert_eq!(centered_total(&BoxFilter::new(0.5)), 1.0);
+        assert_eq!(centered_total(&TentFilter::new(1.0)), 1.0);
+    }
+}
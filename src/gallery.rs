@@ -0,0 +1,55 @@
+//! Renders every built-in scene to `scene_NN.png`, for eyeballing the whole suite after a change.
+
+use crate::world::{get_world_cam, render_to_screen, Config, SCENE_COUNT};
+use std::io;
+use std::path::Path;
+
+/// Render each scene id into `out_dir` with `config`, its aspect ratio adjusted to each
+/// scene's camera. Scenes that fail to build (e.g. a missing texture file) are reported
+/// and skipped. Returns the ids that were written.
+pub fn run(out_dir: &Path, config: &Config) -> io::Result<Vec<usize>> {
+    std::fs::create_dir_all(out_dir)?;
+    let mut written = Vec::new();
+    for scene in 0..SCENE_COUNT {
+        let (world, cam, background) = match get_world_cam(scene) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("Skipping scene {}: {}", scene, e);
+                continue;
+            }
+        };
+        let config = config.clone().with_aspect_ratio(cam.get_aspect_ratio());
+        let image = render_to_screen(world, cam, background, &config);
+        let path = out_dir.join(format!("scene_{:02}.png", scene));
+        image.write_to_png_file(&path.to_string_lossy())?;
+        written.push(scene);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_file_per_buildable_scene() {
+        let out_dir = std::env::temp_dir().join("gallery_test");
+        let _ = std::fs::remove_dir_all(&out_dir);
+        let written = run(&out_dir, &Config::new(1.0, 8, 1, 2, 1).with_seed(1)).unwrap();
+
+        let buildable: Vec<usize> = (0..SCENE_COUNT)
+            .filter(|&scene| get_world_cam(scene).is_ok())
+            .collect();
+        assert_eq!(written, buildable);
+        let mut files: Vec<String> = std::fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        let expected: Vec<String> = buildable
+            .iter()
+            .map(|scene| format!("scene_{:02}.png", scene))
+            .collect();
+        assert_eq!(files, expected);
+    }
+}
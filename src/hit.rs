@@ -1,10 +1,14 @@
-use crate::aabb::Aabb;
-use crate::ray::Ray;
-use crate::texture::{SolidColor, Texture};
+use crate::aabb::{component, Aabb};
+use crate::bvh::BvhNode;
+use crate::mutil::{clamp, rng};
+use crate::ray::{Ray, RayKind};
+use crate::screen::ImageError;
+use crate::spectrum;
+use crate::texture::{Footprint, Image, Scaled, SolidColor, Texture};
 use crate::vec3::{random_in_unit_sphere, random_unit_vector, Color, Point3, Vec3};
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use std::f64::consts::PI;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 #[derive(Clone)]
 pub struct HitRecord {
@@ -15,6 +19,7 @@ pub struct HitRecord {
     v: f64,
     front_face: bool,
     mat_ptr: Arc<Box<dyn Material>>,
+    barycentric: Option<Vec3>,
 }
 
 impl HitRecord {
@@ -35,9 +40,21 @@ impl HitRecord {
             v,
             front_face,
             mat_ptr: material,
+            barycentric: None,
         }
     }
 
+    /// Weights of the three vertices at the hit point, for hits on a `Triangle`.
+    pub fn get_barycentric(&self) -> Option<Vec3> {
+        self.barycentric
+    }
+
+    /// Smallest barycentric weight, i.e. how close a triangle hit is to the nearest edge.
+    pub fn edge_distance(&self) -> Option<f64> {
+        self.barycentric
+            .map(|b| f64::min(b.get_x(), f64::min(b.get_y(), b.get_z())))
+    }
+
     pub fn get_normal(&self) -> &Vec3 {
         return &self.normal;
     }
@@ -66,6 +83,26 @@ impl HitRecord {
         Arc::clone(&self.mat_ptr)
     }
 
+    // an empty record to fill in with `hit_into`
+    fn blank(material: &Arc<Box<dyn Material>>) -> HitRecord {
+        HitRecord::new(
+            Vec3::zero(),
+            Vec3::zero(),
+            0.0,
+            0.0,
+            0.0,
+            false,
+            material.clone(),
+        )
+    }
+
+    // skip the refcount traffic when consecutive hits share a material, as mesh faces do
+    fn set_material(&mut self, material: &Arc<Box<dyn Material>>) {
+        if !Arc::ptr_eq(&self.mat_ptr, material) {
+            self.mat_ptr = material.clone();
+        }
+    }
+
     fn create_normal_face(r: &Ray, outward_normal: &Vec3) -> (Vec3, bool) {
         let front_face = r.get_direction().dot(outward_normal) < 0.0;
         (
@@ -82,6 +119,79 @@ impl HitRecord {
 pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    /// `hit`, but filling in a caller-owned record, so loops over many objects can reuse one.
+    /// Returns whether anything was hit; `out` is left as it was on a miss.
+    fn hit_into(&self, r: &Ray, t_min: f64, t_max: f64, out: &mut HitRecord) -> bool {
+        match self.hit(r, t_min, t_max) {
+            Some(rec) => {
+                *out = rec;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Type name for diagnostics, e.g. `world::validate`.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Number of primitives below this object; a primitive counts itself.
+    fn leaf_count(&self) -> usize {
+        1
+    }
+
+    /// Levels of grouping (lists, BVH nodes) above the deepest primitive.
+    fn depth(&self) -> usize {
+        0
+    }
+
+    /// Whether anything is hit between `t_min` and `t_max`. Unlike `hit`, this can stop at
+    /// the first intersection found, which is all a shadow ray needs.
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit(r, t_min, t_max).is_some()
+    }
+
+    /// Whether this is a primitive with an emissive material.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Push the emissive primitives below this object onto `lights`, keeping any transforms.
+    fn collect_lights(&self, _lights: &mut HittableList) {}
+
+    /// Density, per solid angle, of `random_direction(origin)` returning `direction`.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// A direction from `origin` toward a random point on this object, for sampling it as a
+    /// light. `None` for objects that don't support being sampled.
+    fn random_direction(&self, _origin: &Point3) -> Option<Vec3> {
+        None
+    }
+}
+
+// solid-angle density of aiming at a uniformly chosen point on a flat object of `area`
+fn area_pdf(obj: &dyn Hittable, area: f64, origin: &Point3, direction: &Vec3) -> f64 {
+    match obj.hit(&Ray::new(origin, direction, 0.0), 0.001, f64::INFINITY) {
+        Some(rec) => {
+            let distance_squared = rec.get_t() * rec.get_t() * direction.length_squared();
+            let cosine = f64::abs(direction.dot(rec.get_normal())) / direction.length();
+            distance_squared / (cosine * area)
+        }
+        None => 0.0,
+    }
+}
+
+/// Collect `obj` itself if it glows, otherwise whatever lights it contains.
+pub(crate) fn gather_lights(obj: &Arc<Box<dyn Hittable + Sync>>, lights: &mut HittableList) {
+    if obj.is_emissive() {
+        lights.add(obj.clone());
+    } else {
+        obj.collect_lights(lights);
+    }
 }
 
 pub struct Triangle {
@@ -90,6 +200,7 @@ pub struct Triangle {
     v2: Point3,
     normal: Point3,
     mat_ptr: Arc<Box<dyn Material>>,
+    cull_backface: bool,
 }
 
 impl Triangle {
@@ -103,62 +214,112 @@ impl Triangle {
             v2,
             normal,
             mat_ptr,
+            cull_backface: false,
         }
     }
-}
 
-impl Hittable for Triangle {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        //todo parallel
+    /// Ignore rays arriving from behind (the side the winding order's normal points away
+    /// from). Only safe for closed meshes, where such a ray would hit a front face first.
+    pub fn with_backface_culling(mut self) -> Triangle {
+        self.cull_backface = true;
+        self
+    }
 
-        if f64::abs(self.normal.dot(r.get_direction())) < 0.0001 {
-            return None;
-        }
+    pub fn area(&self) -> f64 {
+        (self.v1 - self.v0).cross(&(self.v2 - self.v0)).length() / 2.0
+    }
 
-        let d = -self.normal.dot(&self.v0);
-        let t = -(self.normal.dot(r.get_origin()) + d) / self.normal.dot(r.get_direction());
+    /// A point drawn uniformly over the triangle's surface.
+    pub fn random_point(&self) -> Point3 {
+        let mut rng = rng();
+        // square-rooting one coordinate keeps the barycentric density uniform over the area
+        let r1 = f64::sqrt(rng.gen::<f64>());
+        let r2 = rng.gen::<f64>();
+        (1.0 - r1) * self.v0 + r1 * (1.0 - r2) * self.v1 + r1 * r2 * self.v2
+    }
 
-        if t < t_min || t > t_max {
+    // Woop, Benthin and Wald's watertight test: shear space so the ray runs down +z from the
+    // origin, then compare the 2D edge functions. Neighbours compute the same value for a
+    // shared edge, with opposite signs, so a ray along it can't slip between them.
+    // Gives the hit's `t` and barycentric coordinates.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+        if self.cull_backface && self.normal.dot(r.get_direction()) > 0.0 {
             return None;
         }
 
-        let p = r.at(t);
-
-        let edge0 = self.v1 - self.v0;
-        let vp0 = p - self.v0;
-
-        let c = edge0.cross(&vp0);
-        if self.normal.dot(&c) < 0.0 {
+        let dir = r.get_direction();
+        let kz = (0..3)
+            .max_by(|&a, &b| component(dir, a).abs().total_cmp(&component(dir, b).abs()))
+            .unwrap();
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        // keeps the winding, and so the sign of the edge functions, the same
+        if component(dir, kz) < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+        let dz = component(dir, kz);
+        let (sx, sy, sz) = (component(dir, kx) / dz, component(dir, ky) / dz, 1.0 / dz);
+
+        let sheared = |v: &Point3| {
+            let a = *v - *r.get_origin();
+            let az = component(&a, kz);
+            (
+                component(&a, kx) - sx * az,
+                component(&a, ky) - sy * az,
+                sz * az,
+            )
+        };
+        let (ax, ay, az) = sheared(&self.v0);
+        let (bx, by, bz) = sheared(&self.v1);
+        let (cx, cy, cz) = sheared(&self.v2);
+
+        // twice the signed areas of the sub-triangles opposite v0, v1 and v2
+        let e0 = cx * by - cy * bx;
+        let e1 = ax * cy - ay * cx;
+        let e2 = bx * ay - by * ax;
+        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
             return None;
         }
-
-        let edge1 = self.v2 - self.v1;
-        let vp1 = p - self.v1;
-
-        let c = edge1.cross(&vp1);
-        if self.normal.dot(&c) < 0.0 {
+        let det = e0 + e1 + e2;
+        if det == 0.0 {
             return None;
         }
 
-        let edge2 = self.v0 - self.v2;
-        let vp2 = p - self.v2;
-
-        let c = edge2.cross(&vp2);
-        if self.normal.dot(&c) < 0.0 {
+        let t = (e0 * az + e1 * bz + e2 * cz) / det;
+        if t < t_min || t > t_max {
             return None;
         }
+        Some((t, Vec3::new(e0 / det, e1 / det, e2 / det)))
+    }
 
+    fn fill(&self, r: &Ray, t: f64, barycentric: Vec3, out: &mut HitRecord) {
         let (normal, front_face) = HitRecord::create_normal_face(r, &self.normal);
 
-        Some(HitRecord::new(
-            r.at(t),
-            normal,
-            t,
-            1.0,
-            1.0,
-            front_face,
-            Arc::clone(&self.mat_ptr),
-        ))
+        out.p = r.at(t);
+        out.normal = normal;
+        out.t = t;
+        out.u = barycentric.get_y();
+        out.v = barycentric.get_z();
+        out.front_face = front_face;
+        out.set_material(&self.mat_ptr);
+        out.barycentric = Some(barycentric);
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t, barycentric) = self.intersect(r, t_min, t_max)?;
+        let mut rec = HitRecord::blank(&self.mat_ptr);
+        self.fill(r, t, barycentric, &mut rec);
+        Some(rec)
+    }
+    fn hit_into(&self, r: &Ray, t_min: f64, t_max: f64, out: &mut HitRecord) -> bool {
+        match self.intersect(r, t_min, t_max) {
+            Some((t, barycentric)) => {
+                self.fill(r, t, barycentric, out);
+                true
+            }
+            None => false,
+        }
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
@@ -173,7 +334,80 @@ impl Hittable for Triangle {
             max.set_y(f64::max(max.get_y(), v.get_y()));
             max.set_z(f64::max(max.get_z(), v.get_z()));
         }
-        Some(Aabb::new(min, max))
+        // axis-aligned triangles (flat terrain, for one) would otherwise get a zero-thickness box
+        let pad = Vec3::new(
+            pad_if_flat(min.get_x(), max.get_x()),
+            pad_if_flat(min.get_y(), max.get_y()),
+            pad_if_flat(min.get_z(), max.get_z()),
+        );
+        Some(Aabb::new(min - pad, max + pad))
+    }
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        area_pdf(self, self.area(), origin, direction)
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        Some(self.random_point() - *origin)
+    }
+}
+
+/// A triangle whose vertices move linearly from one pose at `time0` to another at `time1`.
+pub struct MovingTriangle {
+    pose0: [Point3; 3],
+    pose1: [Point3; 3],
+    time0: f64,
+    time1: f64,
+    mat_ptr: Arc<Box<dyn Material>>,
+}
+
+impl MovingTriangle {
+    pub fn new(
+        pose0: [Point3; 3],
+        pose1: [Point3; 3],
+        time0: f64,
+        time1: f64,
+        mat_ptr: Arc<Box<dyn Material>>,
+    ) -> MovingTriangle {
+        assert!(time1 > time0, "a moving triangle needs time1 after time0");
+        MovingTriangle {
+            pose0,
+            pose1,
+            time0,
+            time1,
+            mat_ptr,
+        }
+    }
+
+    /// The triangle as it is at `time`.
+    pub fn at(&self, time: f64) -> Triangle {
+        let s = (time - self.time0) / (self.time1 - self.time0);
+        let lerp = |k: usize| self.pose0[k] + s * (self.pose1[k] - self.pose0[k]);
+        Triangle::new(lerp(0), lerp(1), lerp(2), self.mat_ptr.clone())
+    }
+}
+
+impl Hittable for MovingTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.at(r.get_time()).hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        // vertices move in straight lines, so the two end poses bound everything in between
+        let box0 = self.at(time0).bounding_box(time0, time0)?;
+        let box1 = self.at(time1).bounding_box(time1, time1)?;
+        Some(Aabb::surrounding_box(&box0, &box1))
+    }
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+}
+
+fn pad_if_flat(min: f64, max: f64) -> f64 {
+    if max - min < 0.0001 {
+        0.0001
+    } else {
+        0.0
     }
 }
 
@@ -198,10 +432,9 @@ impl Sphere {
         let phi = f64::atan2(-p.get_z(), p.get_x()) + PI;
         (phi / (2.0 * PI), theta / PI)
     }
-}
 
-impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    // the nearer root of the ray-sphere quadratic within `[t_min, t_max]`
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
         let oc = *r.get_origin() - self.center;
         let a = r.get_direction().length_squared();
         let half_b = oc.dot(r.get_direction());
@@ -219,22 +452,42 @@ impl Hittable for Sphere {
                 return None;
             }
         }
-        let t = root;
+        Some(root)
+    }
+
+    fn fill(&self, r: &Ray, t: f64, out: &mut HitRecord) {
         let p = r.at(t);
         let outward_normal = (p - self.center) / self.radius;
         let (normal, front_face) = HitRecord::create_normal_face(r, &outward_normal);
         let (u, v) = Sphere::get_sphere_uv(&outward_normal);
 
-        Some(HitRecord::new(
-            p,
-            normal,
-            t,
-            u,
-            v,
-            front_face,
-            Arc::clone(&self.mat_ptr),
-        ))
-        // TODO return an option here?
+        out.p = p;
+        out.normal = normal;
+        out.t = t;
+        out.u = u;
+        out.v = v;
+        out.front_face = front_face;
+        out.set_material(&self.mat_ptr);
+        out.barycentric = None;
+    }
+}
+
+impl Hittable for Sphere {
+    // test first, so a miss never touches the material's refcount
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let t = self.intersect(r, t_min, t_max)?;
+        let mut rec = HitRecord::blank(&self.mat_ptr);
+        self.fill(r, t, &mut rec);
+        Some(rec)
+    }
+    fn hit_into(&self, r: &Ray, t_min: f64, t_max: f64, out: &mut HitRecord) -> bool {
+        match self.intersect(r, t_min, t_max) {
+            Some(t) => {
+                self.fill(r, t, out);
+                true
+            }
+            None => false,
+        }
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         Some(Aabb::new(
@@ -242,6 +495,136 @@ impl Hittable for Sphere {
             self.center + Point3::new(self.radius, self.radius, self.radius),
         ))
     }
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+}
+
+/// Upright cylinder standing on `base`, open at both ends unless built `with_caps`.
+pub struct Cylinder {
+    base: Point3,
+    radius: f64,
+    height: f64,
+    mat_ptr: Arc<Box<dyn Material>>,
+    capped: bool,
+}
+
+impl Cylinder {
+    pub fn new(
+        base: Point3,
+        radius: f64,
+        height: f64,
+        mat_ptr: Arc<Box<dyn Material>>,
+    ) -> Cylinder {
+        assert!(radius > 0.0, "a cylinder needs a positive radius");
+        assert!(height > 0.0, "a cylinder needs a positive height");
+        Cylinder {
+            base,
+            radius,
+            height,
+            mat_ptr,
+            capped: false,
+        }
+    }
+
+    /// Close the ends with disks, making a solid that media and dielectrics can fill.
+    pub fn with_caps(mut self) -> Cylinder {
+        self.capped = true;
+        self
+    }
+
+    /// `u` runs once around the axis, starting at -x like `Sphere::get_sphere_uv`, and `v` up
+    /// from the base; `p` is relative to the base. Caps take the `v` of their edge.
+    pub fn get_cylinder_uv(&self, p: &Point3) -> (f64, f64) {
+        let phi = f64::atan2(-p.get_z(), p.get_x()) + PI;
+        (phi / (2.0 * PI), clamp(p.get_y() / self.height, 0.0, 1.0))
+    }
+
+    // the nearest of the side and caps, with its outward normal
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+        let o = *r.get_origin() - self.base;
+        let d = *r.get_direction();
+        let mut closest: Option<(f64, Vec3)> = None;
+
+        // the infinite cylinder around y, then cut to the height
+        let a = d.get_x() * d.get_x() + d.get_z() * d.get_z();
+        if a > 0.0 {
+            let half_b = o.get_x() * d.get_x() + o.get_z() * d.get_z();
+            let c = o.get_x() * o.get_x() + o.get_z() * o.get_z() - self.radius * self.radius;
+            let discriminant = half_b * half_b - a * c;
+            if discriminant >= 0.0 {
+                let sqrtd = f64::sqrt(discriminant);
+                for root in [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a] {
+                    let y = o.get_y() + root * d.get_y();
+                    if root >= t_min && root <= t_max && (0.0..=self.height).contains(&y) {
+                        let p = o + root * d;
+                        let outward = Vec3::new(p.get_x(), 0, p.get_z()) / self.radius;
+                        closest = Some((root, outward));
+                        break;
+                    }
+                }
+            }
+        }
+
+        if self.capped && d.get_y() != 0.0 {
+            for (y, outward) in [
+                (0.0, Vec3::new(0, -1, 0)),
+                (self.height, Vec3::new(0, 1, 0)),
+            ] {
+                let t = (y - o.get_y()) / d.get_y();
+                let nearest = closest.map_or(t_max, |(t, _)| t);
+                let p = o + t * d;
+                let inside =
+                    p.get_x() * p.get_x() + p.get_z() * p.get_z() <= self.radius * self.radius;
+                if t >= t_min && t < nearest && inside {
+                    closest = Some((t, outward));
+                }
+            }
+        }
+
+        closest
+    }
+
+    fn fill(&self, r: &Ray, t: f64, outward_normal: Vec3, out: &mut HitRecord) {
+        let (normal, front_face) = HitRecord::create_normal_face(r, &outward_normal);
+        let (u, v) = self.get_cylinder_uv(&(r.at(t) - self.base));
+
+        out.p = r.at(t);
+        out.normal = normal;
+        out.t = t;
+        out.u = u;
+        out.v = v;
+        out.front_face = front_face;
+        out.set_material(&self.mat_ptr);
+        out.barycentric = None;
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t, outward_normal) = self.intersect(r, t_min, t_max)?;
+        let mut rec = HitRecord::blank(&self.mat_ptr);
+        self.fill(r, t, outward_normal, &mut rec);
+        Some(rec)
+    }
+    fn hit_into(&self, r: &Ray, t_min: f64, t_max: f64, out: &mut HitRecord) -> bool {
+        match self.intersect(r, t_min, t_max) {
+            Some((t, outward_normal)) => {
+                self.fill(r, t, outward_normal, out);
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        Some(Aabb::new(
+            self.base - Point3::new(self.radius, 0.0, self.radius),
+            self.base + Point3::new(self.radius, self.height, self.radius),
+        ))
+    }
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
 }
 
 pub struct MovingSphere {
@@ -325,6 +708,9 @@ impl Hittable for MovingSphere {
         );
         Some(Aabb::surrounding_box(&box0, &box1))
     }
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
 }
 
 pub struct GravitySphere {
@@ -332,65 +718,72 @@ pub struct GravitySphere {
     time0: f64,
     radius: f64,
     mat_ptr: Arc<Box<dyn Material>>,
-    pub stored: Vec<f64>,
+    pub stored: Arc<Vec<f64>>,
 }
 
 // Fix this up later
 impl GravitySphere {
+    /// Every gravity sphere shares one trajectory table, however high it starts.
     pub fn new(
         start: Point3,
         time0: f64,
         radius: f64,
         mat_ptr: Arc<Box<dyn Material>>,
     ) -> GravitySphere {
-        let mut stored = vec![start.get_y()];
-        let incr = 0.001;
-        let mut t = time0;
-        let mut cur_pos = start;
-        let mut vel = 0.0;
-        while t < 100.0 {
-            t += incr;
-            vel -= 0.000001;
-            if cur_pos.get_y() - 1.0 * radius <= 0.0 {
-                vel *= -0.92;
-            }
-            cur_pos.set_y(f64::max(1.0 * radius, cur_pos.get_y() + vel));
-            stored.push(cur_pos.get_y());
-        }
-        let output = GravitySphere {
+        GravitySphere {
             start,
             time0,
             radius,
             mat_ptr,
-            stored: stored,
-        };
-        output
+            stored: GravitySphere::unit_trajectory(),
+        }
+    }
+
+    // Heights above the floor after a drop from 1, one entry per step. Gravity and the
+    // bounce's restitution are the same for every sphere, so a drop from `h` follows the
+    // same curve with heights scaled by `h` and times by `sqrt(h)`.
+    fn unit_trajectory() -> Arc<Vec<f64>> {
+        static TABLE: OnceLock<Arc<Vec<f64>>> = OnceLock::new();
+        TABLE
+            .get_or_init(|| {
+                let mut stored = vec![1.0];
+                let incr = 0.001;
+                let mut t = 0.0;
+                let mut y = 1.0;
+                let mut vel = 0.0;
+                while t < 100.0 {
+                    t += incr;
+                    vel -= 0.000001;
+                    if y <= 0.0 {
+                        vel *= -0.92;
+                    }
+                    y = f64::max(0.0, y + vel);
+                    stored.push(y);
+                }
+                Arc::new(stored)
+            })
+            .clone()
     }
 
     pub fn get_center(&self, time: f64) -> Point3 {
         let incr = 0.001;
-        // brute force lmao
-        if (time / incr) as usize + 1 <= self.stored.len() {
-            return Vec3::new(
-                self.start.get_x(),
-                self.stored[(time / incr) as usize],
-                self.start.get_z(),
-            );
+        let drop = self.start.get_y() - self.radius;
+        if drop <= 0.0 || time <= self.time0 {
+            return self.start;
         }
-        // TODO: figure out radius x2 bug?
-        let mut t = self.time0;
-        let mut cur_pos = self.start.clone();
-        let mut vel = 0.0;
-        while t < time {
-            t += incr;
-            vel -= 0.000001;
-            if cur_pos.get_y() - 2.0 * self.radius <= 0.0 {
-                vel *= -0.8;
-            }
-            cur_pos.set_y(f64::max(2.0 * self.radius, cur_pos.get_y() + vel));
-        }
-
-        return cur_pos;
+        // position along the unit drop, between two of its steps
+        let step = (time - self.time0) / f64::sqrt(drop) / incr;
+        let k = step as usize;
+        let y = match (self.stored.get(k), self.stored.get(k + 1)) {
+            (Some(y0), Some(y1)) => y0 + (step - k as f64) * (y1 - y0),
+            // long since come to rest
+            _ => 0.0,
+        };
+        Vec3::new(
+            self.start.get_x(),
+            self.radius + drop * y,
+            self.start.get_z(),
+        )
     }
 }
 
@@ -441,6 +834,9 @@ impl Hittable for GravitySphere {
         );
         Some(Aabb::surrounding_box(&box0, &box1))
     }
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
 }
 
 pub struct XyRect {
@@ -506,6 +902,19 @@ impl Hittable for XyRect {
             Point3::new(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        area_pdf(self, area, origin, direction)
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        let mut rng = rng();
+        let x = rng.gen_range(self.x0..self.x1);
+        let y = rng.gen_range(self.y0..self.y1);
+        Some(Point3::new(x, y, self.k) - *origin)
+    }
 }
 
 pub struct XzRect {
@@ -571,6 +980,19 @@ impl Hittable for XzRect {
             Point3::new(self.x1, self.k + 0.0001, self.y1),
         ))
     }
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        area_pdf(self, area, origin, direction)
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        let mut rng = rng();
+        let x = rng.gen_range(self.x0..self.x1);
+        let y = rng.gen_range(self.y0..self.y1);
+        Some(Point3::new(x, self.k, y) - *origin)
+    }
 }
 
 pub struct YzRect {
@@ -636,21 +1058,95 @@ impl Hittable for YzRect {
             Point3::new(self.k + 0.0001, self.x1, self.y1),
         ))
     }
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        area_pdf(self, area, origin, direction)
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        let mut rng = rng();
+        let x = rng.gen_range(self.x0..self.x1);
+        let y = rng.gen_range(self.y0..self.y1);
+        Some(Point3::new(self.k, x, y) - *origin)
+    }
 }
 
 pub struct HittableList {
     objects: Vec<Arc<Box<dyn Hittable + Sync>>>,
+    cached: Option<CachedBounds>,
+}
+
+// the group's box over a fixed shutter interval, kept up to date by `add`
+struct CachedBounds {
+    time0: f64,
+    time1: f64,
+    bbox: Option<Aabb>,
 }
 
 impl HittableList {
     pub fn new() -> HittableList {
-        HittableList { objects: vec![] }
+        HittableList {
+            objects: vec![],
+            cached: None,
+        }
+    }
+
+    /// Keep the group's box over `time0..time1` and test rays against it before any child.
+    /// A cheap alternative to a BVH for small groups; has no effect if a child is unbounded.
+    pub fn with_cached_bbox(mut self, time0: f64, time1: f64) -> HittableList {
+        self.cached = Some(CachedBounds {
+            time0,
+            time1,
+            bbox: self.bounding_box(time0, time1),
+        });
+        self
+    }
+
+    /// Lists shorter than this are tested object by object; a tree over them costs more
+    /// box tests than it saves.
+    pub const BVH_THRESHOLD: usize = 4;
+
+    /// The finished scene: a BVH over the objects for the shutter interval `time0..time1`,
+    /// or the list itself if it has fewer than `BVH_THRESHOLD` objects.
+    pub fn into_world(self, time0: f64, time1: f64) -> Box<dyn Hittable + Sync> {
+        match self.len() < HittableList::BVH_THRESHOLD {
+            true => Box::new(self),
+            false => Box::new(BvhNode::from_list(&self, time0, time1)),
+        }
     }
 
     pub fn add(&mut self, object: Arc<Box<dyn Hittable + Sync>>) {
+        if let Some(cached) = &mut self.cached {
+            let added = object.bounding_box(cached.time0, cached.time1);
+            cached.bbox = match (&cached.bbox, added) {
+                (Some(bbox), Some(added)) => Some(Aabb::surrounding_box(bbox, &added)),
+                (None, added) if self.objects.is_empty() => added,
+                _ => None,
+            };
+        }
         self.objects.push(Arc::clone(&object));
     }
 
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // false only when the cached box says the ray can't hit anything in the group
+    fn may_hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        match &self.cached {
+            Some(CachedBounds {
+                bbox: Some(bbox), ..
+            }) => bbox.hit(r, t_min, t_max),
+            _ => true,
+        }
+    }
+
     pub fn get_objects(&self) -> &Vec<Arc<Box<dyn Hittable + Sync>>> {
         &self.objects
     }
@@ -658,35 +1154,38 @@ impl HittableList {
 
 impl Hittable for HittableList {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut hit_anything = false;
+        if !self.may_hit(r, t_min, t_max) {
+            return None;
+        }
+        let mut closest: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
-
-        let temp_mat: Arc<Box<dyn Material>> =
-            Arc::new(Box::new(Metal::new(Vec3::new(0, 0, 0), 0.0)));
-        let mut temp_rec = HitRecord::new(
-            Vec3::new(0, 0, 0),
-            Vec3::new(0, 0, 0),
-            0.0,
-            0.0,
-            0.0,
-            false,
-            temp_mat,
-        );
         for object in self.objects.iter() {
-            match object.hit(&r, t_min, closest_so_far) {
-                Some(rec) => {
-                    hit_anything = true;
-                    closest_so_far = rec.t;
-                    temp_rec = rec.clone();
+            let hit = match &mut closest {
+                Some(rec) => object.hit_into(r, t_min, closest_so_far, rec),
+                None => {
+                    closest = object.hit(r, t_min, closest_so_far);
+                    closest.is_some()
                 }
-                None => (),
+            };
+            if hit {
+                closest_so_far = closest.as_ref().unwrap().t;
             }
         }
-        if hit_anything {
-            Some(temp_rec)
-        } else {
-            None
+        closest
+    }
+    fn hit_into(&self, r: &Ray, t_min: f64, t_max: f64, out: &mut HitRecord) -> bool {
+        if !self.may_hit(r, t_min, t_max) {
+            return false;
+        }
+        let mut hit_anything = false;
+        let mut closest_so_far = t_max;
+        for object in self.objects.iter() {
+            if object.hit_into(r, t_min, closest_so_far, out) {
+                hit_anything = true;
+                closest_so_far = out.t;
+            }
         }
+        hit_anything
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         if self.objects.is_empty() {
@@ -708,6 +1207,45 @@ impl Hittable for HittableList {
         }
         Some(temp_box)
     }
+    fn leaf_count(&self) -> usize {
+        self.objects.iter().map(|obj| obj.leaf_count()).sum()
+    }
+    fn depth(&self) -> usize {
+        1 + self
+            .objects
+            .iter()
+            .map(|obj| obj.depth())
+            .max()
+            .unwrap_or(0)
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for obj in self.objects.iter() {
+            gather_lights(obj, lights);
+        }
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.may_hit(r, t_min, t_max)
+            && self.objects.iter().any(|obj| obj.occluded(r, t_min, t_max))
+    }
+    // an even mixture of the children, so a child that can't be sampled just wastes its share
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = self
+            .objects
+            .iter()
+            .map(|obj| obj.pdf_value(origin, direction))
+            .sum();
+        total / self.objects.len() as f64
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        if self.objects.is_empty() {
+            return None;
+        }
+        let pick = rng().gen_range(0..self.objects.len());
+        self.objects[pick].random_direction(origin)
+    }
 }
 
 pub struct RectPrism {
@@ -782,12 +1320,31 @@ impl Hittable for RectPrism {
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(Aabb::new(self.box_min, self.box_max))
     }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        self.sides.collect_lights(lights)
+    }
 }
 
-pub struct Translate {
-    obj: Arc<Box<dyn Hittable + Send + Sync>>,
-    offset: Vec3,
-}
+/// The part of a transformed object that glows, ready to be wrapped in the same transform.
+fn lights_inside(
+    obj: &Arc<Box<dyn Hittable + Send + Sync>>,
+) -> Option<Arc<Box<dyn Hittable + Send + Sync>>> {
+    if obj.is_emissive() {
+        return Some(obj.clone());
+    }
+    let mut inner = HittableList::new();
+    obj.collect_lights(&mut inner);
+    if inner.get_objects().is_empty() {
+        None
+    } else {
+        Some(Arc::new(Box::new(inner)))
+    }
+}
+
+pub struct Translate {
+    obj: Arc<Box<dyn Hittable + Send + Sync>>,
+    offset: Vec3,
+}
 
 impl Translate {
     pub fn new(offset: &Vec3, obj: Arc<Box<dyn Hittable + Send + Sync>>) -> Translate {
@@ -804,21 +1361,20 @@ impl Hittable for Translate {
             &(*r.get_origin() - self.offset),
             r.get_direction(),
             r.get_time(),
-        );
+        )
+        .with_kind(r.get_kind())
+        .with_differential(r.get_differential());
         match self.obj.hit(&moved_r, t_min, t_max) {
             Some(rec) => {
                 let (normal, front_face) = HitRecord::create_normal_face(&moved_r, &rec.normal);
-                return Some(HitRecord {
+                Some(HitRecord {
                     p: *rec.get_p() + self.offset,
                     normal,
-                    t: rec.get_t(),
-                    u: rec.get_u(),
-                    v: rec.get_v(),
                     front_face,
-                    mat_ptr: rec.get_material().clone(),
-                });
+                    ..rec
+                })
             }
-            None => return None,
+            None => None,
         }
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
@@ -830,6 +1386,129 @@ impl Hittable for Translate {
             None => None,
         }
     }
+    fn leaf_count(&self) -> usize {
+        self.obj.leaf_count()
+    }
+    fn depth(&self) -> usize {
+        self.obj.depth()
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        if let Some(inner) = lights_inside(&self.obj) {
+            lights.add(Arc::new(Box::new(Translate::new(&self.offset, inner))));
+        }
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let moved_r = Ray::new(
+            &(*r.get_origin() - self.offset),
+            r.get_direction(),
+            r.get_time(),
+        )
+        .with_kind(r.get_kind());
+        self.obj.occluded(&moved_r, t_min, t_max)
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.obj.pdf_value(&(*origin - self.offset), direction)
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        self.obj.random_direction(&(*origin - self.offset))
+    }
+}
+
+/// Stretches `obj` by `scale` along each axis, about the origin, e.g. to reuse one mesh at
+/// several sizes. Negative factors mirror it; normals go through the inverse transpose, so the
+/// outside stays outside even though a mirrored mesh's winding is reversed.
+pub struct Scale {
+    obj: Arc<Box<dyn Hittable + Send + Sync>>,
+    scale: Vec3,
+    inverse: Vec3,
+}
+
+impl Scale {
+    pub fn new(scale: &Vec3, obj: Arc<Box<dyn Hittable + Send + Sync>>) -> Scale {
+        assert!(scale.iter().all(|s| s != 0.0), "cannot scale by zero");
+        Scale {
+            obj,
+            scale: *scale,
+            inverse: Vec3::new(
+                1.0 / scale.get_x(),
+                1.0 / scale.get_y(),
+                1.0 / scale.get_z(),
+            ),
+        }
+    }
+
+    pub fn uniform(factor: f64, obj: Arc<Box<dyn Hittable + Send + Sync>>) -> Scale {
+        Scale::new(&Vec3::splat(factor), obj)
+    }
+
+    pub fn get_scale(&self) -> &Vec3 {
+        &self.scale
+    }
+
+    // `t` carries over unchanged, since the whole ray is squeezed along with the object
+    fn to_object(&self, r: &Ray) -> Ray {
+        Ray::new(
+            &(*r.get_origin() * self.inverse),
+            &(*r.get_direction() * self.inverse),
+            r.get_time(),
+        )
+        .with_kind(r.get_kind())
+        .with_differential(r.get_differential().map(|d| d.map(|v| *v * self.inverse)))
+    }
+}
+
+impl Hittable for Scale {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rec = self.obj.hit(&self.to_object(r), t_min, t_max)?;
+        // the inverse transpose keeps the sign of `normal · direction`, so the normal still
+        // faces the ray and `front_face` holds
+        Some(HitRecord {
+            p: *rec.get_p() * self.scale,
+            normal: (*rec.get_normal() * self.inverse).unit(),
+            ..rec
+        })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let a = self.obj.bounding_box(time0, time1)?;
+        // a negative factor swaps that axis's ends
+        let (p, q) = (*a.get_min() * self.scale, *a.get_max() * self.scale);
+        Some(Aabb::new(
+            Point3::new(
+                f64::min(p.get_x(), q.get_x()),
+                f64::min(p.get_y(), q.get_y()),
+                f64::min(p.get_z(), q.get_z()),
+            ),
+            Point3::new(
+                f64::max(p.get_x(), q.get_x()),
+                f64::max(p.get_y(), q.get_y()),
+                f64::max(p.get_z(), q.get_z()),
+            ),
+        ))
+    }
+    fn leaf_count(&self) -> usize {
+        self.obj.leaf_count()
+    }
+    fn depth(&self) -> usize {
+        self.obj.depth()
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        if let Some(inner) = lights_inside(&self.obj) {
+            lights.add(Arc::new(Box::new(Scale::new(&self.scale, inner))));
+        }
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.obj.occluded(&self.to_object(r), t_min, t_max)
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        // squeezing directions by `inverse` changes solid angle by |det| / |inverse * w|^3
+        let w = direction.unit() * self.inverse;
+        let det = (self.inverse.get_x() * self.inverse.get_y() * self.inverse.get_z()).abs();
+        self.obj.pdf_value(&(*origin * self.inverse), &w) * det / w.length().powi(3)
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        let direction = self.obj.random_direction(&(*origin * self.inverse))?;
+        Some(direction * self.scale)
+    }
 }
 
 pub struct RotateY {
@@ -844,50 +1523,42 @@ impl RotateY {
         let angle = f64::to_radians(angle);
         let sin_theta = f64::sin(angle);
         let cos_theta = f64::cos(angle);
-        // ?
-        let bbox = obj.bounding_box(0.0, 1.0);
-        if bbox.is_none() {
-            return RotateY {
-                obj,
-                sin_theta,
-                cos_theta,
-                bbox: None,
-            };
-        }
-        let bbox = bbox.unwrap();
-        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
-        let mut max = Point3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let i = i as f64;
-                    let j = j as f64;
-                    let k = k as f64;
-                    let x = i * bbox.get_max().get_x() + (1.0 - i) * bbox.get_min().get_x();
-                    let y = j * bbox.get_max().get_y() + (1.0 - j) * bbox.get_min().get_y();
-                    let z = k * bbox.get_max().get_z() + (1.0 - k) * bbox.get_min().get_z();
-
-                    let newx = cos_theta * x + sin_theta * z;
-                    let newz = -sin_theta * x + cos_theta * z;
-                    let tester = Vec3::new(newx, y, newz);
-                    min.set_x(f64::min(min.get_x(), tester.get_x()));
-                    max.set_x(f64::max(max.get_x(), tester.get_x()));
-                    min.set_y(f64::min(min.get_y(), tester.get_y()));
-                    max.set_y(f64::max(max.get_y(), tester.get_y()));
-                    min.set_z(f64::min(min.get_z(), tester.get_z()));
-                    max.set_z(f64::max(max.get_z(), tester.get_z()));
-                }
-            }
-        }
+        let bbox = obj.bounding_box(0.0, 1.0).map(|bbox| {
+            rotated_box(&bbox, |v| {
+                Vec3::new(
+                    cos_theta * v.get_x() + sin_theta * v.get_z(),
+                    v.get_y(),
+                    -sin_theta * v.get_x() + cos_theta * v.get_z(),
+                )
+            })
+        });
         RotateY {
             obj,
             sin_theta,
             cos_theta,
-            bbox: Some(bbox),
+            bbox,
         }
     }
 }
 
+impl RotateY {
+    fn to_object(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.get_x() - self.sin_theta * v.get_z(),
+            v.get_y(),
+            self.sin_theta * v.get_x() + self.cos_theta * v.get_z(),
+        )
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.get_x() + self.sin_theta * v.get_z(),
+            v.get_y(),
+            -self.sin_theta * v.get_x() + self.cos_theta * v.get_z(),
+        )
+    }
+}
+
 impl Hittable for RotateY {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let origin = Vec3::new(
@@ -901,7 +1572,9 @@ impl Hittable for RotateY {
             self.sin_theta * r.get_direction().get_x() + self.cos_theta * r.get_direction().get_z(),
         );
 
-        let rotated_r = Ray::new(&origin, &direction, r.get_time());
+        let rotated_r = Ray::new(&origin, &direction, r.get_time())
+            .with_kind(r.get_kind())
+            .with_differential(r.get_differential().map(|d| d.map(|v| self.to_object(v))));
         let rec = self.obj.hit(&rotated_r, t_min, t_max);
         if rec.is_none() {
             return None;
@@ -918,21 +1591,326 @@ impl Hittable for RotateY {
             rec.get_normal().get_y(),
             -self.sin_theta * rec.get_normal().get_x() + self.cos_theta * rec.get_normal().get_z(),
         );
-        let (normal, front_face) = HitRecord::create_normal_face(&rotated_r, &normal);
-        Some(HitRecord::new(
+        let (normal, front_face) = HitRecord::create_normal_face(r, &normal);
+        Some(HitRecord {
             p,
             normal,
-            rec.get_t(),
-            rec.get_u(),
-            rec.get_v(),
             front_face,
-            rec.get_material(),
-        ))
+            ..rec
+        })
     }
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         self.bbox.clone()
     }
+    fn leaf_count(&self) -> usize {
+        self.obj.leaf_count()
+    }
+    fn depth(&self) -> usize {
+        self.obj.depth()
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        if let Some(inner) = lights_inside(&self.obj) {
+            lights.add(Arc::new(Box::new(RotateY {
+                obj: inner,
+                sin_theta: self.sin_theta,
+                cos_theta: self.cos_theta,
+                bbox: self.bbox.clone(),
+            })));
+        }
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.obj
+            .pdf_value(&self.to_object(origin), &self.to_object(direction))
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        let direction = self.obj.random_direction(&self.to_object(origin))?;
+        Some(self.to_world(&direction))
+    }
+}
+
+/// The box around all eight corners of `bbox` once moved by `to_world`.
+fn rotated_box(bbox: &Aabb, to_world: impl Fn(&Vec3) -> Vec3) -> Aabb {
+    let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut max = Point3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+    for i in 0..2 {
+        for j in 0..2 {
+            for k in 0..2 {
+                let i = i as f64;
+                let j = j as f64;
+                let k = k as f64;
+                let x = i * bbox.get_max().get_x() + (1.0 - i) * bbox.get_min().get_x();
+                let y = j * bbox.get_max().get_y() + (1.0 - j) * bbox.get_min().get_y();
+                let z = k * bbox.get_max().get_z() + (1.0 - k) * bbox.get_min().get_z();
+
+                let tester = to_world(&Vec3::new(x, y, z));
+                min.set_x(f64::min(min.get_x(), tester.get_x()));
+                max.set_x(f64::max(max.get_x(), tester.get_x()));
+                min.set_y(f64::min(min.get_y(), tester.get_y()));
+                max.set_y(f64::max(max.get_y(), tester.get_y()));
+                min.set_z(f64::min(min.get_z(), tester.get_z()));
+                max.set_z(f64::max(max.get_z(), tester.get_z()));
+            }
+        }
+    }
+    Aabb::new(min, max)
+}
+
+/// Pitches `obj` by `angle` degrees about the x axis, turning +y towards +z.
+pub struct RotateX {
+    obj: Arc<Box<dyn Hittable + Send + Sync>>,
+    sin_theta: f64,
+    cos_theta: f64,
+    bbox: Option<Aabb>,
+}
+
+impl RotateX {
+    pub fn new(angle: f64, obj: Arc<Box<dyn Hittable + Send + Sync>>) -> RotateX {
+        let angle = f64::to_radians(angle);
+        let mut rotate = RotateX {
+            obj,
+            sin_theta: f64::sin(angle),
+            cos_theta: f64::cos(angle),
+            bbox: None,
+        };
+        rotate.bbox = rotate
+            .obj
+            .bounding_box(0.0, 1.0)
+            .map(|bbox| rotated_box(&bbox, |v| rotate.to_world(v)));
+        rotate
+    }
+
+    fn to_object(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            v.get_x(),
+            self.cos_theta * v.get_y() + self.sin_theta * v.get_z(),
+            -self.sin_theta * v.get_y() + self.cos_theta * v.get_z(),
+        )
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            v.get_x(),
+            self.cos_theta * v.get_y() - self.sin_theta * v.get_z(),
+            self.sin_theta * v.get_y() + self.cos_theta * v.get_z(),
+        )
+    }
+}
+
+impl Hittable for RotateX {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rotated_r = Ray::new(
+            &self.to_object(r.get_origin()),
+            &self.to_object(r.get_direction()),
+            r.get_time(),
+        )
+        .with_kind(r.get_kind())
+        .with_differential(r.get_differential().map(|d| d.map(|v| self.to_object(v))));
+        let rec = self.obj.hit(&rotated_r, t_min, t_max)?;
+        let normal = self.to_world(rec.get_normal());
+        let (normal, front_face) = HitRecord::create_normal_face(r, &normal);
+        Some(HitRecord {
+            p: self.to_world(rec.get_p()),
+            normal,
+            front_face,
+            ..rec
+        })
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        self.bbox.clone()
+    }
+    fn leaf_count(&self) -> usize {
+        self.obj.leaf_count()
+    }
+    fn depth(&self) -> usize {
+        self.obj.depth()
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        if let Some(inner) = lights_inside(&self.obj) {
+            lights.add(Arc::new(Box::new(RotateX {
+                obj: inner,
+                sin_theta: self.sin_theta,
+                cos_theta: self.cos_theta,
+                bbox: self.bbox.clone(),
+            })));
+        }
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.obj
+            .pdf_value(&self.to_object(origin), &self.to_object(direction))
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        let direction = self.obj.random_direction(&self.to_object(origin))?;
+        Some(self.to_world(&direction))
+    }
+}
+
+/// Rolls `obj` by `angle` degrees about the z axis, turning +x towards +y.
+pub struct RotateZ {
+    obj: Arc<Box<dyn Hittable + Send + Sync>>,
+    sin_theta: f64,
+    cos_theta: f64,
+    bbox: Option<Aabb>,
+}
+
+impl RotateZ {
+    pub fn new(angle: f64, obj: Arc<Box<dyn Hittable + Send + Sync>>) -> RotateZ {
+        let angle = f64::to_radians(angle);
+        let mut rotate = RotateZ {
+            obj,
+            sin_theta: f64::sin(angle),
+            cos_theta: f64::cos(angle),
+            bbox: None,
+        };
+        rotate.bbox = rotate
+            .obj
+            .bounding_box(0.0, 1.0)
+            .map(|bbox| rotated_box(&bbox, |v| rotate.to_world(v)));
+        rotate
+    }
+
+    fn to_object(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.get_x() + self.sin_theta * v.get_y(),
+            -self.sin_theta * v.get_x() + self.cos_theta * v.get_y(),
+            v.get_z(),
+        )
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.get_x() - self.sin_theta * v.get_y(),
+            self.sin_theta * v.get_x() + self.cos_theta * v.get_y(),
+            v.get_z(),
+        )
+    }
+}
+
+impl Hittable for RotateZ {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rotated_r = Ray::new(
+            &self.to_object(r.get_origin()),
+            &self.to_object(r.get_direction()),
+            r.get_time(),
+        )
+        .with_kind(r.get_kind())
+        .with_differential(r.get_differential().map(|d| d.map(|v| self.to_object(v))));
+        let rec = self.obj.hit(&rotated_r, t_min, t_max)?;
+        let normal = self.to_world(rec.get_normal());
+        let (normal, front_face) = HitRecord::create_normal_face(r, &normal);
+        Some(HitRecord {
+            p: self.to_world(rec.get_p()),
+            normal,
+            front_face,
+            ..rec
+        })
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        self.bbox.clone()
+    }
+    fn leaf_count(&self) -> usize {
+        self.obj.leaf_count()
+    }
+    fn depth(&self) -> usize {
+        self.obj.depth()
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        if let Some(inner) = lights_inside(&self.obj) {
+            lights.add(Arc::new(Box::new(RotateZ {
+                obj: inner,
+                sin_theta: self.sin_theta,
+                cos_theta: self.cos_theta,
+                bbox: self.bbox.clone(),
+            })));
+        }
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.obj
+            .pdf_value(&self.to_object(origin), &self.to_object(direction))
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        let direction = self.obj.random_direction(&self.to_object(origin))?;
+        Some(self.to_world(&direction))
+    }
+}
+
+/// Hides `obj` from some kinds of rays, e.g. a backdrop that the camera sees but that
+/// casts no shadows. Everything is visible until switched off.
+pub struct Visibility {
+    obj: Arc<Box<dyn Hittable + Send + Sync>>,
+    primary: bool,
+    shadow: bool,
+    secondary: bool,
+}
+
+impl Visibility {
+    pub fn new(obj: Arc<Box<dyn Hittable + Send + Sync>>) -> Visibility {
+        Visibility {
+            obj,
+            primary: true,
+            shadow: true,
+            secondary: true,
+        }
+    }
+
+    pub fn with_primary(mut self, visible: bool) -> Visibility {
+        self.primary = visible;
+        self
+    }
+
+    pub fn with_shadow(mut self, visible: bool) -> Visibility {
+        self.shadow = visible;
+        self
+    }
+
+    pub fn with_secondary(mut self, visible: bool) -> Visibility {
+        self.secondary = visible;
+        self
+    }
+
+    fn sees(&self, r: &Ray) -> bool {
+        match r.get_kind() {
+            RayKind::Primary => self.primary,
+            RayKind::Shadow => self.shadow,
+            RayKind::Secondary => self.secondary,
+        }
+    }
+}
+
+impl Hittable for Visibility {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        match self.sees(r) {
+            true => self.obj.hit(r, t_min, t_max),
+            false => None,
+        }
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.obj.bounding_box(time0, time1)
+    }
+    fn leaf_count(&self) -> usize {
+        self.obj.leaf_count()
+    }
+    fn depth(&self) -> usize {
+        self.obj.depth()
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.sees(r) && self.obj.occluded(r, t_min, t_max)
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        // a light that shadow rays can't reach would only ever be sampled as blocked;
+        // the rest are only aimed at, so every kind of ray may see them there
+        if let (true, Some(inner)) = (self.shadow, lights_inside(&self.obj)) {
+            lights.add(Arc::new(Box::new(Visibility::new(inner))));
+        }
+    }
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.obj.pdf_value(origin, direction)
+    }
+    fn random_direction(&self, origin: &Point3) -> Option<Vec3> {
+        self.obj.random_direction(origin)
+    }
 }
 
 pub struct ConstantMedium {
@@ -942,35 +1920,54 @@ pub struct ConstantMedium {
 }
 
 impl ConstantMedium {
+    /// Densities below this (including zero, negative and NaN) are raised to it, so the
+    /// medium is effectively clear instead of dividing by zero.
+    pub const MIN_DENSITY: f64 = 1e-12;
+
     pub fn from_color(c: &Color, d: f64, b: Arc<Box<dyn Hittable>>) -> ConstantMedium {
         ConstantMedium {
             boundary: b.clone(),
             phase_function: Arc::new(Box::new(Isotropic::from_color(c))),
-            neg_inv_density: -1.0 / d,
+            neg_inv_density: -1.0 / f64::max(d, ConstantMedium::MIN_DENSITY),
         }
     }
 }
 
 impl Hittable for ConstantMedium {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let rec1 = self.boundary.hit(r, -f64::INFINITY, f64::INFINITY)?;
-        let rec2 = self.boundary.hit(r, rec1.get_t() + 0.0001, f64::INFINITY)?;
-
-        let mut t1 = f64::max(rec1.get_t(), t_min);
-        let t2 = f64::min(rec2.get_t(), t_max);
-        if t1 >= t2 {
-            return None;
-        }
-        if t1 < 0.0 {
-            t1 = 0.0;
-        }
         let ray_length = r.get_direction().length();
-        let distance_inside_boundary = (t2 - t1) * ray_length;
-        let hit_distance = self.neg_inv_density * f64::ln(thread_rng().gen());
-        if hit_distance > distance_inside_boundary {
+        // drawn at the first stretch of fog inside [t_min, t_max], then used up across
+        // every entry/exit pair, so a non-convex boundary's gaps stay clear
+        let mut hit_distance: Option<f64> = None;
+        let mut from = -f64::INFINITY;
+        let t = loop {
+            let rec1 = self.boundary.hit(r, from, f64::INFINITY)?;
+            let rec2 = self.boundary.hit(r, rec1.get_t() + 0.0001, f64::INFINITY)?;
+            if rec1.get_t() >= t_max {
+                return None;
+            }
+            from = rec2.get_t() + 0.0001;
+
+            let t1 = f64::max(f64::max(rec1.get_t(), t_min), 0.0);
+            let t2 = f64::min(rec2.get_t(), t_max);
+            if t1 >= t2 {
+                continue;
+            }
+            let remaining =
+                *hit_distance.get_or_insert_with(|| self.neg_inv_density * f64::ln(rng().gen()));
+            // `ln(0)` is -inf, and a zero-length ray has no distance to scale by
+            if !remaining.is_finite() {
+                return None;
+            }
+            let distance_inside_boundary = (t2 - t1) * ray_length;
+            if remaining <= distance_inside_boundary {
+                break t1 + remaining / ray_length;
+            }
+            hit_distance = Some(remaining - distance_inside_boundary);
+        };
+        if !t.is_finite() {
             return None;
         }
-        let t = t1 + hit_distance / ray_length;
         let p = r.at(t);
         let normal = Vec3::new(0, 0, 0);
         let front_face = true;
@@ -982,6 +1979,7 @@ impl Hittable for ConstantMedium {
             v: 0.0,
             front_face,
             mat_ptr: self.phase_function.clone(),
+            barycentric: None,
         })
     }
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
@@ -989,6 +1987,99 @@ impl Hittable for ConstantMedium {
     }
 }
 
+/// How a `Csg` combines its two solids.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    /// Inside either solid.
+    Union,
+    /// Inside both solids.
+    Intersection,
+    /// Inside the first solid but not the second, e.g. a ball with a bite taken out.
+    Difference,
+}
+
+/// A boolean combination of two closed, convex solids (spheres, boxes), found from where
+/// the ray enters and leaves each one.
+pub struct Csg {
+    op: CsgOp,
+    a: Arc<Box<dyn Hittable + Send + Sync>>,
+    b: Arc<Box<dyn Hittable + Send + Sync>>,
+}
+
+impl Csg {
+    pub fn new(
+        op: CsgOp,
+        a: Arc<Box<dyn Hittable + Send + Sync>>,
+        b: Arc<Box<dyn Hittable + Send + Sync>>,
+    ) -> Csg {
+        Csg { op, a, b }
+    }
+
+    pub fn get_op(&self) -> CsgOp {
+        self.op
+    }
+
+    /// Where the whole line of `r` enters and leaves `obj`, if it passes through it.
+    fn span(obj: &dyn Hittable, r: &Ray) -> Option<(HitRecord, HitRecord)> {
+        let enter = obj.hit(r, -f64::INFINITY, f64::INFINITY)?;
+        let exit = obj.hit(r, enter.get_t() + 0.0001, f64::INFINITY)?;
+        Some((enter, exit))
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let a = Csg::span(self.a.as_ref().as_ref(), r);
+        let b = Csg::span(self.b.as_ref().as_ref(), r);
+        let inside = |span: &Option<(HitRecord, HitRecord)>, t: f64| match span {
+            Some((enter, exit)) => enter.get_t() < t && t < exit.get_t(),
+            None => false,
+        };
+        // each boundary point survives depending on whether it lies in the other solid;
+        // the second solid's surface faces the other way where it carves a cavity
+        let (keep_a_in_b, keep_b_in_a, flip_b) = match self.op {
+            CsgOp::Union => (false, false, false),
+            CsgOp::Intersection => (true, true, false),
+            CsgOp::Difference => (false, true, true),
+        };
+        let mut surfaces: Vec<(&HitRecord, bool)> = Vec::with_capacity(4);
+        if let Some((enter, exit)) = &a {
+            for rec in [enter, exit] {
+                if inside(&b, rec.get_t()) == keep_a_in_b {
+                    surfaces.push((rec, false));
+                }
+            }
+        }
+        if let Some((enter, exit)) = &b {
+            for rec in [enter, exit] {
+                if inside(&a, rec.get_t()) == keep_b_in_a {
+                    surfaces.push((rec, flip_b));
+                }
+            }
+        }
+        let (rec, flip) = surfaces
+            .into_iter()
+            .filter(|(rec, _)| t_min < rec.get_t() && rec.get_t() < t_max)
+            .min_by(|(x, _), (y, _)| x.get_t().total_cmp(&y.get_t()))?;
+        // still facing the ray, but the outside is now on the other side
+        Some(HitRecord {
+            front_face: rec.get_front_face() != flip,
+            ..rec.clone()
+        })
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let a = self.a.bounding_box(time0, time1);
+        match self.op {
+            CsgOp::Union => match (a, self.b.bounding_box(time0, time1)) {
+                (Some(a), Some(b)) => Some(Aabb::surrounding_box(&a, &b)),
+                _ => None,
+            },
+            // never bigger than the first solid
+            CsgOp::Intersection | CsgOp::Difference => a,
+        }
+    }
+}
+
 pub struct Isotropic {
     albedo: Arc<Box<dyn Texture>>,
 }
@@ -1008,6 +2099,9 @@ impl Material for Isotropic {
             self.albedo.value(rec.get_u(), rec.get_v(), rec.get_p()),
         ))
     }
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Option<f64> {
+        Some(1.0 / (4.0 * PI))
+    }
 }
 
 pub trait Material: Send + Sync {
@@ -1015,45 +2109,114 @@ pub trait Material: Send + Sync {
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
         Color::new(0, 0, 0)
     }
+    /// Light leaving `p` along `outgoing`, for emitters that aren't the same in every
+    /// direction. Defaults to `emitted`.
+    fn emitted_toward(&self, u: f64, v: f64, p: &Point3, _outgoing: &Vec3) -> Color {
+        self.emitted(u, v, p)
+    }
+    /// Reflectance at `lambda` for spectral rendering, given the RGB `attenuation` from `scatter`.
+    /// Defaults to a smooth upsampling of the RGB value.
+    fn spectral_attenuation(&self, attenuation: &Color, lambda: f64) -> f64 {
+        spectrum::reflectance(attenuation, lambda)
+    }
+    /// The interior of a refractive material, for tracking nested dielectrics.
+    fn medium(&self) -> Option<Medium> {
+        None
+    }
+    /// `scatter` with the IOR ratio across the surface worked out by the caller.
+    fn scatter_with_ratio(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _refraction_ratio: f64,
+    ) -> Option<(Ray, Color)> {
+        self.scatter(r_in, rec)
+    }
+    /// Whether surfaces with this material are light sources.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    /// Density, per solid angle, of `scatter` sending `r_in` off along `scattered`, for
+    /// materials whose scattering is spread out enough to combine with light sampling.
+    /// `None` (the default) for mirror-like materials.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Option<f64> {
+        None
+    }
 }
 
 pub struct Lambertian {
     albedo: Arc<Box<dyn Texture>>,
+    emit: Option<Arc<Box<dyn Texture>>>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Lambertian {
         Lambertian {
             albedo: Arc::new(Box::new(SolidColor::new(&albedo))),
+            emit: None,
         }
     }
 
     pub fn from_pointer(texture: Arc<Box<dyn Texture>>) -> Lambertian {
         Lambertian {
             albedo: texture.clone(),
+            emit: None,
         }
     }
+
+    /// Glow with `emit` as well as reflecting, like a lampshade or hot metal; unlike a
+    /// `DiffuseLight` the surface still scatters light that arrives on it.
+    pub fn with_emission(mut self, emit: &Color) -> Lambertian {
+        self.emit = Some(Arc::new(Box::new(SolidColor::new(emit))));
+        self
+    }
 }
 
 impl Material for Lambertian {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let mut scatter_direction = *rec.get_normal() + random_unit_vector();
+        let normal = *rec.get_normal();
+        let mut scatter_direction = normal + random_unit_vector();
+        // the pixel's own width when the ray carries differentials, else just how far it went
+        let footprint = match r_in.footprint(rec.t) {
+            Some(width) => Footprint::Width(width),
+            None => Footprint::Distance(rec.t * r_in.get_direction().length()),
+        };
 
         // catch degenerate scatter directions
         if scatter_direction.near_zero() {
-            scatter_direction = *rec.get_normal();
+            scatter_direction = normal;
         }
 
         Some((
             Ray::new(rec.get_p(), &scatter_direction, r_in.get_time()),
-            self.albedo.value(rec.u, rec.v, &rec.p),
+            self.albedo
+                .filtered_value(rec.u, rec.v, &rec.p, &normal, footprint),
         ))
     }
+    // normal + random unit vector is cosine-distributed about the normal
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Option<f64> {
+        let cosine = rec.get_normal().dot(&scattered.get_direction().unit());
+        Some(f64::max(cosine, 0.0) / PI)
+    }
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        match &self.emit {
+            Some(emit) => emit.value(u, v, p),
+            None => Color::zero(),
+        }
+    }
+    fn is_emissive(&self) -> bool {
+        self.emit.is_some()
+    }
 }
 
+/// A fuzzy mirror. Fuzz can push a reflection below the surface, mostly for rough metal
+/// at grazing angles; by default those samples are absorbed, which darkens rough edges.
 pub struct Metal {
     albedo: Color,
     fuzz: f64,
+    roughness: Option<Arc<Box<dyn Texture>>>,
+    clamp_grazing: bool,
+    fresnel: bool,
 }
 
 impl Metal {
@@ -1061,22 +2224,76 @@ impl Metal {
         Metal {
             albedo,
             fuzz: if fuzz < 1.0 { fuzz } else { 1.0 },
+            roughness: None,
+            clamp_grazing: false,
+            fresnel: false,
+        }
+    }
+
+    /// Fuzz is read per hit from `roughness` (channel average, clamped to [0, 1]).
+    pub fn from_roughness(albedo: Color, roughness: Arc<Box<dyn Texture>>) -> Metal {
+        Metal {
+            albedo,
+            fuzz: 1.0,
+            roughness: Some(roughness),
+            clamp_grazing: false,
+            fresnel: false,
+        }
+    }
+
+    /// Keep fuzzed reflections that dip below the surface, flattened to just above it,
+    /// instead of absorbing them.
+    pub fn with_grazing_clamp(mut self) -> Metal {
+        self.clamp_grazing = true;
+        self
+    }
+
+    /// Treat `albedo` as the reflectance head-on and brighten toward white at grazing
+    /// angles (Schlick's approximation), instead of reflecting `albedo` at every angle.
+    pub fn with_fresnel(mut self) -> Metal {
+        self.fresnel = true;
+        self
+    }
+
+    fn reflectance(&self, cosine: f64) -> Color {
+        match self.fresnel {
+            true => {
+                let weight = (1.0 - clamp(cosine, 0.0, 1.0)).powi(5);
+                self.albedo + (Color::new(1, 1, 1) - self.albedo) * weight
+            }
+            false => self.albedo,
+        }
+    }
+
+    fn fuzz_at(&self, rec: &HitRecord) -> f64 {
+        match &self.roughness {
+            Some(texture) => {
+                let sample = texture.value(rec.get_u(), rec.get_v(), rec.get_p());
+                clamp(sample.iter().sum::<f64>() / 3.0, 0.0, 1.0)
+            }
+            None => self.fuzz,
         }
     }
 }
 
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let reflected = r_in.get_direction().unit().reflect(rec.get_normal());
+        let unit_direction = r_in.get_direction().unit();
+        let reflected = unit_direction.reflect(rec.get_normal());
 
-        let scattered = Ray::new(
-            rec.get_p(),
-            &(reflected + self.fuzz * random_in_unit_sphere()),
-            r_in.get_time(),
-        );
+        let mut direction = reflected + self.fuzz_at(rec) * random_in_unit_sphere();
+        let below = direction.dot(&rec.normal);
+        if self.clamp_grazing && below <= 0.0 && !direction.near_zero() {
+            // drop the part going into the surface and lift it a hair back out
+            direction = direction - (below - 1e-4 * direction.length()) * rec.normal;
+        }
+        let scattered = Ray::new(rec.get_p(), &direction, r_in.get_time());
 
         if scattered.get_direction().dot(&rec.normal) > 0.0 {
-            Some((scattered, self.albedo.clone()))
+            Some((
+                scattered,
+                self.reflectance(-unit_direction.dot(&rec.normal)),
+            ))
         } else {
             None
         }
@@ -1085,11 +2302,19 @@ impl Material for Metal {
 
 pub struct Dielectric {
     ir: f64,
+    priority: u32,
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Dielectric {
-        Dielectric { ir }
+        Dielectric { ir, priority: 0 }
+    }
+
+    /// Where dielectrics overlap, the one with the higher priority fills the shared volume;
+    /// see `MediumStack`.
+    pub fn with_priority(mut self, priority: u32) -> Dielectric {
+        self.priority = priority;
+        self
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -1101,13 +2326,29 @@ impl Dielectric {
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let mut rng = thread_rng();
-        let attenuation = Vec3::new(1, 1, 1);
         let refraction_ratio = if rec.get_front_face() {
             1.0 / self.ir
         } else {
             self.ir
         };
+        self.scatter_with_ratio(r_in, rec, refraction_ratio)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(Medium {
+            ir: self.ir,
+            priority: self.priority,
+        })
+    }
+
+    fn scatter_with_ratio(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        refraction_ratio: f64,
+    ) -> Option<(Ray, Color)> {
+        let mut rng = rng();
+        let attenuation = Vec3::new(1, 1, 1);
         let unit_direction = r_in.get_direction().unit();
 
         let cos_theta = f64::min((-unit_direction).dot(&rec.normal), 1.0);
@@ -1119,13 +2360,123 @@ impl Material for Dielectric {
         {
             unit_direction.reflect(&rec.normal)
         } else {
-            Vec3::refract(&unit_direction, &rec.get_normal(), refraction_ratio)
+            Vec3::refract(&unit_direction, rec.get_normal(), refraction_ratio)
         };
 
         Some((Ray::new(&rec.p, &direction, r_in.get_time()), attenuation))
     }
 }
 
+/// A clear dielectric layer over `base`, like varnish or car paint: each hit either bounces
+/// off the coat as a mirror, with the coat's Fresnel reflectance as the probability, or goes
+/// through to the base untouched.
+pub struct Coated {
+    base: Arc<Box<dyn Material>>,
+    ir: f64,
+}
+
+impl Coated {
+    pub fn new(base: Arc<Box<dyn Material>>, ir: f64) -> Coated {
+        Coated { base, ir }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+        let unit_direction = r_in.get_direction().unit();
+        let cos_theta = f64::min((-unit_direction).dot(rec.get_normal()), 1.0);
+        if Dielectric::reflectance(cos_theta, 1.0 / self.ir) > rng().gen::<f64>() {
+            let reflected = unit_direction.reflect(rec.get_normal());
+            return Some((
+                Ray::new(rec.get_p(), &reflected, r_in.get_time()),
+                Color::one(),
+            ));
+        }
+        self.base.scatter(r_in, rec)
+    }
+}
+
+/// Index of refraction and overlap priority of a dielectric's interior.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Medium {
+    ir: f64,
+    priority: u32,
+}
+
+impl Medium {
+    pub fn new(ir: f64, priority: u32) -> Medium {
+        Medium { ir, priority }
+    }
+
+    pub fn get_ir(&self) -> f64 {
+        self.ir
+    }
+}
+
+/// What a ray does at a dielectric surface, given the media it's already inside.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interface {
+    /// The surface is buried inside a higher-priority medium: carry on unchanged.
+    Pass,
+    /// A real boundary, with the ratio of the IOR on the incoming side to the far side.
+    Refract(f64),
+}
+
+/// The dielectrics a path is currently inside, so that surfaces of overlapping objects
+/// (a bubble in glass, glass in water) refract with the right pair of IORs.
+/// Media are identified by the caller, e.g. by material pointer.
+#[derive(Debug, Default)]
+pub struct MediumStack {
+    entered: Vec<(usize, Medium)>,
+}
+
+impl MediumStack {
+    pub fn new() -> MediumStack {
+        MediumStack::default()
+    }
+
+    // the medium filling the current point: highest priority, most recently entered on ties
+    fn current(&self, skip: Option<usize>) -> Option<(usize, Medium)> {
+        let mut best: Option<(usize, Medium)> = None;
+        for (k, &(_, medium)) in self.entered.iter().enumerate() {
+            if Some(k) != skip && best.is_none_or(|(_, b)| medium.priority >= b.priority) {
+                best = Some((k, medium));
+            }
+        }
+        best
+    }
+
+    pub fn interface(&self, id: usize, medium: Medium, entering: bool) -> Interface {
+        let current = self.current(None);
+        if entering {
+            return match current {
+                Some((_, outer)) if medium.priority < outer.priority => Interface::Pass,
+                Some((_, outer)) => Interface::Refract(outer.ir / medium.ir),
+                None => Interface::Refract(1.0 / medium.ir),
+            };
+        }
+        match current {
+            // leaving something we never saw the ray enter: assume it exits into air
+            _ if !self.entered.iter().any(|&(e, _)| e == id) => Interface::Refract(medium.ir),
+            Some((k, _)) if self.entered[k].0 != id => Interface::Pass,
+            Some((k, _)) => {
+                let beyond = self.current(Some(k)).map_or(1.0, |(_, m)| m.ir);
+                Interface::Refract(medium.ir / beyond)
+            }
+            None => Interface::Refract(medium.ir),
+        }
+    }
+
+    /// Record that the ray went through the surface of `id`, into or out of it.
+    pub fn cross(&mut self, id: usize, medium: Medium, entering: bool) {
+        if entering {
+            self.entered.push((id, medium));
+        } else if let Some(k) = self.entered.iter().rposition(|&(e, _)| e == id) {
+            self.entered.remove(k);
+        }
+    }
+}
+
 pub struct DiffuseLight {
     emit: Arc<Box<dyn Texture>>,
 }
@@ -1140,6 +2491,15 @@ impl DiffuseLight {
     pub fn from_pointer(a: Arc<Box<dyn Texture>>) -> DiffuseLight {
         DiffuseLight { emit: a.clone() }
     }
+
+    /// Glow with the picture in the PPM at `path`, `intensity` times as bright as its
+    /// decoded colors; put it on a rect for a screen or a lit sign.
+    pub fn from_image(path: &str, intensity: f64) -> Result<DiffuseLight, ImageError> {
+        let image: Arc<Box<dyn Texture>> = Arc::new(Box::new(Image::from_ppm(path)?));
+        Ok(DiffuseLight::from_pointer(Arc::new(Box::new(Scaled::new(
+            image, intensity,
+        )))))
+    }
 }
 
 impl Material for DiffuseLight {
@@ -1149,4 +2509,999 @@ impl Material for DiffuseLight {
     fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
         self.emit.value(u, v, p)
     }
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+/// Emits along `direction`: fully within `inner` degrees of it, fading smoothly to nothing
+/// at `outer` degrees.
+pub struct SpotLight {
+    emit: Color,
+    direction: Vec3,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    pub fn new(c: &Color, direction: Vec3, inner: f64, outer: f64) -> SpotLight {
+        assert!(0.0 <= inner && inner < outer && outer <= 180.0);
+        SpotLight {
+            emit: *c,
+            direction: direction.unit(),
+            cos_inner: f64::cos(inner.to_radians()),
+            cos_outer: f64::cos(outer.to_radians()),
+        }
+    }
+
+    /// Share of the full emission sent along `outgoing`.
+    pub fn falloff(&self, outgoing: &Vec3) -> f64 {
+        let cos = self.direction.dot(&outgoing.unit());
+        let x = clamp(
+            (cos - self.cos_outer) / (self.cos_inner - self.cos_outer),
+            0.0,
+            1.0,
+        );
+        // smoothstep
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+impl Material for SpotLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Ray, Color)> {
+        None
+    }
+    /// The on-axis emission.
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.emit
+    }
+    fn emitted_toward(&self, _u: f64, _v: f64, _p: &Point3, outgoing: &Vec3) -> Color {
+        self.falloff(outgoing) * self.emit
+    }
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::{CellJitter, Checker, Texture};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn unit_triangle() -> Triangle {
+        Triangle::new(
+            Point3::new(0, 0, 0),
+            Point3::new(1, 0, 0),
+            Point3::new(0, 1, 0),
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+        )
+    }
+
+    fn down_at(x: f64, y: f64) -> Ray {
+        Ray::new(&Point3::new(x, y, 1), &Vec3::new(0, 0, -1), 0.0)
+    }
+
+    struct Counting {
+        inner: Sphere,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Hittable for Counting {
+        fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.inner.hit(r, t_min, t_max)
+        }
+        fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+            self.inner.bounding_box(time0, time1)
+        }
+    }
+
+    #[test]
+    fn backface_culling_skips_the_inside() {
+        let from_below = Ray::new(&Point3::new(0.2, 0.2, -1), &Vec3::new(0, 0, 1), 0.0);
+        let plain = unit_triangle();
+        assert!(plain.hit(&from_below, 0.001, f64::INFINITY).is_some());
+
+        let culled = unit_triangle().with_backface_culling();
+        assert!(culled.hit(&from_below, 0.001, f64::INFINITY).is_none());
+        let rec = culled
+            .hit(&down_at(0.2, 0.2), 0.001, f64::INFINITY)
+            .unwrap();
+        assert!(rec.get_front_face());
+    }
+
+    #[test]
+    fn cached_bbox_rejects_before_children() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counting = |x: f64| -> Arc<Box<dyn Hittable + Sync>> {
+            Arc::new(Box::new(Counting {
+                inner: Sphere::new(
+                    Point3::new(x, 0, 0),
+                    1.0,
+                    Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+                ),
+                calls: calls.clone(),
+            }))
+        };
+        let mut group = HittableList::new().with_cached_bbox(0.0, 1.0);
+        group.add(counting(0.0));
+        group.add(counting(3.0));
+        let mut plain = HittableList::new();
+        plain.add(counting(0.0));
+        plain.add(counting(3.0));
+
+        let miss = Ray::new(&Point3::new(0, 5, 5), &Vec3::new(1, 0, 0), 0.0);
+        assert!(plain.hit(&miss, 0.001, f64::INFINITY).is_none());
+        assert_eq!(calls.swap(0, Ordering::Relaxed), 2);
+        assert!(group.hit(&miss, 0.001, f64::INFINITY).is_none());
+        assert!(!group.occluded(&miss, 0.001, f64::INFINITY));
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+
+        // the box grew with the second child, so rays at it still get through
+        let toward_second = Ray::new(&Point3::new(3, 0, 5), &Vec3::new(0, 0, -1), 0.0);
+        assert!(group.hit(&toward_second, 0.001, f64::INFINITY).is_some());
+    }
+
+    #[test]
+    fn zero_density_medium_never_hits_nan() {
+        crate::mutil::seed(3);
+        for d in [0.0, -1.0, f64::NAN] {
+            let boundary: Arc<Box<dyn Hittable>> = Arc::new(Box::new(Sphere::new(
+                Point3::new(0, 0, 0),
+                1.0,
+                Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+            )));
+            let medium = ConstantMedium::from_color(&Color::new(1, 1, 1), d, boundary);
+            for _ in 0..1000 {
+                let r = Ray::new(&Point3::new(0, 0, -5), &Vec3::new(0, 0, 1), 0.0);
+                // clamped to a practically clear medium rather than a NaN wall
+                assert!(medium.hit(&r, 0.001, f64::INFINITY).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn faces_on_a_cell_wall_take_their_own_cells_color() {
+        // a box filling exactly one cell of the grid, its sides right on the cell walls
+        let texture = CellJitter::new(&Color::new(0.48, 0.83, 0.53), 100.0, 0.3);
+        let inside = texture.value(0.0, 0.0, &Point3::new(50, 25, 50));
+        let material: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::from_pointer(
+            Arc::new(Box::new(texture)),
+        )));
+        let cell = RectPrism::new(
+            &Point3::new(0, 0, 0),
+            &Point3::new(100, 50, 100),
+            material.clone(),
+        );
+        let rays = [
+            Ray::new(&Point3::new(150, 25, 50), &Vec3::new(-1, 0, 0), 0.0),
+            Ray::new(&Point3::new(-50, 25, 50), &Vec3::new(1, 0, 0), 0.0),
+            Ray::new(&Point3::new(50, 25, 150), &Vec3::new(0, 0, -1), 0.0),
+            Ray::new(&Point3::new(50, 25, -50), &Vec3::new(0, 0, 1), 0.0),
+        ];
+        for r in &rays {
+            let rec = cell.hit(r, 0.001, f64::INFINITY).unwrap();
+            let (_, albedo) = material.scatter(r, &rec).unwrap();
+            assert_eq!(albedo, inside);
+        }
+    }
+
+    #[test]
+    fn medium_in_non_convex_boundary_skips_the_gaps() {
+        crate::mutil::seed(3);
+        let grey: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        // two unit spheres along x with a clear gap between them
+        let mut blobs = HittableList::new();
+        for x in [-2, 2] {
+            blobs.add(Arc::new(Box::new(Sphere::new(
+                Point3::new(x, 0, 0),
+                1.0,
+                grey.clone(),
+            ))));
+        }
+        let boundary: Arc<Box<dyn Hittable>> = Arc::new(Box::new(blobs));
+        let medium = ConstantMedium::from_color(&Color::new(1, 1, 1), 0.5, boundary);
+
+        let n = 4000;
+        let r = Ray::new(&Point3::new(-5, 0, 0), &Vec3::new(1, 0, 0), 0.0);
+        let mut in_second = 0;
+        for _ in 0..n {
+            if let Some(rec) = medium.hit(&r, 0.001, f64::INFINITY) {
+                let x = rec.get_p().get_x();
+                assert!(
+                    !(-1.0 + 1e-6..1.0 - 1e-6).contains(&x),
+                    "fog in the gap at {}",
+                    x
+                );
+                if x > 0.0 {
+                    in_second += 1;
+                }
+            }
+        }
+        // through the first sphere with e^-1, then stopped in the second with 1 - e^-1
+        let expected = f64::exp(-1.0) * (1.0 - f64::exp(-1.0));
+        let fraction = in_second as f64 / n as f64;
+        assert!(
+            (fraction - expected).abs() < 0.03,
+            "{} vs {}",
+            fraction,
+            expected
+        );
+    }
+
+    #[test]
+    fn gravity_spheres_share_trajectory_tables() {
+        let grey: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        let dropped_from = |x: f64, height: f64| {
+            GravitySphere::new(Point3::new(x, height, 0.0), 0.0, 0.2, grey.clone())
+        };
+        let a = dropped_from(0.0, 3.1);
+        let b = dropped_from(5.0, 3.1);
+        let higher = dropped_from(0.0, 3.37);
+        assert!(Arc::ptr_eq(&a.stored, &b.stored));
+        assert!(Arc::ptr_eq(&a.stored, &higher.stored));
+        // the three spheres and the table itself; other tests may hold more
+        assert!(Arc::strong_count(&a.stored) >= 4);
+
+        assert_eq!(a.get_center(0.0), Point3::new(0.0, 3.1, 0.0));
+        // free fall under a gravity of 1 until the first bounce
+        for (sphere, height) in [(&a, 3.1), (&higher, 3.37)] {
+            for t in [0.5, 1.0, 2.0] {
+                let fallen = height - t * t / 2.0;
+                assert!((sphere.get_center(t).get_y() - fallen).abs() < 1e-2);
+            }
+        }
+        assert_eq!(
+            b.get_center(0.5),
+            Point3::new(5.0, a.get_center(0.5).get_y(), 0.0)
+        );
+        assert!(higher.get_center(0.5).get_y() > a.get_center(0.5).get_y());
+        // both land, at times in proportion to the square root of their drops
+        let landing = |height: f64| f64::sqrt(2.0 * (height - 0.2));
+        assert!((a.get_center(landing(3.1)).get_y() - 0.2).abs() < 1e-2);
+        assert!((higher.get_center(landing(3.37)).get_y() - 0.2).abs() < 1e-2);
+        // and long after, rest on the floor
+        assert!((a.get_center(99.0).get_y() - 0.2).abs() < 1e-2);
+    }
+
+    #[test]
+    fn into_world_hits_like_a_manual_bvh() {
+        crate::mutil::seed(6);
+        let grey: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        let spheres = || {
+            let mut list = HittableList::new();
+            for (k, x) in [-3.0, -1.0, 0.5, 2.0, 4.0].into_iter().enumerate() {
+                list.add(Arc::new(Box::new(Sphere::new(
+                    Point3::new(x, k as f64 * 0.3, -(k as f64)),
+                    0.6,
+                    grey.clone(),
+                ))));
+            }
+            list
+        };
+        let manual: Box<dyn Hittable + Sync> =
+            Box::new(crate::bvh::BvhNode::from_list(&spheres(), 0.0, 1.0));
+        let world = spheres().into_world(0.0, 1.0);
+        assert_eq!(world.leaf_count(), manual.leaf_count());
+        let (a, b) = (
+            world.bounding_box(0.0, 1.0).unwrap(),
+            manual.bounding_box(0.0, 1.0).unwrap(),
+        );
+        assert_eq!((a.get_min(), a.get_max()), (b.get_min(), b.get_max()));
+        for i in -10..=10 {
+            for j in -4..=4 {
+                let r = Ray::new(
+                    &Point3::new(0, 0, 5),
+                    &Vec3::new(i as f64 * 0.1, j as f64 * 0.1, -1.0),
+                    0.0,
+                );
+                let (a, b) = (
+                    world.hit(&r, 0.001, f64::INFINITY),
+                    manual.hit(&r, 0.001, f64::INFINITY),
+                );
+                assert_eq!(a.is_some(), b.is_some());
+                if let (Some(a), Some(b)) = (a, b) {
+                    assert_eq!((a.get_t(), a.get_p()), (b.get_t(), b.get_p()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rays_at_a_shared_edge_never_slip_through() {
+        crate::mutil::seed(12);
+        let grey: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        // a slightly folded quad, with the fold along a diagonal of awkward coordinates
+        let (a, b) = (Point3::new(-0.3, 0.1, 0.7), Point3::new(1.9, 0.2, -1.3));
+        let (c, d) = (Point3::new(1.7, 0.35, 1.1), Point3::new(-0.1, -0.05, -1.9));
+        let normals = [(b - a).cross(&(c - a)), (a - b).cross(&(d - b))];
+        let mut pair = HittableList::new();
+        for (v0, v1, v2) in [(a, b, c), (b, a, d)] {
+            pair.add(Arc::new(Box::new(Triangle::new(v0, v1, v2, grey.clone()))));
+        }
+        let mut rng = crate::mutil::rng();
+        let mut fired = 0;
+        while fired < 20000 {
+            let target = a + rng.gen::<f64>() * (b - a);
+            let origin = target + (rng.gen::<f64>() * 10.0 + 0.5) * random_unit_vector();
+            let direction = target - origin;
+            // seen from here the fold is a silhouette, which a ray may graze past
+            if normals[0].dot(&direction) * normals[1].dot(&direction) <= 0.0 {
+                continue;
+            }
+            fired += 1;
+            let r = Ray::new(&origin, &direction, 0.0);
+            let rec = pair.hit(&r, 0.001, f64::INFINITY);
+            assert!(rec.is_some(), "slipped through at {}", target);
+            assert!((rec.unwrap().get_t() - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn cylinder_sides_caps_and_uv() {
+        let grey: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        let open = Cylinder::new(Point3::new(1, -1, 0), 0.5, 2.0, grey.clone());
+        let solid = Cylinder::new(Point3::new(1, -1, 0), 0.5, 2.0, grey).with_caps();
+
+        // side-on at mid height, from -z
+        let side = Ray::new(&Point3::new(1, 0, -5), &Vec3::new(0, 0, 1), 0.0);
+        for cylinder in [&open, &solid] {
+            let rec = cylinder.hit(&side, 0.001, f64::INFINITY).unwrap();
+            assert!((rec.get_t() - 4.5).abs() < 1e-9);
+            crate::assert_vec3_eq!(*rec.get_normal(), Vec3::new(0, 0, -1));
+            assert!((rec.get_u() - 0.75).abs() < 1e-9 && (rec.get_v() - 0.5).abs() < 1e-9);
+        }
+        // above the top, and beside it
+        for r in [
+            Ray::new(&Point3::new(1, 1.5, -5), &Vec3::new(0, 0, 1), 0.0),
+            Ray::new(&Point3::new(2, 0, -5), &Vec3::new(0, 0, 1), 0.0),
+        ] {
+            assert!(open.hit(&r, 0.001, f64::INFINITY).is_none());
+            assert!(solid.hit(&r, 0.001, f64::INFINITY).is_none());
+        }
+
+        // straight down the axis: through the open ends, onto the top cap of the solid one
+        let down = Ray::new(&Point3::new(1.1, 5, 0.1), &Vec3::new(0, -1, 0), 0.0);
+        assert!(open.hit(&down, 0.001, f64::INFINITY).is_none());
+        let rec = solid.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.get_t() - 4.0).abs() < 1e-9);
+        crate::assert_vec3_eq!(*rec.get_normal(), Vec3::new(0, 1, 0));
+        assert_eq!(rec.get_v(), 1.0);
+        // into the open top and out through the far wall, seen from inside
+        let slanted = Ray::new(&Point3::new(1, 2, 0), &Vec3::new(0.2, -1, 0), 0.0);
+        let rec = open.hit(&slanted, 0.001, f64::INFINITY).unwrap();
+        assert!(!rec.get_front_face());
+        assert!(rec.get_p().get_y() < 1.0);
+
+        let bbox = solid.bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(*bbox.get_min(), Point3::new(0.5, -1, -0.5));
+        assert_eq!(*bbox.get_max(), Point3::new(1.5, 1, 0.5));
+    }
+
+    #[test]
+    #[should_panic(expected = "positive height")]
+    fn cylinder_rejects_a_flat_height() {
+        let grey: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        Cylinder::new(Point3::new(0, 0, 0), 0.5, 0.0, grey);
+    }
+
+    #[test]
+    fn rotations_move_hits_and_bounds_about_each_axis() {
+        let ball = |center: Point3| -> Arc<Box<dyn Hittable + Send + Sync>> {
+            Arc::new(Box::new(Sphere::new(
+                center,
+                0.5,
+                Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+            )))
+        };
+        // a quarter turn carries each ball onto the next axis
+        let cases: [(Box<dyn Hittable>, Point3); 2] = [
+            (
+                Box::new(RotateX::new(90.0, ball(Point3::new(0, 1, 0)))),
+                Point3::new(0, 0, 1),
+            ),
+            (
+                Box::new(RotateZ::new(90.0, ball(Point3::new(1, 0, 0)))),
+                Point3::new(0, 1, 0),
+            ),
+        ];
+        for (rotated, center) in cases {
+            let bbox = rotated.bounding_box(0.0, 1.0).unwrap();
+            crate::assert_vec3_eq!(*bbox.get_min(), center - Vec3::splat(0.5));
+            crate::assert_vec3_eq!(*bbox.get_max(), center + Vec3::splat(0.5));
+
+            let from = center * 5.0;
+            let r = Ray::new(&from, &(center - from), 0.0);
+            let rec = rotated.hit(&r, 0.001, f64::INFINITY).unwrap();
+            crate::assert_vec3_eq!(*rec.get_p(), center * 1.5);
+            crate::assert_vec3_eq!(*rec.get_normal(), center);
+            assert!(rec.get_front_face());
+        }
+        // RotateY used to keep its object's unrotated box
+        let yawed = RotateY::new(90.0, ball(Point3::new(0, 0, 1)));
+        let bbox = yawed.bounding_box(0.0, 1.0).unwrap();
+        crate::assert_vec3_eq!(*bbox.get_min(), Point3::new(0.5, -0.5, -0.5));
+        crate::assert_vec3_eq!(*bbox.get_max(), Point3::new(1.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn rotate_y_faces_its_normals_against_the_world_ray() {
+        // the rotated normal was compared with the ray in object space, so a ray that the
+        // turn had swung away from the normal saw the front of the ball as its back
+        let ball: Arc<Box<dyn Hittable + Send + Sync>> = Arc::new(Box::new(Sphere::new(
+            Point3::new(0, 0, 1),
+            0.5,
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+        )));
+        let yawed = RotateY::new(90.0, ball);
+        let r = Ray::new(&Point3::new(5, 0, 0), &Vec3::new(-1, 0, 0), 0.0);
+        let rec = yawed.hit(&r, 0.001, f64::INFINITY).unwrap();
+        crate::assert_vec3_eq!(*rec.get_p(), Point3::new(1.5, 0, 0));
+        crate::assert_vec3_eq!(*rec.get_normal(), Vec3::new(1, 0, 0));
+        assert!(rec.get_front_face());
+    }
+
+    #[test]
+    fn csg_difference_leaves_a_cavity() {
+        let ball = |center: Point3, radius: f64| -> Arc<Box<dyn Hittable + Send + Sync>> {
+            Arc::new(Box::new(Sphere::new(
+                center,
+                radius,
+                Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+            )))
+        };
+        let big = ball(Point3::new(0, 0, 0), 1.0);
+        let bite = ball(Point3::new(0, 0, 1), 0.5);
+        let bitten = Csg::new(CsgOp::Difference, big.clone(), bite.clone());
+
+        // a ray into the bite skips the missing cap and stops on the floor of the cavity
+        let r = Ray::new(&Point3::new(0, 0, 5), &Vec3::new(0, 0, -1), 0.0);
+        let rec = bitten.hit(&r, 0.001, f64::INFINITY).unwrap();
+        crate::assert_vec3_eq!(*rec.get_p(), Point3::new(0, 0, 0.5));
+        crate::assert_vec3_eq!(*rec.get_normal(), Vec3::new(0, 0, 1));
+        assert!(rec.get_front_face());
+        // a ray across the rim only passes through the hole
+        let across = Ray::new(&Point3::new(-5, 0, 0.95), &Vec3::new(1, 0, 0), 0.0);
+        assert!(big.hit(&across, 0.001, f64::INFINITY).is_some());
+        assert!(bitten.hit(&across, 0.001, f64::INFINITY).is_none());
+        // away from the bite it's the big sphere as before
+        let side = Ray::new(&Point3::new(5, 0, 0), &Vec3::new(-1, 0, 0), 0.0);
+        let rec = bitten.hit(&side, 0.001, f64::INFINITY).unwrap();
+        crate::assert_vec3_eq!(*rec.get_p(), Point3::new(1, 0, 0));
+
+        // the lens where they overlap starts at the big sphere's surface
+        let lens = Csg::new(CsgOp::Intersection, big.clone(), bite.clone());
+        let rec = lens.hit(&r, 0.001, f64::INFINITY).unwrap();
+        crate::assert_vec3_eq!(*rec.get_p(), Point3::new(0, 0, 1));
+        let union = Csg::new(CsgOp::Union, big, bite);
+        let rec = union.hit(&r, 0.001, f64::INFINITY).unwrap();
+        crate::assert_vec3_eq!(*rec.get_p(), Point3::new(0, 0, 1.5));
+        assert!(union.hit(&across, 0.001, f64::INFINITY).is_some());
+    }
+
+    #[test]
+    fn scale_stretches_hits_normals_bounds_and_light_pdfs() {
+        let gray = || -> Arc<Box<dyn Material>> {
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))))
+        };
+        let ball: Arc<Box<dyn Hittable + Send + Sync>> =
+            Arc::new(Box::new(Sphere::new(Point3::new(0, 0, 0), 1.0, gray())));
+        // x^2 / 4 + y^2 + 4 z^2 = 1
+        let ellipsoid = Scale::new(&Vec3::new(2, 1, 0.5), ball.clone());
+        let r = Ray::new(&Point3::new(5, 0, 0), &Vec3::new(-1, 0, 0), 0.0);
+        let rec = ellipsoid.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.get_t() - 3.0).abs() < 1e-9);
+        crate::assert_vec3_eq!(*rec.get_p(), Point3::new(2, 0, 0));
+        crate::assert_vec3_eq!(*rec.get_normal(), Vec3::new(1, 0, 0));
+        // off the axes the normal follows the gradient (x / 4, y, 4 z), not the sphere's
+        let y = f64::sqrt(0.75);
+        let r = Ray::new(&Point3::new(1, 5, 0), &Vec3::new(0, -1, 0), 0.0);
+        let rec = ellipsoid.hit(&r, 0.001, f64::INFINITY).unwrap();
+        crate::assert_vec3_eq!(*rec.get_p(), Point3::new(1, y, 0));
+        crate::assert_vec3_eq!(*rec.get_normal(), Vec3::new(0.25, y, 0).unit());
+        let bbox = ellipsoid.bounding_box(0.0, 1.0).unwrap();
+        crate::assert_vec3_eq!(*bbox.get_min(), Vec3::new(-2, -1, -0.5));
+        crate::assert_vec3_eq!(*bbox.get_max(), Vec3::new(2, 1, 0.5));
+
+        // mirrored, an off-center ball lands on the other side, still facing out
+        let off_center: Arc<Box<dyn Hittable + Send + Sync>> =
+            Arc::new(Box::new(Sphere::new(Point3::new(1, 0, 0), 0.5, gray())));
+        let mirrored = Scale::new(&Vec3::new(-1, 1, 1), off_center);
+        let r = Ray::new(&Point3::new(-5, 0, 0), &Vec3::new(1, 0, 0), 0.0);
+        let rec = mirrored.hit(&r, 0.001, f64::INFINITY).unwrap();
+        crate::assert_vec3_eq!(*rec.get_p(), Point3::new(-1.5, 0, 0));
+        crate::assert_vec3_eq!(*rec.get_normal(), Vec3::new(-1, 0, 0));
+        assert!(rec.get_front_face());
+        let bbox = mirrored.bounding_box(0.0, 1.0).unwrap();
+        crate::assert_vec3_eq!(*bbox.get_min(), Vec3::new(-1.5, -0.5, -0.5));
+
+        // a scaled light samples like one built at the scaled size
+        let corners = [
+            Point3::new(0, 0, 0),
+            Point3::new(1, 0, 0),
+            Point3::new(0, 1, 0.5),
+        ];
+        let stretch = Vec3::new(2, -0.5, 3);
+        let small: Arc<Box<dyn Hittable + Send + Sync>> = Arc::new(Box::new(Triangle::new(
+            corners[0],
+            corners[1],
+            corners[2],
+            gray(),
+        )));
+        let scaled = Scale::new(&stretch, small);
+        let built = Triangle::new(
+            corners[0] * stretch,
+            corners[1] * stretch,
+            corners[2] * stretch,
+            gray(),
+        );
+        let origin = Point3::new(0.5, 2, -4);
+        crate::mutil::seed(8);
+        for _ in 0..20 {
+            let direction = scaled.random_direction(&origin).unwrap();
+            assert!(scaled
+                .hit(&Ray::new(&origin, &direction, 0.0), 0.001, 1.0 + 1e-9)
+                .is_some());
+            let expected = built.pdf_value(&origin, &direction);
+            assert!(expected > 0.0);
+            assert!((scaled.pdf_value(&origin, &direction) - expected).abs() < 1e-9 * expected);
+        }
+    }
+
+    #[test]
+    fn random_points_cover_triangle_uniformly() {
+        crate::mutil::seed(3);
+        let tri = Triangle::new(
+            Point3::new(0, 0, 0),
+            Point3::new(4, 0, 0),
+            Point3::new(0, 2, 0),
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+        );
+        assert_eq!(tri.area(), 4.0);
+
+        let n = 20000;
+        let mut sum = Vec3::new(0, 0, 0);
+        for _ in 0..n {
+            let p = tri.random_point();
+            assert!(p.get_x() >= 0.0 && p.get_y() >= 0.0 && p.get_z() == 0.0);
+            assert!(p.get_x() / 4.0 + p.get_y() / 2.0 <= 1.0 + 1e-12);
+            sum += p;
+        }
+        let centroid = Vec3::new(4.0 / 3.0, 2.0 / 3.0, 0);
+        assert!((sum / n as f64 - centroid).length() < 0.02);
+    }
+
+    #[test]
+    fn triangle_barycentric() {
+        let rec = unit_triangle().hit(&down_at(0.25, 0.5), 0.0, 10.0).unwrap();
+        let b = rec.get_barycentric().unwrap();
+        assert!(f64::abs(b.get_x() - 0.25) < 1e-9);
+        assert!(f64::abs(b.get_y() - 0.25) < 1e-9);
+        assert!(f64::abs(b.get_z() - 0.5) < 1e-9);
+        assert!(f64::abs(rec.get_u() - 0.25) < 1e-9);
+        assert!(f64::abs(rec.get_v() - 0.5) < 1e-9);
+    }
+
+    #[test]
+    fn edge_distance_near_vertex_and_centroid() {
+        let tri = unit_triangle();
+        let near_vertex = tri.hit(&down_at(0.01, 0.01), 0.0, 10.0).unwrap();
+        let centroid = tri.hit(&down_at(1.0 / 3.0, 1.0 / 3.0), 0.0, 10.0).unwrap();
+        assert!(near_vertex.edge_distance().unwrap() < 0.05);
+        assert!(centroid.edge_distance().unwrap() > 0.3);
+    }
+
+    fn mirror_hit(p: Point3, material: Arc<Box<dyn Material>>) -> HitRecord {
+        HitRecord::new(p, Vec3::new(0, 1, 0), 1.0, 0.0, 0.0, true, material)
+    }
+
+    // average angle between the scattered rays and the perfect reflection
+    fn scatter_spread(material: &Arc<Box<dyn Material>>, rec: &HitRecord) -> f64 {
+        let r_in = Ray::new(
+            &(*rec.get_p() + Vec3::new(-1, 1, 0)),
+            &Vec3::new(1, -1, 0),
+            0.0,
+        );
+        let ideal = r_in.get_direction().unit().reflect(rec.get_normal());
+        let mut total = 0.0;
+        let mut n = 0;
+        for _ in 0..2000 {
+            if let Some((scattered, _)) = material.scatter(&r_in, rec) {
+                total += f64::acos(clamp(
+                    scattered.get_direction().unit().dot(&ideal),
+                    -1.0,
+                    1.0,
+                ));
+                n += 1;
+            }
+        }
+        total / n as f64
+    }
+
+    #[test]
+    fn roughness_texture_modulates_fuzz() {
+        let roughness: Arc<Box<dyn Texture>> = Arc::new(Box::new(Checker::from_colors(
+            &Color::new(0, 0, 0),
+            &Color::new(1, 1, 1),
+        )));
+        let metal: Arc<Box<dyn Material>> = Arc::new(Box::new(Metal::from_roughness(
+            Color::new(0.9, 0.9, 0.9),
+            roughness,
+        )));
+        // sin(10x)sin(10y)sin(10z) is positive (even square) at the first point, negative at the second
+        let even = mirror_hit(Point3::new(0.1, 0.1, 0.1), metal.clone());
+        let odd = mirror_hit(Point3::new(-0.1, 0.1, 0.1), metal.clone());
+
+        let even_spread = scatter_spread(&metal, &even);
+        let odd_spread = scatter_spread(&metal, &odd);
+        assert!(even_spread < 1e-6);
+        assert!(odd_spread > 0.1);
+    }
+
+    #[test]
+    fn ray_differentials_widen_the_filtered_checker_lookup() {
+        let (white, black) = (Color::new(1, 1, 1), Color::new(0, 0, 0));
+        let checker = Checker::from_colors(&white, &black).with_filtering(0.001);
+        let material: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::from_pointer(
+            Arc::new(Box::new(checker)),
+        )));
+        let floor = XzRect::new(-1.0, 1.0, -1.0, 1.0, 0.1, material.clone());
+        // ten units up, a 0.001 radian pixel is far smaller than a square
+        let plain = Ray::new(&Point3::new(0.1, 10.1, 0.1), &Vec3::new(0, -1, 0), 0.0);
+        // but these neighbours land half a unit away, wider than a whole square
+        let spread = crate::ray::RayDifferential::new(
+            Vec3::zero(),
+            Vec3::new(0.05, 0, 0),
+            Vec3::zero(),
+            Vec3::new(0, 0, 0.05),
+        );
+        let wide = plain.with_differential(Some(spread));
+        let albedo = |r: &Ray| {
+            let rec = floor.hit(r, 0.001, f64::INFINITY).unwrap();
+            material.scatter(r, &rec).unwrap().1
+        };
+        let sharp = albedo(&plain);
+        assert!(sharp == white || sharp == black);
+        assert_eq!(albedo(&wide), Color::splat(0.5));
+    }
+
+    #[test]
+    fn back_lit_sheets_scatter_toward_the_ray() {
+        // thin sheets lit from behind: the hit normal already faces the incoming ray, so a
+        // plain Lambertian scatters back to the side the light came from
+        let gray = Color::new(0.5, 0.5, 0.5);
+        let material: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(gray)));
+        let sheets: [Box<dyn Hittable>; 2] = [
+            Box::new(XyRect::new(-1.0, 1.0, -1.0, 1.0, 0.0, material.clone())),
+            Box::new(Triangle::new(
+                Point3::new(-1, -1, 0),
+                Point3::new(1, -1, 0),
+                Point3::new(0, 1, 0),
+                material.clone(),
+            )),
+        ];
+        for sheet in &sheets {
+            for z in [-1.0, 1.0] {
+                let r_in = Ray::new(&Point3::new(0.0, 0.0, z), &Vec3::new(0.0, 0.0, -z), 0.0);
+                let rec = sheet.hit(&r_in, 0.001, f64::INFINITY).unwrap();
+                assert!(rec.get_normal().dot(r_in.get_direction()) < 0.0);
+                for _ in 0..200 {
+                    let (scattered, attenuation) = material.scatter(&r_in, &rec).unwrap();
+                    assert!(scattered.get_direction().get_z() * z >= 0.0);
+                    assert_eq!(attenuation, gray);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn moving_triangle_follows_ray_time() {
+        let pose0 = [
+            Point3::new(0, 0, 0),
+            Point3::new(1, 0, 0),
+            Point3::new(0, 1, 0),
+        ];
+        let pose1 = pose0.map(|v| v + Vec3::new(0, 0, -1));
+        let tri = MovingTriangle::new(
+            pose0,
+            pose1,
+            0.0,
+            1.0,
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+        );
+        for time in [0.0, 0.25, 1.0] {
+            let r = Ray::new(&Point3::new(0.2, 0.2, 1), &Vec3::new(0, 0, -1), time);
+            let rec = tri.hit(&r, 0.0, 10.0).unwrap();
+            assert!(f64::abs(rec.get_p().get_z() + time) < 1e-9);
+        }
+
+        let bbox = tri.bounding_box(0.0, 1.0).unwrap();
+        assert!(bbox.get_min().get_z() <= -1.0 && bbox.get_max().get_z() >= 0.0);
+    }
+
+    // interfaces met by a ray along +x; head-on rays don't bend, so every hit is crossed
+    fn interfaces_along_x(world: &HittableList) -> Vec<Interface> {
+        let mut media = MediumStack::new();
+        let mut r = Ray::new(&Point3::new(-5, 0, 0), &Vec3::new(1, 0, 0), 0.0);
+        let mut seen = vec![];
+        while let Some(rec) = world.hit(&r, 0.001, f64::INFINITY) {
+            let material = rec.get_material();
+            let medium = material.medium().unwrap();
+            let id = Arc::as_ptr(&material) as *const () as usize;
+            seen.push(media.interface(id, medium, rec.get_front_face()));
+            media.cross(id, medium, rec.get_front_face());
+            r = Ray::new(rec.get_p(), r.get_direction(), 0.0);
+        }
+        seen
+    }
+
+    fn overlapping_glass(glass: Dielectric, water: Dielectric) -> HittableList {
+        let mut list = HittableList::new();
+        list.add(Arc::new(Box::new(Sphere::new(
+            Point3::new(0, 0, 0),
+            1.0,
+            Arc::new(Box::new(glass)),
+        ))));
+        list.add(Arc::new(Box::new(Sphere::new(
+            Point3::new(1, 0, 0),
+            1.0,
+            Arc::new(Box::new(water)),
+        ))));
+        list
+    }
+
+    #[test]
+    fn nested_dielectrics_use_both_iors() {
+        // equal priority: the most recently entered medium fills the overlap
+        let world = overlapping_glass(Dielectric::new(1.5), Dielectric::new(1.33));
+        assert_eq!(
+            interfaces_along_x(&world),
+            vec![
+                Interface::Refract(1.0 / 1.5),
+                Interface::Refract(1.5 / 1.33),
+                Interface::Pass,
+                Interface::Refract(1.33),
+            ]
+        );
+
+        // higher-priority glass fills the overlap, so the water's near surface is skipped
+        let world = overlapping_glass(
+            Dielectric::new(1.5).with_priority(2),
+            Dielectric::new(1.33).with_priority(1),
+        );
+        assert_eq!(
+            interfaces_along_x(&world),
+            vec![
+                Interface::Refract(1.0 / 1.5),
+                Interface::Pass,
+                Interface::Refract(1.5 / 1.33),
+                Interface::Refract(1.33),
+            ]
+        );
+    }
+
+    #[test]
+    fn coat_reflects_more_at_grazing_angles() {
+        crate::mutil::seed(9);
+        let base: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        let floor = XzRect::new(
+            -10.0,
+            10.0,
+            -10.0,
+            10.0,
+            0.0,
+            Arc::new(Box::new(Coated::new(base, 1.5))),
+        );
+        let mirrored = |direction: Vec3| {
+            let r = Ray::new(&(-direction), &direction, 0.0);
+            let rec = floor.hit(&r, 0.001, f64::INFINITY).unwrap();
+            let reflected = direction.unit().reflect(rec.get_normal());
+            (0..2000)
+                .filter(|_| {
+                    let (scattered, _) = rec.get_material().scatter(&r, &rec).unwrap();
+                    (scattered.get_direction().unit() - reflected).length() < 1e-9
+                })
+                .count()
+        };
+        let head_on = mirrored(Vec3::new(0, -1, 0));
+        let grazing = mirrored(Vec3::new(1, -0.1, 0));
+        // about 4% of the light at normal incidence, most of it near grazing
+        assert!(head_on < 150, "{}", head_on);
+        assert!(grazing > 3 * head_on, "{} vs {}", grazing, head_on);
+    }
+
+    #[test]
+    fn fresnel_metal_brightens_at_grazing_angles() {
+        let gold = Color::new(1.0, 0.78, 0.34);
+        let reflectance = |metal: &Metal, direction: Vec3| {
+            let floor = XzRect::new(
+                -10.0,
+                10.0,
+                -10.0,
+                10.0,
+                0.0,
+                Arc::new(Box::new(Metal::new(gold, 0.0))),
+            );
+            let r = Ray::new(&(-direction), &direction, 0.0);
+            let rec = floor.hit(&r, 0.001, f64::INFINITY).unwrap();
+            metal.scatter(&r, &rec).unwrap().1
+        };
+        let head_on = Vec3::new(0, -1, 0);
+        let grazing = Vec3::new(1, -0.05, 0);
+
+        let plain = Metal::new(gold, 0.0);
+        assert_eq!(reflectance(&plain, head_on), gold);
+        assert_eq!(reflectance(&plain, grazing), gold);
+
+        let fresnel = Metal::new(gold, 0.0).with_fresnel();
+        crate::assert_vec3_eq!(reflectance(&fresnel, head_on), gold);
+        let edge = reflectance(&fresnel, grazing);
+        assert!(edge.get_z() > gold.get_z() + 0.3, "{}", edge);
+        assert!(
+            edge.get_y() > gold.get_y() && edge.get_x() <= 1.0,
+            "{}",
+            edge
+        );
+    }
+
+    #[test]
+    fn grazing_clamp_keeps_rough_metal_energy() {
+        crate::mutil::seed(4);
+        let reflected_energy = |metal: Metal| {
+            let floor = XzRect::new(-10.0, 10.0, -10.0, 10.0, 0.0, Arc::new(Box::new(metal)));
+            let direction = Vec3::new(1, -0.05, 0);
+            let r = Ray::new(&(-direction), &direction, 0.0);
+            let rec = floor.hit(&r, 0.001, f64::INFINITY).unwrap();
+            (0..4000)
+                .filter_map(|_| rec.get_material().scatter(&r, &rec))
+                .map(|(scattered, attenuation)| {
+                    assert!(scattered.get_direction().dot(rec.get_normal()) > 0.0);
+                    attenuation.get_x()
+                })
+                .sum::<f64>()
+                / 4000.0
+        };
+        let white = Color::splat(1);
+        let dropped = reflected_energy(Metal::new(white, 1.0));
+        let clamped = reflected_energy(Metal::new(white, 1.0).with_grazing_clamp());
+        // at grazing incidence nearly half the fuzzed directions point into the floor
+        assert!(dropped < 0.7, "{}", dropped);
+        assert!(clamped > 0.99, "{}", clamped);
+        // a smooth mirror never needs the clamp
+        assert_eq!(reflected_energy(Metal::new(white, 0.0)), 1.0);
+    }
+
+    #[test]
+    fn axis_aligned_triangle_gets_a_thick_box() {
+        let material: Arc<Box<dyn Material>> =
+            Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        // flat in y, as in terrain, and flat in x
+        for (v1, v2, flat) in [
+            (Point3::new(1, 0, 0), Point3::new(0, 0, 1), 1),
+            (Point3::new(0, 1, 0), Point3::new(0, 0, 1), 0),
+        ] {
+            let triangle = Triangle::new(Point3::new(0, 0, 0), v1, v2, material.clone());
+            let bbox = triangle.bounding_box(0.0, 1.0).unwrap();
+            let extent = *bbox.get_max() - *bbox.get_min();
+            assert!(extent.iter().all(|e| e > 0.0), "{}", extent);
+            assert!(extent.iter().nth(flat).unwrap() < 0.001);
+
+            // straight down onto the flat side, where a zero-width slab would miss
+            let mut normal = Vec3::zero();
+            match flat {
+                0 => normal.set_x(1.0),
+                _ => normal.set_y(1.0),
+            }
+            let inside = 0.25 * (v1 + v2);
+            let r = Ray::new(&(inside + normal), &(-normal), 0.0);
+            assert!(bbox.hit(&r, 0.001, f64::INFINITY));
+            let rec = triangle.hit(&r, 0.001, f64::INFINITY).unwrap();
+            crate::assert_vec3_eq!(*rec.get_p(), inside);
+        }
+    }
+
+    #[test]
+    fn spot_light_fades_between_cones() {
+        let c = Color::splat(4);
+        let spot = SpotLight::new(&c, Vec3::new(0, -1, 0), 20.0, 40.0);
+        let p = Point3::new(0, 0, 0);
+        let at = |degrees: f64| {
+            let angle = degrees.to_radians();
+            spot.emitted_toward(0.0, 0.0, &p, &Vec3::new(angle.sin(), -angle.cos(), 0))
+        };
+        assert_eq!(at(0.0), c);
+        assert_eq!(at(19.0), c);
+        let halfway = at(30.0).get_x();
+        assert!(halfway > 0.0 && halfway < 4.0, "{}", halfway);
+        assert!(at(35.0).get_x() < halfway);
+        assert_eq!(at(41.0), Color::zero());
+        assert_eq!(at(120.0), Color::zero());
+        // the direction needn't be normalized
+        assert_eq!(spot.emitted_toward(0.0, 0.0, &p, &Vec3::new(0, -5, 0)), c);
+    }
+
+    #[test]
+    fn image_light_glows_with_scaled_texels() {
+        // red on the left, blue on the right
+        let path = std::env::temp_dir().join("image_light.ppm");
+        std::fs::write(&path, "P3\n2 1\n255\n255 0 0 0 0 255\n").unwrap();
+        let path = path.to_str().unwrap();
+        let light = DiffuseLight::from_image(path, 8.0).unwrap();
+        let screen = XyRect::new(0.0, 2.0, 0.0, 1.0, 0.0, Arc::new(Box::new(light)));
+        let emitted_at = |x: f64| {
+            let r = Ray::new(&Point3::new(x, 0.5, 1), &Vec3::new(0, 0, -1), 0.0);
+            let rec = screen.hit(&r, 0.001, f64::INFINITY).unwrap();
+            rec.get_material()
+                .emitted(rec.get_u(), rec.get_v(), rec.get_p())
+        };
+        assert_eq!(emitted_at(0.5), Color::new(8, 0, 0));
+        assert_eq!(emitted_at(1.5), Color::new(0, 0, 8));
+        assert!(screen.is_emissive());
+        assert!(matches!(
+            DiffuseLight::from_image("no_such_light.ppm", 1.0),
+            Err(ImageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn hit_into_matches_hit() {
+        crate::mutil::seed(8);
+        let gray: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        let red: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::new(1, 0, 0))));
+        let mut list = HittableList::new();
+        list.add(Arc::new(Box::new(Sphere::new(
+            Point3::new(0, 0, 0),
+            1.0,
+            gray.clone(),
+        ))));
+        list.add(Arc::new(Box::new(Triangle::new(
+            Point3::new(-2, -2, 0.5),
+            Point3::new(2, -2, 0.5),
+            Point3::new(0, 2, 0.5),
+            red,
+        ))));
+        list.add(Arc::new(Box::new(XyRect::new(
+            -3.0,
+            3.0,
+            -3.0,
+            3.0,
+            -2.0,
+            gray.clone(),
+        ))));
+        list.add(Arc::new(Box::new(
+            Cylinder::new(Point3::new(1, -1, -0.5), 0.5, 2.0, gray.clone()).with_caps(),
+        )));
+        let bvh = crate::bvh::BvhNode::from_list(&list, 0.0, 1.0);
+        let same = |a: &HitRecord, b: &HitRecord| {
+            a.p == b.p
+                && a.normal == b.normal
+                && a.t == b.t
+                && (a.u, a.v, a.front_face) == (b.u, b.v, b.front_face)
+                && Arc::ptr_eq(&a.mat_ptr, &b.mat_ptr)
+                && a.barycentric == b.barycentric
+        };
+        // one record reused across every object and ray, as a tight loop would
+        let mut out = HitRecord::blank(&gray);
+        let mut hits = 0;
+        for _ in 0..500 {
+            let origin = Point3::new(0, 0, 5) + random_in_unit_sphere();
+            let r = Ray::new(&origin, &(random_in_unit_sphere() - origin), 0.0);
+            for object in [&list as &dyn Hittable, &bvh] {
+                let before = out.t;
+                match object.hit(&r, 0.001, f64::INFINITY) {
+                    Some(rec) => {
+                        assert!(object.hit_into(&r, 0.001, f64::INFINITY, &mut out));
+                        assert!(same(&rec, &out));
+                        hits += 1;
+                    }
+                    None => {
+                        assert!(!object.hit_into(&r, 0.001, f64::INFINITY, &mut out));
+                        assert_eq!(out.t, before);
+                    }
+                }
+            }
+        }
+        assert!(hits > 500, "{}", hits);
+    }
 }
@@ -0,0 +1,84 @@
+/// A range of reals, usually ray parameters `t`. Empty when `max < min`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    min: f64,
+    max: f64,
+}
+
+impl Interval {
+    pub fn new(min: f64, max: f64) -> Interval {
+        Interval { min, max }
+    }
+
+    pub fn get_min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn get_max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn size(&self) -> f64 {
+        self.max - self.min
+    }
+
+    /// `x` lies in the interval, endpoints included.
+    pub fn contains(&self, x: f64) -> bool {
+        self.min <= x && x <= self.max
+    }
+
+    /// `x` lies strictly inside the interval.
+    pub fn surrounds(&self, x: f64) -> bool {
+        self.min < x && x < self.max
+    }
+
+    pub fn clamp(&self, x: f64) -> f64 {
+        f64::min(f64::max(x, self.min), self.max)
+    }
+
+    /// Widen by `delta` in total, half on each side.
+    pub fn expand(&self, delta: f64) -> Interval {
+        let padding = delta / 2.0;
+        Interval::new(self.min - padding, self.max + padding)
+    }
+
+    /// The overlap of the two intervals, which is empty if they don't meet.
+    pub fn intersect(&self, other: &Interval) -> Interval {
+        Interval::new(f64::max(self.min, other.min), f64::min(self.max, other.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_includes_endpoints_surrounds_does_not() {
+        let i = Interval::new(1.0, 2.0);
+        assert!(i.contains(1.0) && i.contains(2.0) && i.contains(1.5));
+        assert!(!i.surrounds(1.0) && !i.surrounds(2.0) && i.surrounds(1.5));
+        assert!(!i.contains(0.999) && !i.contains(f64::NAN));
+
+        let point = Interval::new(3.0, 3.0);
+        assert!(point.contains(3.0) && !point.surrounds(3.0));
+        let empty = Interval::new(1.0, 0.0);
+        assert!(!empty.contains(0.5) && !empty.surrounds(0.5));
+
+        let all = Interval::new(f64::NEG_INFINITY, f64::INFINITY);
+        assert!(all.surrounds(f64::MAX) && !all.surrounds(f64::INFINITY));
+    }
+
+    #[test]
+    fn clamp_expand_intersect() {
+        let i = Interval::new(-1.0, 1.0);
+        assert_eq!(i.clamp(5.0), 1.0);
+        assert_eq!(i.clamp(-5.0), -1.0);
+        assert_eq!(i.clamp(0.25), 0.25);
+        assert_eq!(i.expand(1.0), Interval::new(-1.5, 1.5));
+        assert_eq!(
+            i.intersect(&Interval::new(0.5, 3.0)),
+            Interval::new(0.5, 1.0)
+        );
+        assert!(i.intersect(&Interval::new(2.0, 3.0)).size() < 0.0);
+    }
+}
@@ -1,12 +1,23 @@
 pub mod aabb;
+pub mod archive;
+pub mod bench;
 pub mod bvh;
 pub mod camera;
+pub mod filter;
+pub mod gallery;
 pub mod hit;
+pub mod interval;
 pub mod model;
 pub mod mutil;
 pub mod perlin;
+pub mod post;
 pub mod ray;
+pub mod reference;
+pub mod sampler;
 pub mod screen;
+pub mod spectrum;
+pub mod stats;
 pub mod texture;
+pub mod tiles;
 pub mod vec3;
 pub mod world;
@@ -1,4 +1,5 @@
 use ray_tracing_series_rust::world::*;
+use ray_tracing_series_rust::{bench, gallery, reference};
 use std::time::Instant;
 
 const THREADS: usize = 11;
@@ -7,7 +8,52 @@ const SCENE_ID: usize = 11;
 fn main() {
     let start = Instant::now();
 
-    let (world, cam, background) = get_world_cam(SCENE_ID);
+    if std::env::args().any(|arg| arg == "--bench") {
+        let config = bench::config(THREADS);
+        if let Err(e) = bench::run(&mut std::io::stdout(), &bench::SCENES, &config) {
+            eprintln!("Benchmark failed: {:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(at) = args.iter().position(|arg| arg == "--diff") {
+        let (Some(reference), Some(candidate)) = (args.get(at + 1), args.get(at + 2)) else {
+            eprintln!("usage: --diff reference.ppm candidate.ppm [max_rmse]");
+            std::process::exit(2);
+        };
+        let threshold = args.get(at + 3).and_then(|t| t.parse().ok()).unwrap_or(1.0);
+        match reference::compare_files(reference, candidate, threshold) {
+            Ok(diff) => println!(
+                "rmse={:.4} max_error={:.1}",
+                diff.get_rmse(),
+                diff.get_max_error()
+            ),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(at) = args.iter().position(|arg| arg == "--gallery") {
+        let out_dir = args.get(at + 1).map_or("gallery", |dir| dir.as_str());
+        let config = Config::new(16.0 / 9.0, 320, 16, 10, THREADS);
+        match gallery::run(std::path::Path::new(out_dir), &config) {
+            Ok(written) => eprintln!("Wrote {} scenes to {}", written.len(), out_dir),
+            Err(e) => {
+                eprintln!("Gallery failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let (world, cam, background) = get_world_cam(SCENE_ID).unwrap_or_else(|e| {
+        eprintln!("Couldn't build scene {}: {}", SCENE_ID, e);
+        std::process::exit(1);
+    });
     let config = Config::new(1.6, 600, 1000, 50, THREADS);
 
     render_scene(world, cam, background, config);
@@ -1,77 +1,403 @@
-use crate::hit::{HittableList, Lambertian, Triangle};
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::hit::{HitRecord, Hittable, HittableList, Lambertian, Material, Triangle};
+use crate::ray::Ray;
+use crate::screen::{ImageError, Screen};
 use crate::vec3::{Color, Point3};
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 use std::sync::Arc;
 
 pub struct TriangleModel {
     vertices: Vec<Point3>,
     faces: Vec<(usize, usize, usize)>,
+    cull_backface: bool,
 }
 
 impl TriangleModel {
-    pub fn load_from_file(path: &str, scale: f64) -> TriangleModel {
-        // kind of hard coded
-        let mut file = File::open(path).expect("Couldn't open the file");
+    /// An ASCII PLY, or a Wavefront OBJ if `path` ends in `.obj`.
+    pub fn load_from_file(path: &str, scale: f64) -> Result<TriangleModel, ImageError> {
+        let mut file = File::open(path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => ImageError::NotFound(path.to_string()),
+            _ => ImageError::Io(e),
+        })?;
         let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Trouble reading file...");
-        let mut contents = contents.split("\n");
+        file.read_to_string(&mut contents).map_err(ImageError::Io)?;
+        if path.ends_with(".obj") {
+            return TriangleModel::from_obj(&contents, scale);
+        }
+        TriangleModel::from_ply(&contents, scale)
+    }
+
+    /// Vertices and triangles from ASCII PLY text, which must list `element vertex` and
+    /// `element face` counts in its header; faces are taken to be triangles.
+    pub fn from_ply(contents: &str, scale: f64) -> Result<TriangleModel, ImageError> {
+        // kind of hard coded
+        let mut lines = contents.lines().enumerate();
         let mut vertex_count = 0;
         let mut face_count = 0;
         loop {
-            let line = contents.next().unwrap();
-            if line == "end_header" {
+            let Some((n, line)) = lines.next() else {
+                return Err(ImageError::Truncated {
+                    expected: vertex_count + face_count,
+                    found: 0,
+                });
+            };
+            if line.trim() == "end_header" {
                 break;
             }
-            let line_contents: Vec<&str> = line.split(" ").collect();
-            if line_contents[0] == "element" {
-                if line_contents[1] == "vertex" {
-                    vertex_count = line_contents[2].parse::<i32>().unwrap();
-                }
-                if line_contents[1] == "face" {
-                    face_count = line_contents[2].parse::<i32>().unwrap();
+            let line_contents: Vec<&str> = line.split_whitespace().collect();
+            if let ["element", kind, count] = line_contents[..] {
+                match kind {
+                    "vertex" => vertex_count = parse_at(n, count)?,
+                    "face" => face_count = parse_at(n, count)?,
+                    _ => {}
                 }
             }
         }
+        // the elements each take one line; running out counts how many were read
+        let mut read = 0;
+        let mut next_element = || {
+            read += 1;
+            lines.next().ok_or(ImageError::Truncated {
+                expected: vertex_count + face_count,
+                found: read - 1,
+            })
+        };
         let mut vertices = vec![];
         let mut faces = vec![];
 
         for _ in 0..vertex_count {
-            let line = contents.next().unwrap();
-            let line_contents: Vec<&str> = line.split(" ").collect();
-            vertices.push(Point3::new(
-                line_contents[0].parse::<f64>().unwrap() * scale,
-                line_contents[1].parse::<f64>().unwrap() * scale,
-                line_contents[2].parse::<f64>().unwrap() * scale,
-            ))
+            let (n, line) = next_element()?;
+            let line_contents: Vec<&str> = line.split_whitespace().collect();
+            if line_contents.len() < 3 {
+                return Err(ImageError::Parse {
+                    line: n + 1,
+                    token: line.to_string(),
+                });
+            }
+            vertices.push(
+                scale
+                    * Point3::new(
+                        parse_at::<f64>(n, line_contents[0])?,
+                        parse_at::<f64>(n, line_contents[1])?,
+                        parse_at::<f64>(n, line_contents[2])?,
+                    ),
+            )
         }
 
         for _ in 0..face_count {
-            let line = contents.next().unwrap();
-            let line_contents: Vec<&str> = line.split(" ").collect();
-
+            let (n, line) = next_element()?;
+            let line_contents: Vec<&str> = line.split_whitespace().collect();
+            if line_contents.len() < 4 {
+                return Err(ImageError::Parse {
+                    line: n + 1,
+                    token: line.to_string(),
+                });
+            }
+            let corner = |token: &str| match parse_at::<usize>(n, token)? {
+                index if index < vertex_count => Ok(index),
+                _ => Err(ImageError::Parse {
+                    line: n + 1,
+                    token: token.to_string(),
+                }),
+            };
             faces.push((
-                line_contents[1].parse::<usize>().unwrap(),
-                line_contents[2].parse::<usize>().unwrap(),
-                line_contents[3].parse::<usize>().unwrap(),
+                corner(line_contents[1])?,
+                corner(line_contents[2])?,
+                corner(line_contents[3])?,
             ))
         }
 
-        TriangleModel { vertices, faces }
+        Ok(TriangleModel {
+            vertices,
+            faces,
+            cull_backface: false,
+        })
+    }
+
+    /// Vertices (`v`) and faces (`f`) from Wavefront OBJ text; everything else is skipped.
+    /// Face indices count from 1, or back from the latest vertex when negative, and may
+    /// carry `/vt/vn` parts, which are ignored. Polygons are split into a fan of triangles,
+    /// and faces with fewer than three corners are dropped. Indices must name a vertex listed
+    /// before them.
+    pub fn from_obj(contents: &str, scale: f64) -> Result<TriangleModel, ImageError> {
+        let mut vertices = vec![];
+        let mut faces = vec![];
+        for (n, line) in contents.lines().enumerate() {
+            let parse_error = |token: &str| ImageError::Parse {
+                line: n + 1,
+                token: token.to_string(),
+            };
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let coords = tokens
+                        .take(3)
+                        .map(|t| t.parse::<f64>().map_err(|_| parse_error(t)))
+                        .collect::<Result<Vec<f64>, ImageError>>()?;
+                    if coords.len() < 3 {
+                        return Err(ImageError::Truncated {
+                            expected: 3,
+                            found: coords.len(),
+                        });
+                    }
+                    vertices.push(scale * Point3::new(coords[0], coords[1], coords[2]));
+                }
+                Some("f") => {
+                    let corners = tokens
+                        .map(|t| {
+                            let index = t.split('/').next().unwrap();
+                            let count = vertices.len() as i64;
+                            match index.parse::<i64>() {
+                                Ok(index) if -count <= index && index < 0 => {
+                                    Ok((count + index) as usize)
+                                }
+                                Ok(index) if 0 < index && index <= count => {
+                                    Ok((index - 1) as usize)
+                                }
+                                _ => Err(parse_error(t)),
+                            }
+                        })
+                        .collect::<Result<Vec<usize>, ImageError>>()?;
+                    for k in 1..corners.len().saturating_sub(1) {
+                        faces.push((corners[0], corners[k], corners[k + 1]));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(TriangleModel {
+            vertices,
+            faces,
+            cull_backface: false,
+        })
+    }
+
+    /// Build triangles that ignore hits from behind; see `Triangle::with_backface_culling`.
+    pub fn with_backface_culling(mut self) -> TriangleModel {
+        self.cull_backface = true;
+        self
     }
 
     pub fn to_hittable(&self) -> HittableList {
+        self.to_hittable_with(Arc::new(Box::new(Lambertian::new(Color::new(
+            0.2, 0.2, 0.2,
+        )))))
+    }
+
+    /// Every face gets `mat_ptr`; pass a `DiffuseLight` to make the whole model glow.
+    pub fn to_hittable_with(&self, mat_ptr: Arc<Box<dyn Material>>) -> HittableList {
         let mut triangles = HittableList::new();
         for (v0, v1, v2) in &self.faces {
-            //eprintln!("{} {} {}",self.vertices[*v0],self.vertices[*v1],self.vertices[*v2]);
-            triangles.add(Arc::new(Box::new(Triangle::new(
+            let triangle = Triangle::new(
                 self.vertices[*v0],
                 self.vertices[*v1],
                 self.vertices[*v2],
-                Arc::new(Box::new(Lambertian::new(Color::new(0.2, 0.2, 0.2)))),
-            ))));
+                mat_ptr.clone(),
+            );
+            triangles.add(Arc::new(Box::new(match self.cull_backface {
+                true => triangle.with_backface_culling(),
+                false => triangle,
+            })));
         }
         triangles
     }
 }
+
+// a number on 0-based line `n`, or an error naming the token
+fn parse_at<T: std::str::FromStr>(n: usize, token: &str) -> Result<T, ImageError> {
+    token.parse::<T>().map_err(|_| ImageError::Parse {
+        line: n + 1,
+        token: token.to_string(),
+    })
+}
+
+/// Terrain mesh built from a grayscale image: brightness is elevation.
+pub struct HeightField {
+    mesh: BvhNode,
+}
+
+impl HeightField {
+    /// Lays `map` over a `width` x `depth` rectangle centred on the origin in the xz plane,
+    /// with white pixels raised to `height`. Image rows run along z, columns along x.
+    pub fn new(
+        map: &Screen,
+        width: f64,
+        depth: f64,
+        height: f64,
+        mat_ptr: Arc<Box<dyn Material>>,
+    ) -> HeightField {
+        let (cols, rows) = (map.get_width(), map.get_height());
+        assert!(
+            cols >= 2 && rows >= 2,
+            "height map needs at least 2x2 pixels"
+        );
+        let vertex = |i: usize, j: usize| {
+            let pixel = map.get(j, i);
+            let elevation = (pixel.get_x() + pixel.get_y() + pixel.get_z()) / (3.0 * 255.0);
+            Point3::new(
+                -width / 2.0 + width * i as f64 / (cols - 1) as f64,
+                height * elevation,
+                -depth / 2.0 + depth * j as f64 / (rows - 1) as f64,
+            )
+        };
+
+        let mut triangles = HittableList::new();
+        for j in 0..rows - 1 {
+            for i in 0..cols - 1 {
+                // wound so a flat cell faces +y
+                let (a, b) = (vertex(i, j), vertex(i + 1, j));
+                let (c, d) = (vertex(i, j + 1), vertex(i + 1, j + 1));
+                triangles.add(Arc::new(Box::new(Triangle::new(a, c, b, mat_ptr.clone()))));
+                triangles.add(Arc::new(Box::new(Triangle::new(b, c, d, mat_ptr.clone()))));
+            }
+        }
+        HeightField {
+            mesh: BvhNode::from_list(&triangles, 0.0, 1.0),
+        }
+    }
+
+    pub fn from_ppm(
+        name: &str,
+        width: f64,
+        depth: f64,
+        height: f64,
+        mat_ptr: Arc<Box<dyn Material>>,
+    ) -> Result<HeightField, ImageError> {
+        Ok(HeightField::new(
+            &Screen::from_ppm_p3(name)?,
+            width,
+            depth,
+            height,
+            mat_ptr,
+        ))
+    }
+}
+
+impl Hittable for HeightField {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.mesh.hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.mesh.bounding_box(time0, time1)
+    }
+    fn leaf_count(&self) -> usize {
+        self.mesh.leaf_count()
+    }
+    fn depth(&self) -> usize {
+        self.mesh.depth()
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        self.mesh.collect_lights(lights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    fn gray(width: usize, height: usize, level: f64) -> Screen {
+        let mut map = Screen::new(width, height);
+        for j in 0..height {
+            for i in 0..width {
+                map.update(j, i, Color::new(level, level, level));
+            }
+        }
+        map
+    }
+
+    #[test]
+    fn flat_map_is_planar() {
+        let mat: Arc<Box<dyn Material>> =
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let field = HeightField::new(&gray(5, 4, 127.5), 4.0, 3.0, 2.0, mat);
+        assert_eq!(field.leaf_count(), 4 * 3 * 2);
+
+        let bbox = field.bounding_box(0.0, 1.0).unwrap();
+        assert!(bbox.get_max().get_y() - bbox.get_min().get_y() < 0.001);
+
+        for (x, z) in [(0.3, 0.2), (-1.7, 1.2), (1.9, -1.4)] {
+            let down = Ray::new(&Point3::new(x, 10, z), &Vec3::new(0, -1, 0), 0.0);
+            let rec = field.hit(&down, 0.001, f64::INFINITY).unwrap();
+            assert!((rec.get_p().get_y() - 1.0).abs() < 1e-9);
+            crate::assert_vec3_eq!(*rec.get_normal(), Vec3::new(0, 1, 0));
+        }
+    }
+
+    #[test]
+    fn obj_indices_count_from_one_or_back_from_the_end() {
+        let path = std::env::temp_dir().join("indices.obj");
+        std::fs::write(
+            &path,
+            "# a quad and a triangle\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\n\
+             f 1 2 3\nf 1/1/1 3/3/1 4/4/1\nv 2 2 0\nf -5 -4 -1\n",
+        )
+        .unwrap();
+        let model = TriangleModel::load_from_file(path.to_str().unwrap(), 2.0).unwrap();
+        assert_eq!(model.vertices.len(), 5);
+        assert_eq!(model.vertices[2], Point3::new(2, 2, 0));
+        assert_eq!(model.faces, vec![(0, 1, 2), (0, 2, 3), (0, 1, 4)]);
+
+        let square =
+            TriangleModel::from_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n", 1.0)
+                .unwrap();
+        assert_eq!(square.faces, vec![(0, 1, 2), (0, 2, 3)]);
+        assert_eq!(square.to_hittable().len(), 2);
+    }
+
+    #[test]
+    fn obj_rejects_bad_numbers_and_skips_degenerate_faces() {
+        // a point and a line make no triangles, rather than underflowing the fan
+        let model = TriangleModel::from_obj("v 0 0 0\nv 1 0 0\nf 1\nf 1 2\nf\n", 1.0).unwrap();
+        assert!(model.faces.is_empty());
+
+        assert!(matches!(
+            TriangleModel::from_obj("v 0 0 0\nv 1 x 0\n", 1.0),
+            Err(ImageError::Parse { line: 2, token }) if token == "x"
+        ));
+        assert!(matches!(
+            TriangleModel::from_obj("v 0 0 0\nv 1 0 0\nf 1 2/3 b/1\n", 1.0),
+            Err(ImageError::Parse { line: 3, token }) if token == "b/1"
+        ));
+        assert!(matches!(
+            TriangleModel::from_obj("v 0 0 0\nf 0 1 1\n", 1.0),
+            Err(ImageError::Parse { line: 2, .. })
+        ));
+        // indices past either end of the vertices seen so far
+        assert!(matches!(
+            TriangleModel::from_obj("v 0 0 0\nf 1 2 3\n", 1.0),
+            Err(ImageError::Parse { line: 2, token }) if token == "2"
+        ));
+        assert!(matches!(
+            TriangleModel::from_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -1 -2 -4\n", 1.0),
+            Err(ImageError::Parse { line: 4, token }) if token == "-4"
+        ));
+        assert!(matches!(
+            TriangleModel::load_from_file("/nonexistent/model.obj", 1.0),
+            Err(ImageError::NotFound(_))
+        ));
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\nelement face 1\nend_header\n";
+        let ply = format!("{header}0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n");
+        assert!(matches!(
+            TriangleModel::from_ply(&ply, 1.0),
+            Err(ImageError::Parse { line: 9, token }) if token == "3"
+        ));
+        assert!(matches!(
+            TriangleModel::from_ply(&format!("{header}0 0 0\n"), 1.0),
+            Err(ImageError::Truncated {
+                expected: 4,
+                found: 1
+            })
+        ));
+        assert!(matches!(
+            TriangleModel::from_obj("v 0 0\n", 1.0),
+            Err(ImageError::Truncated {
+                expected: 3,
+                found: 2
+            })
+        ));
+    }
+}
@@ -1,9 +1,12 @@
+use crate::mutil::rng;
 use crate::vec3::{random_range, Point3, Vec3};
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 const POINT_COUNT: i32 = 256;
 
 pub struct Perlin {
+    // table size minus one; the size is a power of two, so this wraps lattice indices
+    mask: i32,
     ranvec: Vec<Vec3>,
     perm_x: Vec<i32>,
     perm_y: Vec<i32>,
@@ -12,16 +15,23 @@ pub struct Perlin {
 
 impl Perlin {
     pub fn new() -> Perlin {
-        let mut rng = thread_rng();
+        Perlin::with_resolution(POINT_COUNT as usize)
+    }
+
+    /// Noise from `points` random gradients (a power of two). The pattern repeats every
+    /// `points` units along each axis, so more points cover more ground before tiling.
+    pub fn with_resolution(points: usize) -> Perlin {
+        assert!(points >= 2 && points.is_power_of_two());
         let mut ranvec: Vec<Vec3> = vec![];
-        for i in 0..(POINT_COUNT as usize) {
+        for _ in 0..points {
             ranvec.push(random_range(-1.0, 1.0));
         }
         Perlin {
+            mask: points as i32 - 1,
             ranvec,
-            perm_x: Perlin::perlin_generate_perm(),
-            perm_y: Perlin::perlin_generate_perm(),
-            perm_z: Perlin::perlin_generate_perm(),
+            perm_x: Perlin::perlin_generate_perm(points),
+            perm_y: Perlin::perlin_generate_perm(points),
+            perm_z: Perlin::perlin_generate_perm(points),
         }
     }
 
@@ -40,9 +50,10 @@ impl Perlin {
         for di in 0..2 as usize {
             for dj in 0..2 as usize {
                 for dk in 0..2 as usize {
-                    c[di][dj][dk] = self.ranvec[(self.perm_x[((i + di as i32) & 255) as usize]
-                        ^ self.perm_y[((j + dj as i32) & 255) as usize]
-                        ^ self.perm_z[((k + dk as i32) & 255) as usize])
+                    c[di][dj][dk] = self.ranvec[(self.perm_x
+                        [((i + di as i32) & self.mask) as usize]
+                        ^ self.perm_y[((j + dj as i32) & self.mask) as usize]
+                        ^ self.perm_z[((k + dk as i32) & self.mask) as usize])
                         as usize];
                 }
             }
@@ -65,17 +76,14 @@ impl Perlin {
         return f64::abs(accum);
     }
 
-    fn perlin_generate_perm() -> Vec<i32> {
-        let mut p = vec![0; POINT_COUNT as usize];
-        for i in 0..POINT_COUNT {
-            p[i as usize] = i;
-        }
+    fn perlin_generate_perm(points: usize) -> Vec<i32> {
+        let mut p: Vec<i32> = (0..points as i32).collect();
         Perlin::permute(&mut p);
         p
     }
 
     fn permute(vec: &mut Vec<i32>) {
-        let mut rng = thread_rng();
+        let mut rng = rng();
         for i in (1..vec.len() - 1).rev() {
             let target = rng.gen_range(0..i + 1);
             (vec[i], vec[target]) = (vec[target], vec[i])
@@ -105,3 +113,27 @@ impl Perlin {
         accum
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mutil;
+
+    #[test]
+    fn more_points_repeat_less_often() {
+        mutil::seed(12);
+        let coarse = Perlin::new();
+        let fine = Perlin::with_resolution(1024);
+        let p = Point3::new(0.3, 1.7, 2.2);
+        let shifted = |by: i32| p + Vec3::new(by, 0, 0);
+        let same = |perlin: &Perlin, q: &Point3| (perlin.noise(&p) - perlin.noise(q)).abs() < 1e-9;
+
+        assert!(same(&coarse, &shifted(256)));
+        assert!(!same(&fine, &shifted(256)));
+        assert!(same(&fine, &shifted(1024)));
+        // a small table tiles after only a few units
+        let tiny = Perlin::with_resolution(4);
+        assert!(same(&tiny, &shifted(4)));
+        assert!(!same(&tiny, &shifted(2)));
+    }
+}
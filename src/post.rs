@@ -0,0 +1,477 @@
+use crate::screen::{BitDepth, Screen};
+use crate::vec3::Color;
+
+pub fn luminance(c: &Color) -> f64 {
+    0.2126 * c.get_x() + 0.7152 * c.get_y() + 0.0722 * c.get_z()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Bloom {
+    threshold: f64,
+    intensity: f64,
+}
+
+impl Bloom {
+    pub fn new(threshold: f64, intensity: f64) -> Bloom {
+        assert!(intensity >= 0.0);
+        Bloom {
+            threshold,
+            intensity,
+        }
+    }
+
+    /// Blur everything brighter than the threshold and add it back on top of the image.
+    /// Works on linear (HDR) values, so run it before encoding.
+    pub fn apply(&self, hdr: &mut Screen) {
+        let (width, height) = (hdr.get_width(), hdr.get_height());
+        let mut bright = Screen::new(width, height);
+        for j in 0..height {
+            for i in 0..width {
+                let c = *hdr.get(j, i);
+                if luminance(&c) > self.threshold {
+                    bright.update(j, i, c);
+                }
+            }
+        }
+
+        let kernel = gaussian_kernel(f64::max(1.0, width as f64 / 100.0));
+        let blurred = blur_pass(&blur_pass(&bright, &kernel, true), &kernel, false);
+        for j in 0..height {
+            for i in 0..width {
+                let c = *hdr.get(j, i) + self.intensity * *blurred.get(j, i);
+                hdr.update(j, i, c);
+            }
+        }
+    }
+}
+
+/// Scale every pixel by a linear exposure multiplier.
+pub fn expose(hdr: &mut Screen, exposure: f64) {
+    for j in 0..hdr.get_height() {
+        for i in 0..hdr.get_width() {
+            let c = exposure * *hdr.get(j, i);
+            hdr.update(j, i, c);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vignette {
+    strength: f64,
+}
+
+impl Vignette {
+    /// `strength` is how much light the corners lose: 0 leaves the image alone, 1 makes them black.
+    pub fn new(strength: f64) -> Vignette {
+        assert!((0.0..=1.0).contains(&strength));
+        Vignette { strength }
+    }
+
+    pub fn apply(&self, hdr: &mut Screen) {
+        let (width, height) = (hdr.get_width(), hdr.get_height());
+        let (cx, cy) = ((width as f64 - 1.0) / 2.0, (height as f64 - 1.0) / 2.0);
+        let max_r2 = f64::max(cx * cx + cy * cy, f64::EPSILON);
+        for j in 0..height {
+            for i in 0..width {
+                let (dx, dy) = (i as f64 - cx, j as f64 - cy);
+                let falloff = 1.0 - self.strength * (dx * dx + dy * dy) / max_r2;
+                let c = falloff * *hdr.get(j, i);
+                hdr.update(j, i, c);
+            }
+        }
+    }
+}
+
+fn gaussian_kernel(sigma: f64) -> Vec<f64> {
+    let radius = (3.0 * sigma).ceil() as i64;
+    let weights: Vec<f64> = (-radius..=radius)
+        .map(|x| f64::exp(-((x * x) as f64) / (2.0 * sigma * sigma)))
+        .collect();
+    let total: f64 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+// one direction of a separable blur, clamping at the borders
+fn blur_pass(src: &Screen, kernel: &[f64], horizontal: bool) -> Screen {
+    let (width, height) = (src.get_width() as i64, src.get_height() as i64);
+    let radius = (kernel.len() / 2) as i64;
+    let mut out = Screen::new(width as usize, height as usize);
+    for j in 0..height {
+        for i in 0..width {
+            let mut sum = Color::new(0, 0, 0);
+            for (k, w) in kernel.iter().enumerate() {
+                let offset = k as i64 - radius;
+                let (sj, si) = if horizontal {
+                    (j, (i + offset).clamp(0, width - 1))
+                } else {
+                    ((j + offset).clamp(0, height - 1), i)
+                };
+                sum += *w * *src.get(sj as usize, si as usize);
+            }
+            out.update(j as usize, i as usize, sum);
+        }
+    }
+    out
+}
+
+/// How linear radiance is squeezed into the displayable [0, 1] range, per channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    /// Stretch `[black, white]` over the whole range and cut off everything outside it.
+    Clamp { black: f64, white: f64 },
+    /// Linear up to `knee` (a fraction of `white`), then a smooth shoulder that approaches
+    /// but never reaches 1, so highlights roll off instead of flattening.
+    SoftClip { knee: f64, white: f64 },
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve: a toe that deepens the shadows,
+    /// more contrast through the mid-tones and a long highlight shoulder that reaches 1 at
+    /// about 7. Use `expose` to place the image on it.
+    Aces,
+}
+
+impl Default for ToneMap {
+    fn default() -> ToneMap {
+        ToneMap::Clamp {
+            black: 0.0,
+            white: 1.0,
+        }
+    }
+}
+
+impl ToneMap {
+    pub fn apply(&self, c: f64) -> f64 {
+        match *self {
+            ToneMap::Clamp { black, white } => ((c - black) / (white - black)).clamp(0.0, 1.0),
+            ToneMap::SoftClip { knee, white } => {
+                let x = f64::max(c / white, 0.0);
+                if x <= knee {
+                    x
+                } else {
+                    // exponential shoulder with slope 1 at the knee
+                    let room = 1.0 - knee;
+                    knee + room * (1.0 - f64::exp(-(x - knee) / room))
+                }
+            }
+            ToneMap::Aces => {
+                let x = f64::max(c, 0.0);
+                let mapped = x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14);
+                mapped.clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+/// Tone map and gamma-2 encode a linear buffer into the 0-255 values the PPM writers expect.
+pub fn encode(hdr: &Screen, tone_map: &ToneMap) -> Screen {
+    encode_with_depth(hdr, tone_map, BitDepth::Eight)
+}
+
+/// Like `encode`, but quantized to `bit_depth`'s 0..=max range.
+pub fn encode_with_depth(hdr: &Screen, tone_map: &ToneMap, bit_depth: BitDepth) -> Screen {
+    let mut out = Screen::new(hdr.get_width(), hdr.get_height()).with_bit_depth(bit_depth);
+    // just under max + 1, so only a full 1.0 reaches the top value
+    let top = bit_depth.max_value() as f64 + 0.9;
+    for j in 0..hdr.get_height() {
+        for i in 0..hdr.get_width() {
+            let c = hdr.get(j, i);
+            let mapped = Color::new(
+                tone_map.apply(c.get_x()),
+                tone_map.apply(c.get_y()),
+                tone_map.apply(c.get_z()),
+            );
+            let encoded = match bit_depth {
+                BitDepth::Eight => mapped.get_normalized_color(1),
+                BitDepth::Sixteen => {
+                    let quantize = |x: f64| (top * f64::sqrt(x.clamp(0.0, 1.0))).floor();
+                    Color::new(
+                        quantize(mapped.get_x()),
+                        quantize(mapped.get_y()),
+                        quantize(mapped.get_z()),
+                    )
+                }
+            };
+            out.update(j, i, encoded);
+        }
+    }
+    out
+}
+
+/// How display values are encoded from linear radiance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// Tone mapped and gamma-2 encoded, for viewing.
+    #[default]
+    Srgb,
+    /// Proportional to radiance, clamped to [0, 1] and then scaled to the bit depth.
+    Linear,
+}
+
+/// Quantize a linear buffer to `bit_depth` without tone mapping or gamma, for tools that
+/// expect linear data. Light above 1 is clipped.
+pub fn encode_linear(hdr: &Screen, bit_depth: BitDepth) -> Screen {
+    let mut out = Screen::new(hdr.get_width(), hdr.get_height()).with_bit_depth(bit_depth);
+    let top = bit_depth.max_value() as f64 + 0.9;
+    let quantize = |x: f64| (top * x.clamp(0.0, 1.0)).floor();
+    for j in 0..hdr.get_height() {
+        for i in 0..hdr.get_width() {
+            let c = hdr.get(j, i);
+            out.update(
+                j,
+                i,
+                Color::new(
+                    quantize(c.get_x()),
+                    quantize(c.get_y()),
+                    quantize(c.get_z()),
+                ),
+            );
+        }
+    }
+    out
+}
+
+/// Retro look: snap each channel of a display image to `levels` evenly spaced
+/// values, spreading the rounding error onto unvisited neighbours (Floyd-Steinberg)
+/// so smooth regions average out to their original shade.
+#[derive(Debug, Clone, Copy)]
+pub struct Dither {
+    levels: usize,
+}
+
+impl Dither {
+    pub fn new(levels: usize) -> Dither {
+        assert!(levels >= 2);
+        Dither { levels }
+    }
+
+    pub fn apply(&self, image: &mut Screen) {
+        let (width, height) = (image.get_width(), image.get_height());
+        let max = image.get_bit_depth().max_value() as f64;
+        let step = max / (self.levels - 1) as f64;
+        // levels needn't divide `max` evenly, so round each one to a code the file can hold
+        let snap = |x: f64| ((x.clamp(0.0, max) / step).round() * step).round();
+        let spread = |image: &mut Screen, j: usize, i: usize, error: Color| {
+            let c = *image.get(j, i) + error;
+            image.update(j, i, c);
+        };
+        // top row first, as the image is displayed; row 0 is the bottom
+        for j in (0..height).rev() {
+            for i in 0..width {
+                let old = *image.get(j, i);
+                let new = Color::new(snap(old.get_x()), snap(old.get_y()), snap(old.get_z()));
+                image.update(j, i, new);
+                let error = old - new;
+                if i + 1 < width {
+                    spread(image, j, i + 1, 7.0 / 16.0 * error);
+                }
+                if j > 0 {
+                    if i > 0 {
+                        spread(image, j - 1, i - 1, 3.0 / 16.0 * error);
+                    }
+                    spread(image, j - 1, i, 5.0 / 16.0 * error);
+                    if i + 1 < width {
+                        spread(image, j - 1, i + 1, 1.0 / 16.0 * error);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_encoding_skips_the_gamma_curve() {
+        let mut hdr = Screen::new(3, 1);
+        hdr.update(0, 0, Color::new(0.25, 0.5, 1));
+        hdr.update(0, 1, Color::new(-1, 0, 7));
+        hdr.update(0, 2, Color::splat(0.25));
+
+        let linear = encode_linear(&hdr, BitDepth::Eight);
+        assert_eq!(*linear.get(0, 0), Color::new(63, 127, 255));
+        assert_eq!(*linear.get(0, 1), Color::new(0, 0, 255));
+        // gamma 2 would have lifted a quarter to half scale
+        assert_eq!(
+            *encode(&hdr, &ToneMap::default()).get(0, 2),
+            Color::splat(127)
+        );
+
+        let deep = encode_linear(&hdr, BitDepth::Sixteen);
+        assert_eq!(deep.get_bit_depth(), BitDepth::Sixteen);
+        assert_eq!(*deep.get(0, 0), Color::new(16383, 32767, 65535));
+    }
+
+    #[test]
+    fn bloom_spreads_bright_pixel() {
+        let mut hdr = Screen::new(9, 9);
+        hdr.update(4, 4, Color::new(50, 50, 50));
+        hdr.update(0, 0, Color::new(0.5, 0.5, 0.5));
+        Bloom::new(1.0, 1.0).apply(&mut hdr);
+
+        assert!(luminance(hdr.get(4, 5)) > 0.0);
+        assert!(luminance(hdr.get(3, 4)) > 0.0);
+        assert!(luminance(hdr.get(4, 5)) > luminance(hdr.get(4, 7)));
+        // the dim pixel is below threshold and isn't bloomed into its neighbours
+        assert_eq!(*hdr.get(0, 1), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn doubling_exposure_doubles_mid_value() {
+        let mut hdr = Screen::new(1, 1);
+        hdr.update(0, 0, Color::new(0.2, 0.2, 0.2));
+        expose(&mut hdr, 2.0);
+        assert_eq!(*hdr.get(0, 0), Color::new(0.4, 0.4, 0.4));
+        // still below 1.0, so the encoded value is the gamma of the doubled radiance
+        assert_eq!(
+            *encode(&hdr, &ToneMap::default()).get(0, 0),
+            Color::new(0.4, 0.4, 0.4).get_normalized_color(1)
+        );
+    }
+
+    #[test]
+    fn vignette_darkens_corners() {
+        let mut hdr = Screen::new(9, 7);
+        for j in 0..7 {
+            for i in 0..9 {
+                hdr.update(j, i, Color::new(1, 1, 1));
+            }
+        }
+        Vignette::new(0.5).apply(&mut hdr);
+        let center = luminance(hdr.get(3, 4));
+        assert_eq!(center, 1.0);
+        for (j, i) in [(0, 0), (0, 8), (6, 0), (6, 8)] {
+            assert!(luminance(hdr.get(j, i)) < center);
+        }
+    }
+
+    #[test]
+    fn soft_clip_rolls_off_highlights() {
+        let hard = ToneMap::default();
+        let soft = ToneMap::SoftClip {
+            knee: 0.8,
+            white: 1.0,
+        };
+        assert_eq!(hard.apply(1.5), 1.0);
+        assert_eq!(hard.apply(4.0), 1.0);
+        assert_eq!(hard.apply(-0.5), 0.0);
+        // a raised black point crushes the shadows the same way
+        let lifted = ToneMap::Clamp {
+            black: 0.2,
+            white: 1.2,
+        };
+        assert_eq!(lifted.apply(0.1), 0.0);
+        assert!((lifted.apply(0.7) - 0.5).abs() < 1e-12);
+        assert_eq!(lifted.apply(1.5), 1.0);
+        assert_eq!(soft.apply(0.5), 0.5);
+
+        // above 1, values stay distinct and below 1, increasing with input
+        let mut last = soft.apply(0.8);
+        for x in [0.9, 1.0, 1.5, 2.0, 3.0] {
+            let y = soft.apply(x);
+            assert!(y > last && y < 1.0);
+            last = y;
+        }
+        // no kink at the knee
+        let slope = (soft.apply(0.8 + 1e-6) - soft.apply(0.8)) / 1e-6;
+        assert!((slope - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn aces_is_an_s_curve() {
+        let aces = ToneMap::Aces;
+        let reinhard = |x: f64| x / (1.0 + x);
+        assert_eq!(aces.apply(0.0), 0.0);
+        assert_eq!(aces.apply(-1.0), 0.0);
+        // the toe darkens shadows, where Reinhard is nearly linear
+        assert!(aces.apply(0.02) < 0.6 * 0.02);
+        assert!(reinhard(0.02) > 0.9 * 0.02);
+        // steeper than Reinhard through the mid-tones
+        let slope = |f: &dyn Fn(f64) -> f64| (f(0.6) - f(0.3)) / 0.3;
+        assert!(slope(&|x| aces.apply(x)) > 1.5 * slope(&reinhard));
+        // highlights are compressed under 1 rather than cut off
+        let bright = aces.apply(4.0);
+        assert!(bright > 0.95 && bright < 1.0, "{}", bright);
+        assert!(aces.apply(2.0) < bright && bright < aces.apply(5.0));
+        assert!((bright - reinhard(4.0)).abs() > 0.1);
+        assert_eq!(aces.apply(100.0), 1.0);
+    }
+
+    #[test]
+    fn sixteen_bits_resolve_finer_gradients() {
+        let width = 1000;
+        let mut hdr = Screen::new(width, 1);
+        for i in 0..width {
+            let v = 0.2 + 0.01 * i as f64 / width as f64;
+            hdr.update(0, i, Color::new(v, v, v));
+        }
+        let levels = |bit_depth| {
+            let image = encode_with_depth(&hdr, &ToneMap::default(), bit_depth);
+            let mut values: Vec<u32> = (0..width).map(|i| image.get(0, i).get_x() as u32).collect();
+            values.dedup();
+            values.len()
+        };
+        let (eight, sixteen) = (levels(BitDepth::Eight), levels(BitDepth::Sixteen));
+        assert!(eight < 5, "{}", eight);
+        assert!(sixteen > 100 * eight, "{} vs {}", sixteen, eight);
+        // full white reaches the top of either range
+        let mut white = Screen::new(1, 1);
+        white.update(0, 0, Color::new(1, 1, 1));
+        let white = encode_with_depth(&white, &ToneMap::default(), BitDepth::Sixteen);
+        assert_eq!(white.get(0, 0).get_x(), 65535.0);
+    }
+
+    #[test]
+    fn dithered_gradient_averages_to_original() {
+        let (width, height) = (64, 16);
+        let mut image = Screen::new(width, height);
+        for j in 0..height {
+            for i in 0..width {
+                let v = i as f64 * 4.0;
+                image.update(j, i, Color::new(v, v, v));
+            }
+        }
+        Dither::new(2).apply(&mut image);
+
+        for j in 0..height {
+            for i in 0..width {
+                let v = image.get(j, i).get_x();
+                assert!(v == 0.0 || v == 255.0);
+            }
+        }
+        // mid-gray comes out as a mix of both levels rather than a flat band
+        let row: Vec<f64> = (28..36).map(|i| image.get(8, i).get_x()).collect();
+        assert!(row.contains(&0.0) && row.contains(&255.0));
+        // each 8x8 block keeps roughly its original mean
+        for block in 0..width / 8 {
+            let mut sum = 0.0;
+            for j in 4..12 {
+                for i in block * 8..block * 8 + 8 {
+                    sum += image.get(j, i).get_x();
+                }
+            }
+            let original = (block * 8) as f64 * 4.0 + 14.0;
+            assert!((sum / 64.0 - original).abs() < 24.0, "block {}", block);
+        }
+    }
+
+    #[test]
+    fn dither_levels_land_on_integer_codes() {
+        // 255 / 2 isn't whole, yet the middle level must still be a valid PPM value
+        let mut image = Screen::new(32, 4);
+        for j in 0..4 {
+            for i in 0..32 {
+                let v = i as f64 * 8.0;
+                image.update(j, i, Color::new(v, v, v));
+            }
+        }
+        Dither::new(3).apply(&mut image);
+        for j in 0..4 {
+            for i in 0..32 {
+                let v = image.get(j, i).get_x();
+                assert!(v == 0.0 || v == 128.0 || v == 255.0, "{}", v);
+            }
+        }
+        let row: Vec<f64> = (0..32).map(|i| image.get(2, i).get_x()).collect();
+        assert!(row.contains(&128.0));
+    }
+}
@@ -1,21 +1,115 @@
 use crate::vec3::{Point3, Vec3};
 
+/// What a ray is for, so objects can choose which rays see them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RayKind {
+    /// Straight from the camera.
+    #[default]
+    Primary,
+    /// Aimed at a light to check whether it's blocked.
+    Shadow,
+    /// Reflected, refracted or scattered off a surface.
+    Secondary,
+}
+
+/// How a camera ray's origin and direction change from one pixel to the next in x and in
+/// y, so whatever it hits can tell how much surface a pixel covers there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayDifferential {
+    dx_origin: Vec3,
+    dx_direction: Vec3,
+    dy_origin: Vec3,
+    dy_direction: Vec3,
+}
+
+impl RayDifferential {
+    pub fn new(
+        dx_origin: Vec3,
+        dx_direction: Vec3,
+        dy_origin: Vec3,
+        dy_direction: Vec3,
+    ) -> RayDifferential {
+        RayDifferential {
+            dx_origin,
+            dx_direction,
+            dy_origin,
+            dy_direction,
+        }
+    }
+
+    pub fn get_dx_origin(&self) -> &Vec3 {
+        &self.dx_origin
+    }
+
+    pub fn get_dx_direction(&self) -> &Vec3 {
+        &self.dx_direction
+    }
+
+    pub fn get_dy_origin(&self) -> &Vec3 {
+        &self.dy_origin
+    }
+
+    pub fn get_dy_direction(&self) -> &Vec3 {
+        &self.dy_direction
+    }
+
+    /// The same differentials with every vector passed through `f`, e.g. a rotation.
+    pub fn map(&self, f: impl Fn(&Vec3) -> Vec3) -> RayDifferential {
+        RayDifferential::new(
+            f(&self.dx_origin),
+            f(&self.dx_direction),
+            f(&self.dy_origin),
+            f(&self.dy_direction),
+        )
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
     time: f64,
+    kind: RayKind,
+    // per-axis 1 / direction, and whether it's negative, for box tests
+    inv_direction: Vec3,
+    sign: [usize; 3],
+    differential: Option<RayDifferential>,
 }
 
 impl Ray {
     pub fn new(&origin: &Point3, &direction: &Vec3, time: f64) -> Ray {
+        let inv_direction = Vec3::new(
+            1.0 / direction.get_x(),
+            1.0 / direction.get_y(),
+            1.0 / direction.get_z(),
+        );
+        let negative = |inv: f64| (inv < 0.0) as usize;
         Ray {
             origin,
             direction,
             time,
+            kind: RayKind::Primary,
+            inv_direction,
+            sign: [
+                negative(inv_direction.get_x()),
+                negative(inv_direction.get_y()),
+                negative(inv_direction.get_z()),
+            ],
+            differential: None,
         }
     }
 
+    pub fn with_kind(mut self, kind: RayKind) -> Ray {
+        self.kind = kind;
+        self
+    }
+
+    /// Attach (or, with `None`, drop) pixel footprint information; see `Camera::differential`.
+    pub fn with_differential(mut self, differential: Option<RayDifferential>) -> Ray {
+        self.differential = differential;
+        self
+    }
+
     pub fn get_origin(&self) -> &Point3 {
         &self.origin
     }
@@ -28,6 +122,34 @@ impl Ray {
         self.time
     }
 
+    pub fn get_kind(&self) -> RayKind {
+        self.kind
+    }
+
+    pub fn get_differential(&self) -> Option<RayDifferential> {
+        self.differential
+    }
+
+    /// Rough width of one pixel at `at(t)`: how far the neighboring pixels' rays land from
+    /// this one's, measured across the ray. `None` without differentials.
+    pub fn footprint(&self, t: f64) -> Option<f64> {
+        self.differential.map(|d| {
+            let dx = *d.get_dx_origin() + t * *d.get_dx_direction();
+            let dy = *d.get_dy_origin() + t * *d.get_dy_direction();
+            f64::max(dx.length(), dy.length())
+        })
+    }
+
+    /// Componentwise reciprocal of the direction; infinite along axes it doesn't move on.
+    pub fn get_inv_direction(&self) -> &Vec3 {
+        &self.inv_direction
+    }
+
+    /// Per axis, 1 if the ray runs toward negative coordinates and 0 otherwise.
+    pub fn get_sign(&self) -> [usize; 3] {
+        self.sign
+    }
+
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + self.direction * t
     }
@@ -0,0 +1,148 @@
+//! Compare a render against a known-good reference image, for regression testing the integrator.
+
+use crate::screen::{ImageError, Screen};
+use std::fmt;
+
+/// How far a candidate image is from its reference, in the images' own units (0-255 for PPMs).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDiff {
+    rmse: f64,
+    max_error: f64,
+}
+
+impl ImageDiff {
+    /// Root mean square difference over every channel of every pixel.
+    pub fn get_rmse(&self) -> f64 {
+        self.rmse
+    }
+
+    /// Largest difference in any one channel.
+    pub fn get_max_error(&self) -> f64 {
+        self.max_error
+    }
+}
+
+#[derive(Debug)]
+pub enum DiffError {
+    Image(ImageError),
+    SizeMismatch {
+        reference: (usize, usize),
+        candidate: (usize, usize),
+    },
+    /// The images differ by more than the allowed RMSE.
+    TooDifferent {
+        diff: ImageDiff,
+        threshold: f64,
+    },
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiffError::Image(e) => write!(f, "{}", e),
+            DiffError::SizeMismatch {
+                reference,
+                candidate,
+            } => write!(
+                f,
+                "reference is {}x{} but candidate is {}x{}",
+                reference.0, reference.1, candidate.0, candidate.1
+            ),
+            DiffError::TooDifferent { diff, threshold } => write!(
+                f,
+                "rmse {:.4} (max error {:.1}) is above the threshold of {}",
+                diff.rmse, diff.max_error, threshold
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DiffError {}
+
+pub fn diff(reference: &Screen, candidate: &Screen) -> Result<ImageDiff, DiffError> {
+    let size = |s: &Screen| (s.get_width(), s.get_height());
+    if size(reference) != size(candidate) {
+        return Err(DiffError::SizeMismatch {
+            reference: size(reference),
+            candidate: size(candidate),
+        });
+    }
+    let (mut squares, mut max_error) = (0.0, 0.0);
+    for j in 0..reference.get_height() {
+        for i in 0..reference.get_width() {
+            let delta = *candidate.get(j, i) - *reference.get(j, i);
+            for d in delta.iter() {
+                squares += d * d;
+                max_error = f64::max(max_error, d.abs());
+            }
+        }
+    }
+    let channels = (reference.get_width() * reference.get_height() * 3) as f64;
+    Ok(ImageDiff {
+        rmse: f64::sqrt(squares / channels),
+        max_error,
+    })
+}
+
+/// Load both P3 PPMs and diff them, failing if the RMSE is above `threshold`.
+pub fn compare_files(
+    reference: &str,
+    candidate: &str,
+    threshold: f64,
+) -> Result<ImageDiff, DiffError> {
+    let reference = Screen::from_ppm_p3(reference).map_err(DiffError::Image)?;
+    let candidate = Screen::from_ppm_p3(candidate).map_err(DiffError::Image)?;
+    let diff = diff(&reference, &candidate)?;
+    if diff.rmse > threshold {
+        return Err(DiffError::TooDifferent { diff, threshold });
+    }
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+
+    fn gradient(shift: usize) -> Screen {
+        let mut screen = Screen::new(16, 4);
+        for j in 0..4 {
+            for i in 0..16 {
+                let v = ((i + shift) % 16 * 16) as f64;
+                screen.update(j, i, Color::new(v, v, 255.0 - v));
+            }
+        }
+        screen
+    }
+
+    #[test]
+    fn identical_images_have_no_error() {
+        let path = std::env::temp_dir().join("reference_gradient.ppm");
+        let path = path.to_str().unwrap();
+        gradient(0).write_to_ppm_file(path);
+        let same = compare_files(path, path, 0.0).unwrap();
+        assert_eq!(same.get_rmse(), 0.0);
+        assert_eq!(same.get_max_error(), 0.0);
+    }
+
+    #[test]
+    fn shifted_image_fails_threshold() {
+        let shifted = diff(&gradient(0), &gradient(1)).unwrap();
+        assert!(shifted.get_rmse() > 0.0);
+        // the wrapped-around column jumps all the way from 240 to 0
+        assert_eq!(shifted.get_max_error(), 240.0);
+
+        let dir = std::env::temp_dir();
+        let (a, b) = (dir.join("reference_a.ppm"), dir.join("reference_b.ppm"));
+        gradient(0).write_to_ppm_file(a.to_str().unwrap());
+        gradient(1).write_to_ppm_file(b.to_str().unwrap());
+        assert!(matches!(
+            compare_files(a.to_str().unwrap(), b.to_str().unwrap(), 1.0),
+            Err(DiffError::TooDifferent { .. })
+        ));
+        assert!(matches!(
+            diff(&gradient(0), &Screen::new(4, 4)),
+            Err(DiffError::SizeMismatch { .. })
+        ));
+    }
+}
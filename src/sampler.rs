@@ -0,0 +1,108 @@
+//! Where pixel samples land. `Sampler::Random` draws every dimension independently;
+//! `Sampler::Halton` uses a low-discrepancy sequence, which converges faster in smooth regions.
+
+use crate::mutil::rng;
+use rand::Rng;
+
+/// Dimensions of one camera sample: pixel x, pixel y, lens u, lens v, time.
+pub const DIMENSIONS: usize = 5;
+const PRIMES: [u64; DIMENSIONS] = [2, 3, 5, 7, 11];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Sampler {
+    #[default]
+    Random,
+    Halton,
+}
+
+impl Sampler {
+    /// Coordinates in [0, 1) for sample `index` of pixel `(i, j)`. Halton points are shifted
+    /// per pixel (and per `seed`) so neighbouring pixels don't share a pattern.
+    pub fn point(&self, index: usize, i: usize, j: usize, seed: u64) -> [f64; DIMENSIONS] {
+        match self {
+            Sampler::Random => {
+                let mut rng = rng();
+                [(); DIMENSIONS].map(|_| rng.gen::<f64>())
+            }
+            Sampler::Halton => {
+                let shift = pixel_shift(i, j, seed);
+                let mut p = [0.0; DIMENSIONS];
+                for d in 0..DIMENSIONS {
+                    // skip index 0, which is the origin in every dimension
+                    p[d] = (radical_inverse(PRIMES[d], index as u64 + 1) + shift[d]).fract();
+                }
+                p
+            }
+        }
+    }
+}
+
+/// `index` with its base-`base` digits mirrored around the radix point.
+pub fn radical_inverse(base: u64, mut index: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut scale = inv_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale *= inv_base;
+    }
+    result
+}
+
+// random rotation from a hash of the pixel, so it doesn't need to be stored between samples
+fn pixel_shift(i: usize, j: usize, seed: u64) -> [f64; DIMENSIONS] {
+    let mut state = seed ^ ((i as u64) << 32 | j as u64);
+    [(); DIMENSIONS].map(|_| {
+        // splitmix64
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mutil;
+
+    // largest gap between the fraction of points in [0, a) x [0, b) and its area, over a grid of boxes
+    fn discrepancy(points: &[(f64, f64)]) -> f64 {
+        let steps = 32;
+        let mut worst: f64 = 0.0;
+        for a in 1..=steps {
+            for b in 1..=steps {
+                let (a, b) = (a as f64 / steps as f64, b as f64 / steps as f64);
+                let inside = points.iter().filter(|(x, y)| *x < a && *y < b).count();
+                worst = worst.max((inside as f64 / points.len() as f64 - a * b).abs());
+            }
+        }
+        worst
+    }
+
+    #[test]
+    fn radical_inverse_base_two() {
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 2), 0.25);
+        assert_eq!(radical_inverse(2, 3), 0.75);
+        assert_eq!(radical_inverse(3, 4), 1.0 / 3.0 + 1.0 / 9.0);
+    }
+
+    #[test]
+    fn halton_is_more_uniform_than_random() {
+        mutil::seed(3);
+        let n = 256;
+        let halton: Vec<(f64, f64)> = (0..n)
+            .map(|k| Sampler::Halton.point(k, 4, 7, 0))
+            .map(|p| (p[0], p[1]))
+            .collect();
+        let random: Vec<(f64, f64)> = (0..n)
+            .map(|k| Sampler::Random.point(k, 4, 7, 0))
+            .map(|p| (p[0], p[1]))
+            .collect();
+        assert!(discrepancy(&halton) * 2.0 < discrepancy(&random));
+    }
+}
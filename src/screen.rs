@@ -2,11 +2,31 @@ use crate::vec3::Color;
 use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io::Write;
+use std::ops::Range;
+use std::{fmt, io};
+
+/// Bits per channel of encoded (display) values, which then run from 0 to `max_value()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BitDepth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth {
+    pub fn max_value(&self) -> u32 {
+        match self {
+            BitDepth::Eight => 255,
+            BitDepth::Sixteen => 65535,
+        }
+    }
+}
 
 pub struct Screen {
     width: usize,
     height: usize,
     pixels: Vec<Color>,
+    bit_depth: BitDepth,
 }
 
 impl Screen {
@@ -16,9 +36,21 @@ impl Screen {
             height: height,
             width,
             pixels: vec![Color::new(0, 0, 0); height * width],
+            bit_depth: BitDepth::Eight,
         }
     }
 
+    /// Mark the values as running up to `bit_depth`'s maximum instead of 255; the writers
+    /// use this for the PPM maxval and the PNG sample size.
+    pub fn with_bit_depth(mut self, bit_depth: BitDepth) -> Screen {
+        self.bit_depth = bit_depth;
+        self
+    }
+
+    pub fn get_bit_depth(&self) -> BitDepth {
+        self.bit_depth
+    }
+
     pub fn get_width(&self) -> usize {
         self.width
     }
@@ -37,9 +69,131 @@ impl Screen {
         self.pixels[i * self.width + j] = color;
     }
 
+    /// Fold in another render of the same view, e.g. from another machine with another seed.
+    /// Both hold per-pixel averages (linear HDR), over `samples` and `other_samples` samples per
+    /// pixel; the result is the average over all of them.
+    pub fn merge(&mut self, other: &Screen, samples: usize, other_samples: usize) {
+        assert!(self.width == other.width && self.height == other.height);
+        let total = samples + other_samples;
+        if total == 0 {
+            return;
+        }
+        let (a, b) = (
+            samples as f64 / total as f64,
+            other_samples as f64 / total as f64,
+        );
+        for (pixel, theirs) in self.pixels.iter_mut().zip(&other.pixels) {
+            *pixel = a * *pixel + b * *theirs;
+        }
+    }
+
+    /// Split the rows into `bands` runs as even as possible, bottom first, for threads to fill
+    /// in at the same time. Each band borrows its own slice of the pixels, so no two threads
+    /// can ever write the same pixel and no locking (or `unsafe`) is needed.
+    pub fn bands_mut(&mut self, bands: usize) -> Vec<Band<'_>> {
+        assert!(bands > 0);
+        let (width, height) = (self.width, self.height);
+        let mut rest = self.pixels.as_mut_slice();
+        (0..bands)
+            .map(|t| {
+                let rows = t * height / bands..(t + 1) * height / bands;
+                let (pixels, tail) = std::mem::take(&mut rest).split_at_mut(rows.len() * width);
+                rest = tail;
+                Band {
+                    width,
+                    rows,
+                    pixels,
+                }
+            })
+            .collect()
+    }
+
+    /// Nearest-neighbour resample to `width` x `height`.
+    pub fn resize_nearest(&self, width: usize, height: usize) -> Screen {
+        let mut out = Screen::new(width, height).with_bit_depth(self.bit_depth);
+        for j in 0..height {
+            for i in 0..width {
+                let src_j = usize::min(j * self.height / height, self.height - 1);
+                let src_i = usize::min(i * self.width / width, self.width - 1);
+                out.update(j, i, *self.get(src_j, src_i));
+            }
+        }
+        out
+    }
+
+    /// `images` in a grid `cols` wide, reading left to right from the top-left corner, with
+    /// `gutter` black pixels between cells. Cells are as big as the largest image; smaller
+    /// ones sit in their cell's top-left corner. Takes the first image's bit depth.
+    pub fn contact_sheet(images: &[Screen], cols: usize, gutter: usize) -> Screen {
+        assert!(!images.is_empty() && cols > 0);
+        let cols = usize::min(cols, images.len());
+        let rows = images.len().div_ceil(cols);
+        let cell_width = images.iter().map(|image| image.width).max().unwrap();
+        let cell_height = images.iter().map(|image| image.height).max().unwrap();
+        let mut sheet = Screen::new(
+            cols * cell_width + (cols - 1) * gutter,
+            rows * cell_height + (rows - 1) * gutter,
+        )
+        .with_bit_depth(images[0].bit_depth);
+        for (k, image) in images.iter().enumerate() {
+            let left = (k % cols) * (cell_width + gutter);
+            // row 0 is the bottom of the picture, so count cells down from the top
+            let top = sheet.height - (k / cols) * (cell_height + gutter);
+            let bottom = top - image.height;
+            for j in 0..image.height {
+                for i in 0..image.width {
+                    sheet.update(bottom + j, left + i, *image.get(j, i));
+                }
+            }
+        }
+        sheet
+    }
+
+    /// Display values as RGBA8 bytes, row-major from the top-left corner.
+    /// 16-bit values are scaled down to 8.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let scale = 255.0 / self.bit_depth.max_value() as f64;
+        let mut bytes = Vec::with_capacity(self.width * self.height * 4);
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                let c = self.get(j, i);
+                for channel in [c.get_x(), c.get_y(), c.get_z()] {
+                    bytes.push((channel * scale).clamp(0.0, 255.0) as u8);
+                }
+                bytes.push(255);
+            }
+        }
+        bytes
+    }
+
+    // RGBA at the screen's own depth, big-endian for 16 bits as PNG wants
+    fn to_rgba_samples(&self) -> Vec<u8> {
+        if self.bit_depth == BitDepth::Eight {
+            return self.to_rgba8();
+        }
+        let max = self.bit_depth.max_value() as f64;
+        let mut bytes = Vec::with_capacity(self.width * self.height * 8);
+        for j in (0..self.height).rev() {
+            for i in 0..self.width {
+                let c = self.get(j, i);
+                for channel in [c.get_x(), c.get_y(), c.get_z(), max] {
+                    bytes.extend_from_slice(&(channel.clamp(0.0, max) as u16).to_be_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
     pub fn write_to_ppm(&self) {
         let mut stdout = std::io::stdout().lock();
-        writeln!(stdout, "P3\n{} {}\n255", self.width, self.height).unwrap();
+        writeln!(
+            stdout,
+            "P3\n{} {}\n{}",
+            self.width,
+            self.height,
+            self.bit_depth.max_value()
+        )
+        .unwrap();
         for j in (0..self.height).rev() {
             for i in 0..self.width {
                 writeln!(stdout, "{}", self.get(j, i).get_color()).unwrap();
@@ -49,7 +203,12 @@ impl Screen {
 
     pub fn write_to_ppm_file(&self, path: &str) {
         let mut output = String::new();
-        output += &format!("P3\n{} {}\n255\n", self.get_width(), self.get_height());
+        output += &format!(
+            "P3\n{} {}\n{}\n",
+            self.get_width(),
+            self.get_height(),
+            self.bit_depth.max_value()
+        );
         for j in (0..self.height).rev() {
             for i in 0..self.width {
                 output += &format!("{}\n", self.get(j, i).get_color());
@@ -58,39 +217,478 @@ impl Screen {
         fs::write(path, output).unwrap();
     }
 
-    pub fn from_ppm_p3(name: &str) -> Screen {
-        let mut file = File::open(name).expect("Couldn't open the file");
+    /// Write display values as an RGBA PNG of the screen's bit depth. The image data goes in
+    /// uncompressed deflate blocks, so files are big but any viewer can open them.
+    pub fn write_to_png_file(&self, path: &str) -> io::Result<()> {
+        let rgba = self.to_rgba_samples();
+        let row_bytes = rgba.len() / self.height;
+        let mut raw = Vec::with_capacity(rgba.len() + self.height);
+        for row in rgba.chunks(row_bytes) {
+            // filter type 0: the row as is
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        let depth = match self.bit_depth {
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+        };
+        // RGBA, deflate, no filtering variants, no interlace
+        header.extend_from_slice(&[depth, 6, 0, 0, 0]);
+
+        let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut png, b"IEND", &[]);
+        fs::write(path, png)
+    }
+
+    pub fn from_ppm_p3(name: &str) -> Result<Screen, ImageError> {
+        let mut file = File::open(name).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => ImageError::NotFound(name.to_string()),
+            _ => ImageError::Io(e),
+        })?;
         let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .expect("Trouble reading file...");
-        let mut contents = contents.split("\n");
-        contents.next();
-        let wh: Vec<&str> = contents.next().unwrap().split(" ").collect();
-        let width = wh[0].parse::<usize>().unwrap();
-        let height = wh[1].parse::<usize>().unwrap();
-        let mut pixels: Vec<Color> = vec![Color::new(0, 0, 0); height * width];
-        contents.next();
-        let nums: Vec<&str> = contents.map(|l| l.split_whitespace()).flatten().collect();
-        let mut num_iter = nums.iter();
-        for j in 0..height {
-            for i in 0..width {
-                let (x, y, z) = (
-                    num_iter.next().unwrap(),
-                    num_iter.next().unwrap(),
-                    num_iter.next().unwrap(),
-                );
-
-                pixels[j * width + i] = Color::new(
-                    x.parse::<f64>().unwrap(),
-                    y.parse::<f64>().unwrap(),
-                    z.parse::<f64>().unwrap(),
-                );
-            }
+        file.read_to_string(&mut contents).map_err(ImageError::Io)?;
+        // (line number, token) so parse errors can point at the offending spot
+        let mut tokens = contents
+            .lines()
+            .enumerate()
+            .flat_map(|(n, l)| l.split_whitespace().map(move |t| (n + 1, t)));
+
+        match tokens.next() {
+            Some((_, "P3")) => (),
+            Some((_, magic)) => return Err(ImageError::BadMagic(magic.to_string())),
+            None => return Err(ImageError::BadMagic(String::new())),
         }
-        Screen {
+        let mut next_number = |expected: usize, found: usize| -> Result<f64, ImageError> {
+            let (line, token) = tokens
+                .next()
+                .ok_or(ImageError::Truncated { expected, found })?;
+            token.parse::<f64>().map_err(|_| ImageError::Parse {
+                line,
+                token: token.to_string(),
+            })
+        };
+        let width = next_number(3, 0)? as usize;
+        let height = next_number(3, 1)? as usize;
+        next_number(3, 2)?;
+
+        let expected = height * width * 3;
+        let numbers: Vec<(usize, &str)> = tokens.take(expected).collect();
+        if numbers.len() < expected {
+            return Err(ImageError::Truncated {
+                expected,
+                found: numbers.len(),
+            });
+        }
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Ok(Screen {
             width,
             height,
-            pixels,
+            pixels: parse_pixels(&numbers, threads)?,
+            bit_depth: BitDepth::Eight,
+        })
+    }
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+// a zlib stream of uncompressed deflate blocks, each holding at most 65535 bytes
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = match data.is_empty() {
+        true => vec![&[]],
+        false => data.chunks(65535).collect(),
+    };
+    for (n, block) in blocks.iter().enumerate() {
+        out.push((n + 1 == blocks.len()) as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+/// Parse `r g b` triples, split into contiguous chunks across `threads` workers.
+/// Chunks are joined back in order, and the first bad token in file order is the one reported.
+fn parse_pixels(numbers: &[(usize, &str)], threads: usize) -> Result<Vec<Color>, ImageError> {
+    let parse_chunk = |chunk: &[(usize, &str)]| -> Result<Vec<Color>, ImageError> {
+        let mut values = chunk.iter().map(|&(line, token)| {
+            token.parse::<f64>().map_err(|_| ImageError::Parse {
+                line,
+                token: token.to_string(),
+            })
+        });
+        let mut pixels = Vec::with_capacity(chunk.len() / 3);
+        for _ in 0..chunk.len() / 3 {
+            pixels.push(Color::new(
+                values.next().unwrap()?,
+                values.next().unwrap()?,
+                values.next().unwrap()?,
+            ));
+        }
+        Ok(pixels)
+    };
+
+    let pixel_count = numbers.len() / 3;
+    let per_thread = usize::max(1, pixel_count.div_ceil(usize::max(1, threads)));
+    if per_thread >= pixel_count {
+        return parse_chunk(numbers);
+    }
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = numbers
+            .chunks(per_thread * 3)
+            .map(|chunk| scope.spawn(move || parse_chunk(chunk)))
+            .collect();
+        let mut pixels = Vec::with_capacity(pixel_count);
+        for worker in workers {
+            pixels.extend(worker.join().unwrap()?);
+        }
+        Ok(pixels)
+    })
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    NotFound(String),
+    Io(io::Error),
+    /// The file doesn't start with the `P3` magic number; holds what it started with.
+    BadMagic(String),
+    /// A token that should have been a number, with the 1-based line it was on.
+    Parse {
+        line: usize,
+        token: String,
+    },
+    /// The file ended after `found` of the `expected` numbers.
+    Truncated {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::NotFound(path) => write!(f, "image file not found: {}", path),
+            ImageError::Io(e) => write!(f, "couldn't read image: {}", e),
+            ImageError::BadMagic(magic) => write!(f, "expected P3 ppm, found {:?}", magic),
+            ImageError::Parse { line, token } => {
+                write!(f, "line {}: {:?} is not a number", line, token)
+            }
+            ImageError::Truncated { expected, found } => {
+                write!(
+                    f,
+                    "expected {} numbers, file ended after {}",
+                    expected, found
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Some whole rows of a `Screen`, from `Screen::bands_mut`. Rows keep their numbering in the
+/// full image.
+pub struct Band<'a> {
+    width: usize,
+    rows: Range<usize>,
+    pixels: &'a mut [Color],
+}
+
+impl Band<'_> {
+    pub fn get_rows(&self) -> Range<usize> {
+        self.rows.clone()
+    }
+
+    pub fn update(&mut self, i: usize, j: usize, color: Color) {
+        assert!(self.rows.contains(&i) && j < self.width);
+        self.pixels[(i - self.rows.start) * self.width + j] = color;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_temp(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn ppm_round_trip() {
+        let path = write_temp("screen_round_trip.ppm", "P3\n2 1\n255\n1 2 3\n4 5 6\n");
+        let screen = Screen::from_ppm_p3(&path).unwrap();
+        assert_eq!(screen.get_width(), 2);
+        assert_eq!(screen.get_height(), 1);
+        assert_eq!(*screen.get(0, 1), Color::new(4, 5, 6));
+    }
+
+    #[test]
+    fn parallel_parse_matches_sequential() {
+        let (width, height) = (37, 23);
+        let mut contents = format!("P3\n{} {}\n255\n", width, height);
+        for n in 0..width * height {
+            contents += &format!("{} {} {}\n", n % 256, (n * 7) % 256, (n * 13) % 256);
+        }
+        let path = write_temp("screen_parallel.ppm", &contents);
+        let loaded = Screen::from_ppm_p3(&path).unwrap();
+
+        let numbers: Vec<(usize, &str)> = contents
+            .lines()
+            .enumerate()
+            .skip(3)
+            .flat_map(|(n, l)| l.split_whitespace().map(move |t| (n + 1, t)))
+            .collect();
+        let sequential = parse_pixels(&numbers, 1).unwrap();
+        for threads in [2, 5, 64] {
+            let parallel = parse_pixels(&numbers, threads).unwrap();
+            assert_eq!(parallel.len(), sequential.len());
+            for (a, b) in parallel.iter().zip(&sequential) {
+                assert_eq!(a, b);
+            }
+        }
+        for (a, b) in loaded.pixels.iter().zip(&sequential) {
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn parallel_parse_reports_first_bad_token() {
+        let numbers = [
+            (4, "1"),
+            (4, "2"),
+            (4, "3"),
+            (5, "oops"),
+            (5, "5"),
+            (5, "6"),
+            (6, "bad"),
+            (6, "8"),
+            (6, "9"),
+        ];
+        for threads in [1, 3] {
+            assert!(matches!(
+                parse_pixels(&numbers, threads),
+                Err(ImageError::Parse { line: 5, token }) if token == "oops"
+            ));
+        }
+    }
+
+    #[test]
+    fn rgba8_starts_at_top_left() {
+        let mut screen = Screen::new(2, 2);
+        // row 0 is the bottom of the image
+        screen.update(1, 0, Color::new(10, 20, 30));
+        screen.update(0, 1, Color::new(300, -5, 7));
+        let bytes = screen.to_rgba8();
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(&bytes[0..4], &[10, 20, 30, 255]);
+        assert_eq!(&bytes[12..16], &[255, 0, 7, 255]);
+    }
+
+    #[test]
+    fn png_has_valid_chunks() {
+        // the standard check value for CRC-32
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        let mut screen = Screen::new(3, 2);
+        screen.update(1, 0, Color::new(10, 20, 30));
+        let path = std::env::temp_dir().join("screen_small.png");
+        let path = path.to_str().unwrap();
+        screen.write_to_png_file(path).unwrap();
+        let png = fs::read(path).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 3, 0, 0, 0, 2]);
+        // two rows of a filter byte plus 3 RGBA pixels, in one stored block
+        let idat = 8 + 25;
+        assert_eq!(&png[idat + 4..idat + 8], b"IDAT");
+        let data = &png[idat + 8..];
+        assert_eq!(&data[..3], &[0x78, 0x01, 1]);
+        assert_eq!(&data[3..5], &26u16.to_le_bytes());
+        // top-left pixel, right after the first filter byte
+        assert_eq!(&data[8..12], &[10, 20, 30, 255]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn sixteen_bit_headers() {
+        let mut screen = Screen::new(1, 1).with_bit_depth(BitDepth::Sixteen);
+        screen.update(0, 0, Color::new(65535, 256, 0));
+        let dir = std::env::temp_dir();
+        let ppm = dir.join("screen_16.ppm");
+        screen.write_to_ppm_file(ppm.to_str().unwrap());
+        assert_eq!(
+            fs::read_to_string(&ppm).unwrap(),
+            "P3\n1 1\n65535\n65535 256 0\n"
+        );
+
+        let png = dir.join("screen_16.png");
+        screen.write_to_png_file(png.to_str().unwrap()).unwrap();
+        let png = fs::read(&png).unwrap();
+        assert_eq!(png[24], 16);
+        // past the zlib and block headers and the filter byte, 16-bit big-endian samples
+        let data = &png[8 + 25 + 8..];
+        assert_eq!(&data[8..16], &[255, 255, 1, 0, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn merge_weights_by_samples() {
+        let mut a = Screen::new(1, 1);
+        a.update(0, 0, Color::new(0, 4, 8));
+        let mut b = Screen::new(1, 1);
+        b.update(0, 0, Color::new(4, 8, 12));
+        a.merge(&b, 100, 300);
+        assert_eq!(*a.get(0, 0), Color::new(3, 7, 11));
+    }
+
+    #[test]
+    fn resize_nearest_repeats_pixels() {
+        let mut small = Screen::new(2, 1);
+        small.update(0, 0, Color::new(1, 1, 1));
+        small.update(0, 1, Color::new(2, 2, 2));
+        let big = small.resize_nearest(4, 2);
+        assert_eq!(*big.get(1, 1), Color::new(1, 1, 1));
+        assert_eq!(*big.get(0, 2), Color::new(2, 2, 2));
+    }
+
+    #[test]
+    fn missing_file() {
+        let result = Screen::from_ppm_p3("definitely/not/here.ppm");
+        assert!(matches!(result, Err(ImageError::NotFound(_))));
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let path = write_temp("screen_bad_magic.ppm", "P6\n2 1\n255\n");
+        assert!(matches!(
+            Screen::from_ppm_p3(&path),
+            Err(ImageError::BadMagic(m)) if m == "P6"
+        ));
+
+        let path = write_temp("screen_bad_size.ppm", "P3\n2 x\n255\n");
+        assert!(matches!(
+            Screen::from_ppm_p3(&path),
+            Err(ImageError::Parse { line: 2, token }) if token == "x"
+        ));
+
+        let path = write_temp("screen_truncated.ppm", "P3\n2 1\n255\n1 2 3\n");
+        assert!(matches!(
+            Screen::from_ppm_p3(&path),
+            Err(ImageError::Truncated {
+                expected: 6,
+                found: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn contact_sheet_tiles_in_reading_order() {
+        let tile = |value: f64| {
+            let mut screen = Screen::new(2, 2);
+            for j in 0..2 {
+                for i in 0..2 {
+                    screen.update(j, i, Color::new(value, j as f64, i as f64));
+                }
+            }
+            screen
+        };
+        let images: Vec<Screen> = (1..=4).map(|k| tile(k as f64)).collect();
+
+        let sheet = Screen::contact_sheet(&images, 2, 0);
+        assert_eq!((sheet.get_width(), sheet.get_height()), (4, 4));
+        // the first image is top-left, and rows are stored bottom up
+        assert_eq!(*sheet.get(3, 1), Color::new(1, 1, 1));
+        assert_eq!(*sheet.get(2, 0), Color::new(1, 0, 0));
+        assert_eq!(*sheet.get(3, 2), Color::new(2, 1, 0));
+        assert_eq!(*sheet.get(0, 0), Color::new(3, 0, 0));
+        assert_eq!(*sheet.get(1, 3), Color::new(4, 1, 1));
+
+        let spaced = Screen::contact_sheet(&images, 2, 1);
+        assert_eq!((spaced.get_width(), spaced.get_height()), (5, 5));
+        assert_eq!(*spaced.get(2, 2), Color::new(0, 0, 0));
+        assert_eq!(*spaced.get(4, 3), Color::new(2, 1, 0));
+        assert_eq!(*spaced.get(0, 4), Color::new(4, 0, 1));
+
+        let row = Screen::contact_sheet(&images, 8, 0);
+        assert_eq!((row.get_width(), row.get_height()), (8, 2));
+    }
+
+    #[test]
+    fn bands_written_from_threads_match_a_single_writer() {
+        let (width, height) = (7, 11);
+        let color = |j: usize, i: usize| Color::new(j as f64, i as f64, (j * width + i) as f64);
+
+        // one writer, fed pixels through a channel
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut sent = Screen::new(width, height);
+        std::thread::scope(|scope| {
+            for t in 0..3 {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    for k in (t..width * height).step_by(3) {
+                        sender.send((k / width, k % width)).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            for (j, i) in receiver {
+                sent.update(j, i, color(j, i));
+            }
+        });
+
+        for bands in [1, 2, 4, 11, 16] {
+            let mut screen = Screen::new(width, height);
+            let mut covered = 0;
+            std::thread::scope(|scope| {
+                for mut band in screen.bands_mut(bands) {
+                    covered += band.get_rows().len();
+                    scope.spawn(move || {
+                        for j in band.get_rows() {
+                            for i in 0..width {
+                                band.update(j, i, color(j, i));
+                            }
+                        }
+                    });
+                }
+            });
+            assert_eq!(covered, height);
+            assert!(screen.pixels == sent.pixels, "{} bands", bands);
         }
     }
 }
@@ -0,0 +1,155 @@
+//! Spectral rendering helpers. RGB colors are upsampled to smooth spectra over three
+//! overlapping bands, paths carry a few wavelengths, and the result is projected back to RGB.
+
+use crate::mutil::rng;
+use crate::vec3::Color;
+use rand::Rng;
+use std::sync::OnceLock;
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 720.0;
+/// Wavelengths carried by one path.
+pub const WAVELENGTHS: usize = 4;
+
+// band centres for red, green and blue, in nm
+const CENTERS: [f64; 3] = [610.0, 550.0, 465.0];
+const WIDTH: f64 = 40.0;
+
+/// Weight of each RGB band at `lambda`. The weights always sum to 1, so white upsamples
+/// to a flat spectrum and colors in [0, 1] stay in [0, 1].
+fn basis(lambda: f64) -> [f64; 3] {
+    let g = CENTERS.map(|c| f64::exp(-0.5 * ((lambda - c) / WIDTH).powi(2)));
+    let total = g[0] + g[1] + g[2];
+    g.map(|x| x / total)
+}
+
+/// Inverse of the bands' Gram matrix, so that projecting an upsampled color gives it back.
+fn dual() -> &'static [[f64; 3]; 3] {
+    static DUAL: OnceLock<[[f64; 3]; 3]> = OnceLock::new();
+    DUAL.get_or_init(|| {
+        let steps = 1000;
+        let mut m = [[0.0; 3]; 3];
+        for s in 0..steps {
+            let lambda = LAMBDA_MIN + (s as f64 + 0.5) / steps as f64 * (LAMBDA_MAX - LAMBDA_MIN);
+            let w = basis(lambda);
+            for j in 0..3 {
+                for k in 0..3 {
+                    m[j][k] += w[j] * w[k] / steps as f64;
+                }
+            }
+        }
+        invert(&m)
+    })
+}
+
+fn invert(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    let mut inv = [[0.0; 3]; 3];
+    for (j, row) in inv.iter_mut().enumerate() {
+        for (k, x) in row.iter_mut().enumerate() {
+            // cofactor of m[k][j]
+            let (r0, r1) = ((k + 1) % 3, (k + 2) % 3);
+            let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+            *x = (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) / det;
+        }
+    }
+    inv
+}
+
+/// Value of the spectrum upsampled from `c` at wavelength `lambda`.
+pub fn reflectance(c: &Color, lambda: f64) -> f64 {
+    let w = basis(lambda);
+    c.get_x() * w[0] + c.get_y() * w[1] + c.get_z() * w[2]
+}
+
+/// RGB contribution of one sample `value` at `lambda`, for `lambda` drawn uniformly from
+/// the visible range. Averaging these over many wavelengths projects the spectrum onto RGB.
+pub fn to_rgb(lambda: f64, value: f64) -> Color {
+    let w = basis(lambda);
+    let d = dual();
+    let band = |j: usize| value * (d[j][0] * w[0] + d[j][1] * w[1] + d[j][2] * w[2]);
+    Color::new(band(0), band(1), band(2))
+}
+
+/// Per-wavelength throughput and radiance along one path.
+pub struct SpectralPath {
+    lambdas: [f64; WAVELENGTHS],
+    throughput: [f64; WAVELENGTHS],
+    radiance: [f64; WAVELENGTHS],
+}
+
+impl SpectralPath {
+    /// Stratified wavelengths from one random offset, so every path covers the whole range.
+    pub fn sample() -> SpectralPath {
+        let offset = rng().gen::<f64>();
+        let span = (LAMBDA_MAX - LAMBDA_MIN) / WAVELENGTHS as f64;
+        let mut lambdas = [0.0; WAVELENGTHS];
+        for (k, lambda) in lambdas.iter_mut().enumerate() {
+            *lambda = LAMBDA_MIN + (k as f64 + offset) * span;
+        }
+        SpectralPath {
+            lambdas,
+            throughput: [1.0; WAVELENGTHS],
+            radiance: [0.0; WAVELENGTHS],
+        }
+    }
+
+    pub fn get_lambdas(&self) -> &[f64; WAVELENGTHS] {
+        &self.lambdas
+    }
+
+    /// How much of the light found further along the path still reaches the camera.
+    pub fn get_throughput(&self) -> &[f64; WAVELENGTHS] {
+        &self.throughput
+    }
+
+    /// Add light `emitted` (an RGB color, upsampled) weighted by the current throughput.
+    pub fn emit(&mut self, emitted: &Color) {
+        for k in 0..WAVELENGTHS {
+            self.radiance[k] += self.throughput[k] * reflectance(emitted, self.lambdas[k]);
+        }
+    }
+
+    pub fn attenuate(&mut self, attenuation: &[f64; WAVELENGTHS]) {
+        for (t, a) in self.throughput.iter_mut().zip(attenuation) {
+            *t *= a;
+        }
+    }
+
+    pub fn to_rgb(&self) -> Color {
+        let mut c = Color::new(0, 0, 0);
+        for k in 0..WAVELENGTHS {
+            c += to_rgb(self.lambdas[k], self.radiance[k]);
+        }
+        c / WAVELENGTHS as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(spectrum: impl Fn(f64) -> f64) -> Color {
+        let steps = 2000;
+        let mut c = Color::new(0, 0, 0);
+        for s in 0..steps {
+            let lambda = LAMBDA_MIN + (s as f64 + 0.5) / steps as f64 * (LAMBDA_MAX - LAMBDA_MIN);
+            c += to_rgb(lambda, spectrum(lambda));
+        }
+        c / steps as f64
+    }
+
+    #[test]
+    fn upsampling_round_trips() {
+        for c in [
+            Color::new(1, 1, 1),
+            Color::new(0.9, 0.2, 0.1),
+            Color::new(0.05, 0.3, 0.95),
+        ] {
+            assert!((project(|l| reflectance(&c, l)) - c).length() < 1e-6);
+        }
+        assert!((0.0..=1.0).contains(&reflectance(&Color::new(1, 0, 1), 550.0)));
+    }
+}
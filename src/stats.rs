@@ -0,0 +1,146 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Paths this long or longer share the last histogram bucket.
+pub const MAX_PATH_LENGTH: usize = 128;
+
+/// Counters shared by the render workers. Hand one to `Config::with_stats` inside an `Arc`
+/// and read it once the render returns.
+#[derive(Debug)]
+pub struct RenderStats {
+    samples: AtomicU64,
+    rays: AtomicU64,
+    hits: AtomicU64,
+    escapes: AtomicU64,
+    path_lengths: Vec<AtomicU64>,
+}
+
+impl Default for RenderStats {
+    fn default() -> RenderStats {
+        RenderStats {
+            samples: AtomicU64::new(0),
+            rays: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            escapes: AtomicU64::new(0),
+            path_lengths: (0..=MAX_PATH_LENGTH).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+impl RenderStats {
+    pub fn new() -> RenderStats {
+        RenderStats::default()
+    }
+
+    pub fn record_sample(&self) {
+        self.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// One ray segment traced against the world, and whether it hit anything.
+    pub fn record_ray(&self, hit: bool) {
+        self.rays.fetch_add(1, Ordering::Relaxed);
+        match hit {
+            true => self.hits.fetch_add(1, Ordering::Relaxed),
+            false => self.escapes.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// A camera path ended after `segments` rays, its camera ray included.
+    pub fn record_path(&self, segments: usize) {
+        self.path_lengths[usize::min(segments, MAX_PATH_LENGTH)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Camera samples taken.
+    pub fn get_samples(&self) -> u64 {
+        self.samples.load(Ordering::Relaxed)
+    }
+
+    /// Ray segments traced, camera rays and bounces alike.
+    pub fn get_rays(&self) -> u64 {
+        self.rays.load(Ordering::Relaxed)
+    }
+
+    pub fn get_hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn get_escapes(&self) -> u64 {
+        self.escapes.load(Ordering::Relaxed)
+    }
+
+    /// How many paths ended after each number of segments, up to the longest one seen.
+    pub fn get_path_lengths(&self) -> Vec<u64> {
+        let mut counts: Vec<u64> = self
+            .path_lengths
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed))
+            .collect();
+        while counts.last() == Some(&0) {
+            counts.pop();
+        }
+        counts
+    }
+
+    pub fn get_mean_path_length(&self) -> f64 {
+        let counts = self.get_path_lengths();
+        let paths: u64 = counts.iter().sum();
+        let segments: u64 = counts.iter().enumerate().map(|(n, c)| n as u64 * c).sum();
+        segments as f64 / u64::max(paths, 1) as f64
+    }
+
+    /// The path length histogram as text, one bar per length.
+    pub fn path_length_histogram(&self) -> String {
+        let counts = self.get_path_lengths();
+        let most = counts.iter().cloned().max().unwrap_or(0).max(1);
+        let mut out = format!("path lengths (mean {:.2}):\n", self.get_mean_path_length());
+        for (length, &count) in counts.iter().enumerate().skip(1) {
+            let bar = "#".repeat((40 * count).div_ceil(most) as usize);
+            out += &format!("{:>4} {:>10} {}\n", length, count, bar);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn counters_sum_across_threads() {
+        let stats = Arc::new(RenderStats::new());
+        let workers: Vec<_> = (0..4)
+            .map(|t| {
+                let stats = stats.clone();
+                thread::spawn(move || {
+                    for n in 0..1000 {
+                        stats.record_sample();
+                        stats.record_ray((n + t) % 2 == 0);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(stats.get_samples(), 4000);
+        assert_eq!(stats.get_rays(), 4000);
+        assert_eq!(stats.get_hits(), 2000);
+        assert_eq!(stats.get_escapes(), 2000);
+    }
+
+    #[test]
+    fn path_lengths_clamp_into_last_bucket() {
+        let stats = RenderStats::new();
+        for length in [1, 1, 3, MAX_PATH_LENGTH + 10] {
+            stats.record_path(length);
+        }
+        let counts = stats.get_path_lengths();
+        assert_eq!(counts.len(), MAX_PATH_LENGTH + 1);
+        assert_eq!(
+            (counts[1], counts[2], counts[3], counts[MAX_PATH_LENGTH]),
+            (2, 0, 1, 1)
+        );
+        assert!(stats.path_length_histogram().starts_with("path lengths"));
+    }
+}
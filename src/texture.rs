@@ -1,11 +1,34 @@
 use crate::mutil::clamp;
 use crate::perlin::Perlin;
-use crate::screen::Screen;
-use crate::vec3::{Color, Point3};
+use crate::screen::{ImageError, Screen};
+use crate::vec3::{Color, Point3, Vec3};
 use std::sync::Arc;
 
+/// How much surface one pixel covers around a texture lookup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Footprint {
+    /// Only how far the ray travelled; textures estimate the width from that.
+    Distance(f64),
+    /// The width across, in world units, from the ray's differentials.
+    Width(f64),
+}
+
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+
+    /// `value` on a surface facing `normal`, seen through a pixel covering `footprint`, for
+    /// textures that can pre-filter detail too fine to resolve there. Defaults to the
+    /// unfiltered value.
+    fn filtered_value(
+        &self,
+        u: f64,
+        v: f64,
+        p: &Point3,
+        _normal: &Vec3,
+        _footprint: Footprint,
+    ) -> Color {
+        self.value(u, v, p)
+    }
 }
 
 pub struct SolidColor {
@@ -30,16 +53,33 @@ impl Texture for SolidColor {
     }
 }
 
+/// Where a `Checker` lays out its squares.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckerSpace {
+    /// Sine pattern over the hit point's world position; cheap, but squares pinch near sphere poles.
+    World,
+    /// `bands` squares around in `u` and half as many from pole to pole in `v`, so they come out
+    /// square at a sphere's equator and meet up across its seam. `bands` must be even.
+    Uv { bands: usize },
+}
+
 pub struct Checker {
     even: Arc<Box<dyn Texture>>,
     odd: Arc<Box<dyn Texture>>,
+    space: CheckerSpace,
+    pixel_angle: Option<f64>,
 }
 
+// side of a `CheckerSpace::World` square: half the period of sin(10 x)
+const WORLD_SQUARE: f64 = std::f64::consts::PI / 10.0;
+
 impl Checker {
     pub fn new(even: Arc<Box<dyn Texture>>, odd: Arc<Box<dyn Texture>>) -> Checker {
         Checker {
             even: even.clone(),
             odd: odd.clone(),
+            space: CheckerSpace::World,
+            pixel_angle: None,
         }
     }
 
@@ -47,20 +87,157 @@ impl Checker {
         Checker {
             even: Arc::new(Box::new(SolidColor::new(even))),
             odd: Arc::new(Box::new(SolidColor::new(odd))),
+            space: CheckerSpace::World,
+            pixel_angle: None,
+        }
+    }
+
+    pub fn with_space(mut self, space: CheckerSpace) -> Checker {
+        if let CheckerSpace::Uv { bands } = space {
+            assert!(bands >= 2 && bands % 2 == 0);
         }
+        self.space = space;
+        self
+    }
+
+    /// Fade world-space squares toward the average of the two textures once a pixel,
+    /// `pixel_angle` radians across (about the camera's vfov over the image height), covers
+    /// a good part of one, so distant floors don't shimmer. Has no effect in UV space.
+    /// Rays with differentials give the pixel's width directly; `pixel_angle` is the fallback.
+    pub fn with_filtering(mut self, pixel_angle: f64) -> Checker {
+        assert!(pixel_angle > 0.0);
+        self.pixel_angle = Some(pixel_angle);
+        self
     }
 }
 
 impl Texture for Checker {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
-        let sines =
-            f64::sin(10.0 * p.get_x()) * f64::sin(10.0 * p.get_y()) * f64::sin(10.0 * p.get_z());
-        if sines < 0.0 {
+        let odd = match self.space {
+            CheckerSpace::World => {
+                let sines = f64::sin(10.0 * p.get_x())
+                    * f64::sin(10.0 * p.get_y())
+                    * f64::sin(10.0 * p.get_z());
+                sines < 0.0
+            }
+            CheckerSpace::Uv { bands } => {
+                let column = (u * bands as f64).floor() as i64;
+                let row = (v * (bands / 2) as f64).floor() as i64;
+                (column + row) % 2 != 0
+            }
+        };
+        if odd {
             self.odd.value(u, v, p)
         } else {
             self.even.value(u, v, p)
         }
     }
+
+    fn filtered_value(
+        &self,
+        u: f64,
+        v: f64,
+        p: &Point3,
+        _normal: &Vec3,
+        footprint: Footprint,
+    ) -> Color {
+        let width = match (self.pixel_angle, self.space, footprint) {
+            (Some(_), CheckerSpace::World, Footprint::Width(width)) => width,
+            (Some(angle), CheckerSpace::World, Footprint::Distance(distance)) => distance * angle,
+            _ => return self.value(u, v, p),
+        };
+        // sharp while a pixel spans under a quarter of a square, fully averaged from a whole one
+        let footprint = width / WORLD_SQUARE;
+        let blend = clamp((footprint - 0.25) / 0.75, 0.0, 1.0);
+        if blend == 0.0 {
+            return self.value(u, v, p);
+        }
+        let average = 0.5 * (self.even.value(u, v, p) + self.odd.value(u, v, p));
+        (1.0 - blend) * self.value(u, v, p) + blend * average
+    }
+}
+
+/// Splits the ground plane into `cell_size` squares (over x and z) and darkens each
+/// channel of `base` by up to `variation`, by a hash of the square's integer coordinates.
+/// Gives a field of boxes varied colors from one shared material. A surface lying on a cell
+/// wall takes the color of the cell behind it, the side its normal points away from.
+pub struct CellJitter {
+    base: Color,
+    cell_size: f64,
+    variation: f64,
+}
+
+impl CellJitter {
+    pub fn new(base: &Color, cell_size: f64, variation: f64) -> CellJitter {
+        assert!(cell_size > 0.0);
+        assert!((0.0..=1.0).contains(&variation));
+        CellJitter {
+            base: *base,
+            cell_size,
+            variation,
+        }
+    }
+
+    // nudged a millionth of a cell against `normal`, so points on a wall fall on its far side
+    fn cell(&self, p: &Point3, normal: &Vec3) -> (i64, i64) {
+        let index = |x: f64, n: f64| (x / self.cell_size - 1e-6 * n).floor() as i64;
+        (
+            index(p.get_x(), normal.get_x()),
+            index(p.get_z(), normal.get_z()),
+        )
+    }
+
+    fn color(&self, (x, z): (i64, i64)) -> Color {
+        let h = mix(mix(x as u64) ^ z as u64);
+        // one 16-bit slice of the hash per channel
+        let channel = |shift: u32| 1.0 - self.variation * ((h >> shift) & 0xffff) as f64 / 65535.0;
+        Color::new(
+            self.base.get_x() * channel(0),
+            self.base.get_y() * channel(16),
+            self.base.get_z() * channel(32),
+        )
+    }
+}
+
+// splitmix64's finalizer: neighboring inputs give unrelated outputs
+fn mix(mut h: u64) -> u64 {
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+impl Texture for CellJitter {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        self.color(self.cell(p, &Vec3::zero()))
+    }
+    fn filtered_value(
+        &self,
+        _u: f64,
+        _v: f64,
+        p: &Point3,
+        normal: &Vec3,
+        _footprint: Footprint,
+    ) -> Color {
+        self.color(self.cell(p, normal))
+    }
+}
+
+/// Another texture multiplied by a constant, e.g. to turn an image into a bright light.
+pub struct Scaled {
+    inner: Arc<Box<dyn Texture>>,
+    factor: f64,
+}
+
+impl Scaled {
+    pub fn new(inner: Arc<Box<dyn Texture>>, factor: f64) -> Scaled {
+        Scaled { inner, factor }
+    }
+}
+
+impl Texture for Scaled {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.factor * self.inner.value(u, v, p)
+    }
 }
 
 pub struct Noise {
@@ -75,6 +252,13 @@ impl Noise {
             scale,
         }
     }
+
+    /// Draw from a noise table of `points` gradients instead of the default 256; see
+    /// `Perlin::with_resolution`.
+    pub fn with_resolution(mut self, points: usize) -> Noise {
+        self.noise = Perlin::with_resolution(points);
+        self
+    }
 }
 
 impl Texture for Noise {
@@ -92,10 +276,40 @@ pub struct Image {
 }
 
 impl Image {
-    pub fn from_ppm(name: &str) -> Image {
-        Image {
-            data: Screen::from_ppm_p3(name),
+    /// Color texture: texels are taken to be sRGB-encoded and decoded to linear on load.
+    pub fn from_ppm(name: &str) -> Result<Image, ImageError> {
+        Ok(Image::from_texels(Screen::from_ppm_p3(name)?, true))
+    }
+
+    /// Data texture (roughness, normals, heights): texel values are used as stored.
+    pub fn from_ppm_linear(name: &str) -> Result<Image, ImageError> {
+        Ok(Image::from_texels(Screen::from_ppm_p3(name)?, false))
+    }
+
+    /// Texture from a linear render (as from `world::render_hdr`), so one render can be shown
+    /// inside another without going through a file. Values are used as rendered, unclamped.
+    pub fn from_screen(screen: &Screen) -> Image {
+        // renders keep row 0 at the bottom; images are stored top row first, like a PPM
+        let (width, height) = (screen.get_width(), screen.get_height());
+        let mut data = Screen::new(width, height);
+        for j in 0..height {
+            for i in 0..width {
+                data.update(height - 1 - j, i, *screen.get(j, i));
+            }
         }
+        Image { data }
+    }
+
+    // scale 0-255 texels to [0, 1] once up front, decoding sRGB if asked
+    fn from_texels(mut data: Screen, srgb: bool) -> Image {
+        let color_scale = 1.0 / 255.0;
+        for j in 0..data.get_height() {
+            for i in 0..data.get_width() {
+                let texel = color_scale * *data.get(j, i);
+                data.update(j, i, if srgb { texel.srgb_to_linear() } else { texel });
+            }
+        }
+        Image { data }
     }
 }
 
@@ -110,13 +324,140 @@ impl Texture for Image {
         i = i32::min(i, self.data.get_width() as i32 - 1);
         j = i32::min(j, self.data.get_height() as i32 - 1);
 
-        let color_scale = 1.0 / 255.0;
-        let pixel = self.data.get(j as usize, i as usize);
+        *self.data.get(j as usize, i as usize)
+    }
+}
 
-        Color::new(
-            color_scale * pixel.get_x(),
-            color_scale * pixel.get_y(),
-            color_scale * pixel.get_z(),
-        )
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::hit::{Hittable, HittableList};
+    use crate::ray::Ray;
+    use crate::vec3::Vec3;
+    use crate::world::{render_hdr, Config, Sky};
+
+    #[test]
+    fn uv_checker_alternates_around_equator() {
+        let (white, black) = (Color::new(1, 1, 1), Color::new(0, 0, 0));
+        let bands = 12;
+        let checker = Checker::from_colors(&white, &black).with_space(CheckerSpace::Uv { bands });
+        let origin = Point3::new(0, 0, 0);
+        // the middle of each band, just above the equator
+        let colors: Vec<Color> = (0..bands)
+            .map(|i| checker.value((i as f64 + 0.5) / bands as f64, 0.51, &origin))
+            .collect();
+        for i in 0..bands {
+            assert_ne!(colors[i], colors[(i + 1) % bands], "band {}", i);
+        }
+        let changes = (0..1000)
+            .filter(|&i| {
+                let at = |i: usize| checker.value(i as f64 / 1000.0, 0.51, &origin);
+                at(i) != at((i + 1) % 1000)
+            })
+            .count();
+        assert_eq!(changes, bands);
+    }
+
+    #[test]
+    fn cell_jitter_varies_by_cell() {
+        let base = Color::new(0.48, 0.83, 0.53);
+        let texture = CellJitter::new(&base, 100.0, 0.3);
+        let at = |x: f64, y: f64, z: f64| texture.value(0.0, 0.0, &Point3::new(x, y, z));
+
+        // anywhere on one box, top or side, is the same color
+        assert_eq!(at(10.0, 50.0, 10.0), at(90.0, 1.0, 40.0));
+        // a face on the wall between two cells takes the color of the one it faces away from
+        let wall = Point3::new(100.0, 20.0, 50.0);
+        let facing =
+            |n: Vec3| texture.filtered_value(0.0, 0.0, &wall, &n, Footprint::Distance(1.0));
+        assert_eq!(facing(Vec3::new(1, 0, 0)), at(50.0, 20.0, 50.0));
+        assert_eq!(facing(Vec3::new(-1, 0, 0)), at(150.0, 20.0, 50.0));
+        let colors: Vec<Color> = [(50.0, 50.0), (150.0, 50.0), (50.0, 150.0), (-50.0, -50.0)]
+            .iter()
+            .map(|&(x, z)| at(x, 20.0, z))
+            .collect();
+        for (i, a) in colors.iter().enumerate() {
+            for b in &colors[i + 1..] {
+                assert_ne!(a, b);
+            }
+            for (c, b) in a.iter().zip(base.iter()) {
+                assert!(c <= b && c >= 0.7 * b);
+            }
+        }
+    }
+
+    #[test]
+    fn filtered_checker_fades_to_average_far_away() {
+        let (white, black) = (Color::new(1, 1, 1), Color::new(0, 0, 0));
+        let checker = Checker::from_colors(&white, &black).with_filtering(0.001);
+        let gray = Color::splat(0.5);
+        // well inside a square
+        let (p, up) = (Point3::new(0.1, 0.1, 0.1), Vec3::new(0, 1, 0));
+        let square = checker.value(0.0, 0.0, &p);
+        assert!(square == white || square == black);
+
+        assert_eq!(
+            checker.filtered_value(0.0, 0.0, &p, &up, Footprint::Distance(10.0)),
+            square
+        );
+        let mid = checker.filtered_value(0.0, 0.0, &p, &up, Footprint::Distance(200.0));
+        assert!((mid - gray).length() > 0.01 && (mid - square).length() > 0.01);
+        assert_eq!(
+            checker.filtered_value(0.0, 0.0, &p, &up, Footprint::Distance(5000.0)),
+            gray
+        );
+        // unfiltered checkers ignore the distance
+        let sharp = Checker::from_colors(&white, &black);
+        assert_eq!(
+            sharp.filtered_value(0.0, 0.0, &p, &up, Footprint::Distance(5000.0)),
+            square
+        );
+    }
+
+    #[test]
+    fn color_textures_decode_srgb() {
+        let path = std::env::temp_dir().join("texture_mid_gray.ppm");
+        std::fs::write(&path, "P3\n1 1\n255\n128 128 128\n").unwrap();
+        let path = path.to_str().unwrap();
+        let p = Point3::new(0, 0, 0);
+
+        let color = Image::from_ppm(path).unwrap().value(0.5, 0.5, &p);
+        assert!((color.get_x() - 0.2158605).abs() < 1e-6);
+        assert_eq!(color.get_x(), color.get_z());
+
+        let data = Image::from_ppm_linear(path).unwrap().value(0.5, 0.5, &p);
+        assert_eq!(
+            data,
+            Color::new(128.0 / 255.0, 128.0 / 255.0, 128.0 / 255.0)
+        );
+    }
+
+    #[test]
+    fn rendered_screen_becomes_texture() {
+        // a sky that is blue above the horizon and red below, seen straight on
+        let sky: Sky = Arc::new(|r: &Ray| match r.get_direction().get_y() > 0.0 {
+            true => Color::new(0, 0, 1),
+            false => Color::new(1, 0, 0),
+        });
+        let cam = Arc::new(Camera::new(
+            Point3::new(0, 0, 0),
+            Point3::new(0, 0, -1),
+            Vec3::new(0, 1, 0),
+            60.0,
+            1.0,
+            0.0,
+            1.0,
+            0.0,
+            1.0,
+        ));
+        let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(Box::new(HittableList::new()));
+        let config = Config::new(1.0, 8, 1, 2, 1).with_sky(sky);
+        let screen = render_hdr(world, cam, Color::new(0, 0, 0), &config);
+
+        let texture = Image::from_screen(&screen);
+        let p = Point3::new(0, 0, 0);
+        assert_eq!(texture.value(0.5, 0.9, &p), Color::new(0, 0, 1));
+        assert_eq!(texture.value(0.5, 0.1, &p), Color::new(1, 0, 0));
     }
 }
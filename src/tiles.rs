@@ -0,0 +1,187 @@
+//! Tiled rendering with a cache, for re-rendering while tweaking: tiles whose inputs
+//! haven't changed since the last render are reused instead of traced again.
+
+use crate::camera::{hash_vec, Camera};
+use crate::hit::Hittable;
+use crate::screen::Screen;
+use crate::vec3::{Color, Vec3};
+use crate::world::{sample_pixel, Config};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Pixels `x0..x1` by `y0..y1` of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tile {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+impl Tile {
+    pub fn new(x0: usize, y0: usize, x1: usize, y1: usize) -> Tile {
+        assert!(x0 < x1 && y0 < y1);
+        Tile { x0, y0, x1, y1 }
+    }
+
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+}
+
+/// Cover a `width` x `height` image in `size` x `size` tiles, row by row; edge tiles are smaller.
+pub fn split(width: usize, height: usize, size: usize) -> Vec<Tile> {
+    assert!(size > 0);
+    let mut tiles = vec![];
+    for y0 in (0..height).step_by(size) {
+        for x0 in (0..width).step_by(size) {
+            tiles.push(Tile::new(
+                x0,
+                y0,
+                usize::min(x0 + size, width),
+                usize::min(y0 + size, height),
+            ));
+        }
+    }
+    tiles
+}
+
+/// Average radiance of each pixel in `tile`, row by row.
+pub fn render_tile(
+    world: &dyn Hittable,
+    cam: &Camera,
+    background: &Color,
+    config: &Config,
+    tile: &Tile,
+) -> Vec<Color> {
+    let samples = config.get_samples_per_pixel();
+    let mut pixels = Vec::with_capacity(tile.width() * tile.height());
+    for j in tile.y0..tile.y1 {
+        for i in tile.x0..tile.x1 {
+            let mut pixel = Vec3::new(0, 0, 0);
+            for n in 0..samples {
+                pixel += sample_pixel(world, cam, background, config, i, j, n);
+            }
+            pixels.push(pixel / samples as f64);
+        }
+    }
+    pixels
+}
+
+pub struct TileCache {
+    size: usize,
+    tiles: HashMap<u64, Vec<Color>>,
+}
+
+impl TileCache {
+    pub fn new(size: usize) -> TileCache {
+        assert!(size > 0);
+        TileCache {
+            size,
+            tiles: HashMap::new(),
+        }
+    }
+
+    /// Tiles kept from the last render.
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Render to a linear buffer like `world::render_hdr`, tracing only tiles not in the cache.
+    /// The scene itself can't be hashed, so `scene_key` stands in for it: change it whenever
+    /// the world changes. Only the tiles of this render are kept afterwards.
+    /// Preview, edge-aware and deadline settings are ignored.
+    pub fn render(
+        &mut self,
+        world: Arc<Box<dyn Hittable + Sync>>,
+        cam: Arc<Camera>,
+        background: Color,
+        config: &Config,
+        scene_key: u64,
+    ) -> Screen {
+        let config = &config.with_lights_from(world.as_ref().as_ref());
+        let (width, height) = (config.get_image_width(), config.get_image_height());
+        let mut inputs = DefaultHasher::new();
+        scene_key.hash(&mut inputs);
+        cam.hash(&mut inputs);
+        hash_vec(&background, &mut inputs);
+        config.hash_inputs(&mut inputs);
+
+        let tiles = split(width, height, self.size);
+        let keys: Vec<u64> = tiles
+            .iter()
+            .map(|tile| {
+                let mut hasher = inputs.clone();
+                tile.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+        let missing: Vec<usize> = (0..tiles.len())
+            .filter(|&k| !self.tiles.contains_key(&keys[k]))
+            .collect();
+
+        // workers pull tiles off a shared counter; with a seed every sample reseeds itself,
+        // so it doesn't matter which worker got which tile
+        let next = AtomicUsize::new(0);
+        let fresh: Vec<(usize, Vec<Color>)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..config.get_threads())
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = vec![];
+                        loop {
+                            let m = next.fetch_add(1, Ordering::Relaxed);
+                            if m >= missing.len() {
+                                break done;
+                            }
+                            let k = missing[m];
+                            let pixels = render_tile(
+                                world.as_ref().as_ref(),
+                                &cam,
+                                &background,
+                                config,
+                                &tiles[k],
+                            );
+                            done.push((k, pixels));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap())
+                .collect()
+        });
+
+        let mut old = std::mem::take(&mut self.tiles);
+        for (k, pixels) in fresh {
+            old.insert(keys[k], pixels);
+        }
+        let mut screen = Screen::new(width, height);
+        for (tile, key) in tiles.iter().zip(&keys) {
+            let pixels = old
+                .remove(key)
+                .expect("every tile is cached or freshly rendered");
+            for (n, color) in pixels.iter().enumerate() {
+                screen.update(
+                    tile.y0 + n / tile.width(),
+                    tile.x0 + n % tile.width(),
+                    *color,
+                );
+            }
+            self.tiles.insert(*key, pixels);
+        }
+        screen
+    }
+}
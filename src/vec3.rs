@@ -1,5 +1,6 @@
 use crate::mutil::clamp;
-use rand::{thread_rng, Rng};
+use crate::mutil::rng;
+use rand::Rng;
 use std::{fmt, ops};
 
 #[derive(Debug, Clone, Copy)]
@@ -9,6 +10,11 @@ pub type Color = Vec3;
 
 const COLOR_MAX: f64 = 255.9;
 
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+use scalar as backend;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+use simd as backend;
+
 impl Vec3 {
     pub fn get_x(&self) -> f64 {
         return self.0;
@@ -40,22 +46,49 @@ impl Vec3 {
         f64::sqrt(self.length_squared())
     }
 
-    pub fn dot(&self, &other: &Vec3) -> f64 {
-        self.get_x() * other.get_x() + self.get_y() * other.get_y() + self.get_z() * other.get_z()
+    pub fn dot(&self, other: &Vec3) -> f64 {
+        backend::dot(self, other)
     }
 
-    pub fn cross(&self, &other: &Vec3) -> Vec3 {
-        Vec3::new(
-            self.get_y() * other.get_z() - self.get_z() * other.get_y(),
-            self.get_z() * other.get_x() - self.get_x() * other.get_z(),
-            self.get_x() * other.get_y() - self.get_y() * other.get_x(),
-        )
+    pub fn cross(&self, other: &Vec3) -> Vec3 {
+        backend::cross(self, other)
     }
 
     pub fn unit(&self) -> Vec3 {
         *self / self.length()
     }
 
+    /// Every component within `eps` of `other`'s. Use instead of `==` on computed values.
+    pub fn approx_eq(&self, other: &Vec3, eps: f64) -> bool {
+        self.iter()
+            .zip(other.iter())
+            .all(|(a, b)| (a - b).abs() <= eps)
+    }
+
+    /// No component is NaN or infinite.
+    pub fn is_finite(&self) -> bool {
+        self.iter().all(f64::is_finite)
+    }
+
+    /// `e` raised to each component, e.g. Beer-Lambert transmittance from `-absorbance * distance`.
+    pub fn exp(&self) -> Vec3 {
+        self.map(f64::exp)
+    }
+
+    /// Each component raised to the power `e`.
+    pub fn powf(&self, e: f64) -> Vec3 {
+        self.map(|c| c.powf(e))
+    }
+
+    /// Componentwise square root, i.e. gamma-2 encoding.
+    pub fn sqrt(&self) -> Vec3 {
+        self.map(f64::sqrt)
+    }
+
+    fn map(&self, f: impl Fn(f64) -> f64) -> Vec3 {
+        Vec3(f(self.0), f(self.1), f(self.2))
+    }
+
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
         f64::abs(self.get_x()) < s && f64::abs(self.get_y()) < s && f64::abs(self.get_z()) < s
@@ -65,19 +98,28 @@ impl Vec3 {
         *self - 2.0 * self.dot(normal) * *normal
     }
 
+    /// The component of `self` along `other`; `other` needn't be normalized.
+    pub fn project_onto(&self, other: &Vec3) -> Vec3 {
+        (self.dot(other) / other.length_squared()) * *other
+    }
+
+    /// The component of `self` perpendicular to `other`, so that projection plus rejection
+    /// gives back `self`. Reflecting about the plane with normal `other` is `reflect`.
+    pub fn reject_from(&self, other: &Vec3) -> Vec3 {
+        *self - self.project_onto(other)
+    }
+
+    /// Rotate by `angle` radians about `axis` (right-handed), using Rodrigues' formula.
+    pub fn rotate_around(&self, axis: &Vec3, angle: f64) -> Vec3 {
+        let k = axis.unit();
+        let (sin, cos) = angle.sin_cos();
+        cos * *self + sin * k.cross(self) + (1.0 - cos) * k.dot(self) * k
+    }
+
     pub fn write_color(&self, samples_per_pixel: u32) {
         // TODO: take output stream as param
-        let mut r = self.get_x();
-        let mut g = self.get_y();
-        let mut b = self.get_z();
-
-        let scale = 1.0 / samples_per_pixel as f64;
-        r *= scale;
-        g *= scale;
-        b *= scale;
-        r = f64::sqrt(r);
-        g = f64::sqrt(g);
-        b = f64::sqrt(b);
+        let encoded = (*self * (1.0 / samples_per_pixel as f64)).sqrt();
+        let (r, g, b) = (encoded.get_x(), encoded.get_y(), encoded.get_z());
         println!(
             "{} {} {}",
             (COLOR_MAX * clamp(r, 0.0, 1.0)) as i32,
@@ -86,19 +128,21 @@ impl Vec3 {
         );
     }
 
+    /// Decode an sRGB-encoded color with channels in [0, 1] to linear light.
+    pub fn srgb_to_linear(&self) -> Color {
+        self.map(|c| {
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                f64::powf((c + 0.055) / 1.055, 2.4)
+            }
+        })
+    }
+
     pub fn get_normalized_color(&self, samples_per_pixel: u32) -> Color {
         // TODO: take output stream as param
-        let mut r = self.get_x();
-        let mut g = self.get_y();
-        let mut b = self.get_z();
-
-        let scale = 1.0 / samples_per_pixel as f64;
-        r *= scale;
-        g *= scale;
-        b *= scale;
-        r = f64::sqrt(r);
-        g = f64::sqrt(g);
-        b = f64::sqrt(b);
+        let encoded = (*self * (1.0 / samples_per_pixel as f64)).sqrt();
+        let (r, g, b) = (encoded.get_x(), encoded.get_y(), encoded.get_z());
         Color::new(
             (COLOR_MAX * clamp(r, 0.0, 1.0)) as i32,
             (COLOR_MAX * clamp(g, 0.0, 1.0)) as i32,
@@ -134,17 +178,27 @@ impl Vec3 {
     {
         Vec3(x.into(), y.into(), z.into())
     }
+
+    /// All three components set to `v`, e.g. a gray.
+    pub fn splat<T: Into<f64>>(v: T) -> Vec3 {
+        let v = v.into();
+        Vec3(v, v, v)
+    }
+
+    pub fn zero() -> Vec3 {
+        Vec3::splat(0)
+    }
+
+    pub fn one() -> Vec3 {
+        Vec3::splat(1)
+    }
 }
 
 impl ops::Mul for Vec3 {
     type Output = Vec3;
 
     fn mul(self, other: Vec3) -> Vec3 {
-        Vec3(
-            self.get_x() * other.get_x(),
-            self.get_y() * other.get_y(),
-            self.get_z() * other.get_z(),
-        )
+        backend::mul(&self, &other)
     }
 }
 
@@ -200,11 +254,7 @@ impl ops::Add for Vec3 {
     type Output = Vec3;
 
     fn add(self, other: Vec3) -> Vec3 {
-        Vec3(
-            self.get_x() + other.get_x(),
-            self.get_y() + other.get_y(),
-            self.get_z() + other.get_z(),
-        )
+        backend::add(&self, &other)
     }
 }
 
@@ -212,11 +262,7 @@ impl ops::Sub for Vec3 {
     type Output = Vec3;
 
     fn sub(self, other: Vec3) -> Vec3 {
-        Vec3(
-            self.get_x() - other.get_x(),
-            self.get_y() - other.get_y(),
-            self.get_z() - other.get_z(),
-        )
+        backend::sub(&self, &other)
     }
 }
 
@@ -271,12 +317,12 @@ impl fmt::Display for Vec3 {
 }
 
 pub fn random() -> Vec3 {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     Vec3::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>())
 }
 
 pub fn random_range(min: f64, max: f64) -> Vec3 {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     Vec3::new(
         rng.gen_range::<f64, ops::Range<f64>>(min..max),
         rng.gen_range::<f64, ops::Range<f64>>(min..max),
@@ -308,7 +354,7 @@ pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
 }
 
 pub fn random_in_unit_disk() -> Vec3 {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     loop {
         let p = Vec3::new(
             rng.gen_range::<f64, ops::Range<f64>>(-1.0..1.0),
@@ -340,6 +386,104 @@ impl<'a> Iterator for Vec3Iter<'a> {
     }
 }
 
+// Scalar math used when the `simd` feature is off (and as the reference for it when on).
+#[allow(dead_code)]
+mod scalar {
+    use super::Vec3;
+
+    pub fn dot(a: &Vec3, b: &Vec3) -> f64 {
+        a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+    }
+
+    pub fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
+        Vec3(
+            a.1 * b.2 - a.2 * b.1,
+            a.2 * b.0 - a.0 * b.2,
+            a.0 * b.1 - a.1 * b.0,
+        )
+    }
+
+    pub fn add(a: &Vec3, b: &Vec3) -> Vec3 {
+        Vec3(a.0 + b.0, a.1 + b.1, a.2 + b.2)
+    }
+
+    pub fn sub(a: &Vec3, b: &Vec3) -> Vec3 {
+        Vec3(a.0 - b.0, a.1 - b.1, a.2 - b.2)
+    }
+
+    pub fn mul(a: &Vec3, b: &Vec3) -> Vec3 {
+        Vec3(a.0 * b.0, a.1 * b.1, a.2 * b.2)
+    }
+}
+
+// SSE2 versions: x and y share a register, z stays scalar. The operations are done in the
+// same order as the scalar code so results match bit for bit.
+//
+// SAFETY (all `unsafe` blocks below): SSE2 is part of the x86_64 baseline, so the intrinsics
+// are always available on this target.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod simd {
+    use super::Vec3;
+    use std::arch::x86_64::*;
+
+    fn xy(v: &Vec3) -> __m128d {
+        unsafe { _mm_set_pd(v.1, v.0) }
+    }
+
+    fn store(xy: __m128d, z: f64) -> Vec3 {
+        let mut out = [0.0; 2];
+        unsafe { _mm_storeu_pd(out.as_mut_ptr(), xy) };
+        Vec3(out[0], out[1], z)
+    }
+
+    pub fn dot(a: &Vec3, b: &Vec3) -> f64 {
+        let xy_sum = unsafe {
+            let prod = _mm_mul_pd(xy(a), xy(b));
+            _mm_cvtsd_f64(_mm_add_sd(prod, _mm_unpackhi_pd(prod, prod)))
+        };
+        xy_sum + a.2 * b.2
+    }
+
+    pub fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
+        let xy = unsafe {
+            let lhs = _mm_mul_pd(_mm_set_pd(a.2, a.1), _mm_set_pd(b.0, b.2));
+            let rhs = _mm_mul_pd(_mm_set_pd(a.0, a.2), _mm_set_pd(b.2, b.1));
+            _mm_sub_pd(lhs, rhs)
+        };
+        store(xy, a.0 * b.1 - a.1 * b.0)
+    }
+
+    pub fn add(a: &Vec3, b: &Vec3) -> Vec3 {
+        store(unsafe { _mm_add_pd(xy(a), xy(b)) }, a.2 + b.2)
+    }
+
+    pub fn sub(a: &Vec3, b: &Vec3) -> Vec3 {
+        store(unsafe { _mm_sub_pd(xy(a), xy(b)) }, a.2 - b.2)
+    }
+
+    pub fn mul(a: &Vec3, b: &Vec3) -> Vec3 {
+        store(unsafe { _mm_mul_pd(xy(a), xy(b)) }, a.2 * b.2)
+    }
+}
+
+/// `assert!(a.approx_eq(&b, eps))`, printing both vectors when it fails. `eps` defaults to 1e-9.
+#[macro_export]
+macro_rules! assert_vec3_eq {
+    ($a:expr, $b:expr) => {
+        $crate::assert_vec3_eq!($a, $b, 1e-9)
+    };
+    ($a:expr, $b:expr, $eps:expr) => {{
+        let (a, b): (&$crate::vec3::Vec3, &$crate::vec3::Vec3) = (&$a, &$b);
+        assert!(
+            a.approx_eq(b, $eps),
+            "{} is not within {} of {}",
+            a,
+            $eps,
+            b
+        );
+    }};
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -362,6 +506,91 @@ mod tests {
         assert_eq!(-v2, Vec3::new(-5, -7, -4.1));
     }
 
+    #[test]
+    fn splat_zero_one() {
+        assert_eq!(Vec3::splat(5), Vec3::new(5, 5, 5));
+        assert_eq!(Vec3::splat(0.73), Vec3::new(0.73, 0.73, 0.73));
+        assert_eq!(Vec3::zero(), Vec3::new(0, 0, 0));
+        assert_eq!(Vec3::one(), Vec3::new(1, 1, 1));
+    }
+
+    #[test]
+    fn componentwise_exp_powf_sqrt() {
+        let v = Vec3::new(0.25, 2, -1.5);
+        assert_eq!(
+            v.exp(),
+            Vec3::new(f64::exp(0.25), f64::exp(2.0), f64::exp(-1.5))
+        );
+        let w = Vec3::new(0.25, 2, 1.5);
+        assert_eq!(
+            w.powf(2.4),
+            Vec3::new(
+                f64::powf(0.25, 2.4),
+                f64::powf(2.0, 2.4),
+                f64::powf(1.5, 2.4)
+            )
+        );
+        assert_eq!(Vec3::new(4, 0.25, 0).sqrt(), Vec3::new(2, 0.5, 0));
+        assert!(v.sqrt().get_z().is_nan());
+    }
+
+    #[test]
+    fn reflect_mirrors_about_the_normal() {
+        let n = Vec3::new(0, 1, 0);
+        let v = Vec3::new(1, -2, 0.5);
+        assert_eq!(v.reflect(&n), Vec3::new(1, 2, 0.5));
+        assert_eq!(v.reflect(&n).length(), v.length());
+        // the tangential part is untouched, the normal part flips
+        assert_eq!(v.reflect(&n).reject_from(&n), v.reject_from(&n));
+    }
+
+    #[test]
+    fn refract_obeys_snells_law() {
+        let n = Vec3::new(0, 1, 0);
+        for (angle, ratio) in [(0.3, 1.0 / 1.5), (1.0, 1.0 / 1.5), (0.5, 1.2)] {
+            let uv = Vec3::new(f64::sin(angle), -f64::cos(angle), 0);
+            let refracted = Vec3::refract(&uv, &n, ratio);
+            assert!((refracted.length() - 1.0).abs() < 1e-12);
+            let sin_out = refracted.get_x();
+            assert!((sin_out - ratio * f64::sin(angle)).abs() < 1e-12);
+            // still heading into the surface
+            assert!(refracted.get_y() < 0.0);
+        }
+        // straight on passes through undeflected
+        let down = Vec3::new(0, -1, 0);
+        assert_vec3_eq!(Vec3::refract(&down, &n, 1.0 / 1.5), down);
+    }
+
+    #[test]
+    fn approx_eq_tolerates_rounding_only() {
+        let v = Vec3::new(1, -2, 3);
+        assert!(v.approx_eq(&(v + Vec3::new(1e-12, -1e-12, 0)), 1e-9));
+        assert!(!v.approx_eq(&(v + Vec3::new(0, 1e-3, 0)), 1e-9));
+        assert!(!v.approx_eq(&Vec3::new(f64::NAN, -2, 3), 1e-9));
+        // 0.1 + 0.2 isn't 0.3 exactly
+        assert_ne!(Vec3::splat(0.1) + Vec3::splat(0.2), Vec3::splat(0.3));
+        assert_vec3_eq!(Vec3::splat(0.1) + Vec3::splat(0.2), Vec3::splat(0.3));
+    }
+
+    #[test]
+    fn project_and_reject() {
+        let v = Vec3::new(2, 2, 0);
+        let x = Vec3::new(3, 0, 0);
+        assert_eq!(v.project_onto(&x), Vec3::new(2, 0, 0));
+        assert_eq!(v.reject_from(&x), Vec3::new(0, 2, 0));
+        assert_eq!(v.project_onto(&x) + v.reject_from(&x), v);
+    }
+
+    #[test]
+    fn rotate_around_axis() {
+        let y = Vec3::new(1, 0, 0).rotate_around(&Vec3::new(0, 0, 1), std::f64::consts::FRAC_PI_2);
+        assert_vec3_eq!(y, Vec3::new(0, 1, 0), 1e-12);
+
+        // the axis needn't be normalized, and components along it are left alone
+        let v = Vec3::new(1, 2, 3).rotate_around(&Vec3::new(0, 0, 5), std::f64::consts::PI);
+        assert_vec3_eq!(v, Vec3::new(-1, -2, 3), 1e-12);
+    }
+
     #[test]
     fn add_assign() {
         let mut v1 = Vec3::new(3, 2, 1);
@@ -425,4 +654,24 @@ mod tests {
         assert_eq!(k.next(), Some((7.0, 9.0)));
         assert_eq!(k.next(), None);
     }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    #[test]
+    fn simd_matches_scalar() {
+        for _ in 0..1000 {
+            let a = random_range(-1e3, 1e3);
+            let b = random_range(-1e3, 1e3);
+            assert_eq!(simd::dot(&a, &b).to_bits(), scalar::dot(&a, &b).to_bits());
+            for (x, y) in [
+                (simd::cross(&a, &b), scalar::cross(&a, &b)),
+                (simd::add(&a, &b), scalar::add(&a, &b)),
+                (simd::sub(&a, &b), scalar::sub(&a, &b)),
+                (simd::mul(&a, &b), scalar::mul(&a, &b)),
+            ] {
+                for (p, q) in x.iter().zip(y.iter()) {
+                    assert_eq!(p.to_bits(), q.to_bits());
+                }
+            }
+        }
+    }
 }
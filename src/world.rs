@@ -1,28 +1,105 @@
 use crate::bvh::BvhNode;
-use crate::camera::Camera;
+use crate::camera::{hash_vec, Camera};
+use crate::filter::PixelFilter;
 use crate::hit::{
-    ConstantMedium, Dielectric, DiffuseLight, GravitySphere, Hittable, HittableList, Lambertian,
-    Material, Metal, MovingSphere, RectPrism, RotateY, Sphere, Translate, Triangle, XyRect, XzRect,
-    YzRect,
+    ConstantMedium, Dielectric, DiffuseLight, GravitySphere, HitRecord, Hittable, HittableList,
+    Interface, Lambertian, Material, MediumStack, Metal, MovingSphere, RectPrism, RotateY, Sphere,
+    Translate, Triangle, XyRect, XzRect, YzRect,
 };
 use crate::model::TriangleModel;
-use crate::ray::Ray;
-use crate::screen::Screen;
-use crate::texture::{Checker, Image, Noise, SolidColor};
+use crate::mutil::{self, rng};
+use crate::post::{self, Bloom, ColorSpace, Dither, ToneMap, Vignette};
+use crate::ray::{Ray, RayKind};
+use crate::sampler::Sampler;
+use crate::screen::{BitDepth, ImageError, Screen};
+use crate::spectrum::{self, SpectralPath};
+use crate::stats::RenderStats;
+use crate::texture::{CellJitter, Checker, Image, Noise, SolidColor};
 use crate::vec3::{random, random_range, Color, Point3, Vec3};
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 const THREADS: usize = 11;
 
+// a pixel's samples are summed in this many runs, each in order, and the runs are then added
+// in order; the runs can go to any thread without changing a seeded total
+const SAMPLE_CHUNKS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    /// Regular path tracing with the scene's materials.
+    Shaded,
+    /// Every non-emissive surface is shaded as a matte `Lambertian` of this color.
+    Clay(Color),
+    /// Triangle hits within `width` (in barycentric units) of an edge are drawn flat in `wire`.
+    Wireframe { wire: Color, width: f64 },
+    /// Primary hits are drawn flat as `(u, v, 0)`, to check texture mapping for seams.
+    Uv,
+}
+
+/// How `ray_color` finds light at diffuse bounces. Only RGB renders sample lights;
+/// spectral renders always follow the BRDF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LightSampling {
+    /// Follow the scattered ray and count whatever light it happens to hit.
+    #[default]
+    Brdf,
+    /// Also aim a shadow ray at a random point on a light; bounces then carry only indirect light.
+    Lights,
+    /// Take both samples and weight them with the power heuristic, so each covers
+    /// for the other where it's noisy.
+    Mis,
+}
+
+/// Radiance for rays that escape the scene, by ray.
+pub type Sky = Arc<dyn Fn(&Ray) -> Color + Send + Sync>;
+
+/// Called with the fraction of the render finished so far, from 0 up to 1.
+pub type Progress = Arc<dyn Fn(f64) + Send + Sync>;
+
+#[derive(Clone)]
 pub struct Config {
     aspect_ratio: f64,
     image_width: i32,
     samples_per_pixel: i32,
     max_depth: i32,
     threads: usize,
+    seed: Option<u64>,
+    flush_size: Option<usize>,
+    preview: Option<f64>,
+    edge_aware: bool,
+    // index of each pixel's first sample, so a later pass continues the earlier one's sequence
+    first_sample: usize,
+    deadline: Option<Duration>,
+    spectral: bool,
+    exposure: f64,
+    bloom: Option<Bloom>,
+    vignette: Option<Vignette>,
+    tone_map: ToneMap,
+    mode: RenderMode,
+    stats: Option<Arc<RenderStats>>,
+    sky: Option<Sky>,
+    sampler: Sampler,
+    progress: Option<Progress>,
+    min_bounces: Option<i32>,
+    stop_at_emission: bool,
+    normal_offset: f64,
+    dither: Option<Dither>,
+    light_sampling: LightSampling,
+    lights: Option<Arc<HittableList>>,
+    path_histogram: bool,
+    trace_paths: bool,
+    bit_depth: BitDepth,
+    color_space: ColorSpace,
+    direct_only: bool,
+    ray_differentials: bool,
+    filter: Option<Arc<dyn PixelFilter>>,
 }
 
 impl Config {
@@ -45,55 +122,734 @@ impl Config {
             samples_per_pixel,
             max_depth,
             threads,
+            seed: None,
+            flush_size: None,
+            preview: None,
+            edge_aware: false,
+            first_sample: 0,
+            deadline: None,
+            spectral: false,
+            exposure: 1.0,
+            bloom: None,
+            vignette: None,
+            tone_map: ToneMap::default(),
+            mode: RenderMode::Shaded,
+            stats: None,
+            sky: None,
+            sampler: Sampler::Random,
+            progress: None,
+            min_bounces: None,
+            stop_at_emission: false,
+            normal_offset: 1e-9,
+            dither: None,
+            light_sampling: LightSampling::Brdf,
+            lights: None,
+            path_histogram: false,
+            trace_paths: false,
+            bit_depth: BitDepth::Eight,
+            color_space: ColorSpace::Srgb,
+            direct_only: false,
+            ray_differentials: false,
+            filter: None,
+        }
+    }
+
+    pub fn get_image_width(&self) -> usize {
+        self.image_width as usize
+    }
+
+    pub fn get_image_height(&self) -> usize {
+        (self.image_width as f64 / self.aspect_ratio) as usize
+    }
+
+    pub fn get_samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel as usize
+    }
+
+    pub fn get_max_depth(&self) -> usize {
+        self.max_depth as usize
+    }
+
+    pub fn get_threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Fix the RNG seed so the same scene and config render the same image. Every sample
+    /// reseeds from the seed and its pixel and index, and each pixel's samples are summed in
+    /// order on one thread, so the result is bit-identical at any thread count.
+    pub fn with_seed(mut self, seed: u64) -> Config {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Change the image shape, e.g. to match a scene's camera.
+    pub fn with_aspect_ratio(mut self, aspect_ratio: f64) -> Config {
+        assert!(aspect_ratio > 0.0);
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    /// Workers count finished pixels towards progress in runs of `pixels` (default: one row).
+    pub fn with_flush_size(mut self, pixels: usize) -> Config {
+        assert!(pixels > 0);
+        self.flush_size = Some(pixels);
+        self
+    }
+
+    /// Quick look for focus tuning: render with resolution and samples scaled by `factor`
+    /// (in (0, 1]), then upscale to the full image size.
+    pub fn with_preview(mut self, factor: f64) -> Config {
+        assert!(factor > 0.0 && factor <= 1.0);
+        self.preview = Some(factor);
+        self
+    }
+
+    /// Spend the sample budget unevenly: one sample everywhere first, then the rest
+    /// concentrated on pixels where that pass shows a sharp luminance change.
+    pub fn with_edge_aware(mut self) -> Config {
+        self.edge_aware = true;
+        self
+    }
+
+    /// Stop sampling once `limit` has passed and return what has been rendered so far.
+    /// Pixels are sampled in rounds, so an early stop leaves them evenly (if lightly) sampled.
+    pub fn with_deadline(mut self, limit: Duration) -> Config {
+        self.deadline = Some(limit);
+        self
+    }
+
+    /// Trace a few wavelengths per path instead of RGB, so colored filters multiply
+    /// as spectra rather than as three channels.
+    pub fn with_spectral(mut self) -> Config {
+        self.spectral = true;
+        self
+    }
+
+    /// Russian roulette: after `bounces` full bounces, paths carrying little light are ended
+    /// at random, and the survivors are weighted up to keep the result unbiased.
+    /// With `bounces >= max_depth` no path is ever cut short.
+    pub fn with_min_bounces(mut self, bounces: i32) -> Config {
+        assert!(bounces >= 0);
+        self.min_bounces = Some(bounces);
+        self
+    }
+
+    /// End each path at the first light it hits, for a quick direct-lighting-only preview.
+    pub fn with_stop_at_emission(mut self) -> Config {
+        self.stop_at_emission = true;
+        self
+    }
+
+    /// Start each bounce `scale` times the hit point's largest coordinate (at least 1) off the
+    /// surface, on the side the new ray leaves from. Rounding error in hit points grows with
+    /// their distance from the origin, so this keeps grazing bounces in huge scenes from
+    /// re-hitting the surface they left. Defaults to 1e-9; 0 turns it off.
+    pub fn with_normal_offset(mut self, scale: f64) -> Config {
+        assert!(scale >= 0.0);
+        self.normal_offset = scale;
+        self
+    }
+
+    /// Sample the scene's emissive primitives directly at diffuse bounces; see `LightSampling`.
+    pub fn with_light_sampling(mut self, light_sampling: LightSampling) -> Config {
+        self.light_sampling = light_sampling;
+        self
+    }
+
+    /// Fast preview of the light placement: stop at the first diffuse surface with one light
+    /// sample, so everything lit only by bounce light stays black. Mirrors and glass are still
+    /// followed. Implies `LightSampling::Lights`.
+    pub fn with_direct_only(mut self) -> Config {
+        self.direct_only = true;
+        self
+    }
+
+    /// How lights are found at bounces; `with_direct_only` forces `Lights`.
+    pub fn get_light_sampling(&self) -> LightSampling {
+        match self.direct_only {
+            true => LightSampling::Lights,
+            false => self.light_sampling,
+        }
+    }
+
+    /// Linear multiplier applied to the radiance before any other post effect.
+    pub fn with_exposure(mut self, exposure: f64) -> Config {
+        assert!(exposure >= 0.0);
+        self.exposure = exposure;
+        self
+    }
+
+    /// Radial falloff toward the frame edges; see `Vignette::new`.
+    pub fn with_vignette(mut self, strength: f64) -> Config {
+        self.vignette = Some(Vignette::new(strength));
+        self
+    }
+
+    /// How radiance above the display range is handled; clamps at 1 by default.
+    pub fn with_tone_map(mut self, tone_map: ToneMap) -> Config {
+        self.tone_map = tone_map;
+        self
+    }
+
+    /// Quantize the final image to `levels` shades per channel, with dithering; see `Dither`.
+    pub fn with_dither(mut self, levels: usize) -> Config {
+        self.dither = Some(Dither::new(levels));
+        self
+    }
+
+    /// Write 16 bits per channel instead of 8, for smooth gradients without banding.
+    pub fn with_bit_depth(mut self, bit_depth: BitDepth) -> Config {
+        self.bit_depth = bit_depth;
+        self
+    }
+
+    /// `Linear` writes radiance as is, clamped, for compositing; tone map and gamma are skipped.
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Config {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Give camera rays differentials (`Ray::footprint`) so textures can tell how much
+    /// surface a pixel covers, as filtered `Checker`s do. Off by default.
+    pub fn with_ray_differentials(mut self) -> Config {
+        self.ray_differentials = true;
+        self
+    }
+
+    /// Glow around pixels whose luminance exceeds `threshold`.
+    pub fn with_bloom(mut self, threshold: f64, intensity: f64) -> Config {
+        self.bloom = Some(Bloom::new(threshold, intensity));
+        self
+    }
+
+    /// Splat every sample onto the pixels within `filter`'s radius instead of averaging it
+    /// into its own pixel alone. Tiled renders and `debug_pixel` keep the plain average.
+    pub fn with_filter(mut self, filter: Arc<dyn PixelFilter>) -> Config {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// How pixel, lens and time positions are chosen for each sample.
+    pub fn with_sampler(mut self, sampler: Sampler) -> Config {
+        self.sampler = sampler;
+        self
+    }
+
+    /// Use `sky` for escaped rays instead of the flat background color.
+    pub fn with_sky(mut self, sky: Sky) -> Config {
+        self.sky = Some(sky);
+        self
+    }
+
+    /// Report render progress to `progress` instead of printing it to stderr.
+    pub fn with_progress(mut self, progress: Progress) -> Config {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Print how many bounces each path took to stderr once the render is done,
+    /// e.g. to see whether `max_depth` or Russian roulette is cutting paths short.
+    pub fn with_path_histogram(mut self) -> Config {
+        self.path_histogram = true;
+        self
+    }
+
+    /// Print every bounce of every path to stderr; meant for `debug_pixel`, not whole images.
+    pub fn with_path_trace(mut self) -> Config {
+        self.trace_paths = true;
+        self
+    }
+
+    /// Count samples, rays and hits into `stats` while rendering.
+    pub fn with_stats(mut self, stats: Arc<RenderStats>) -> Config {
+        self.stats = Some(stats);
+        self
+    }
+
+    pub fn with_mode(mut self, mode: RenderMode) -> Config {
+        self.mode = mode;
+        self
+    }
+
+    /// Feed everything that changes the radiance of a pixel into `state`. Post effects,
+    /// progress and deadline settings are left out; custom skies and filters hash by identity.
+    pub(crate) fn hash_inputs<H: Hasher>(&self, state: &mut H) {
+        self.aspect_ratio.to_bits().hash(state);
+        self.image_width.hash(state);
+        self.samples_per_pixel.hash(state);
+        self.max_depth.hash(state);
+        self.seed.hash(state);
+        self.spectral.hash(state);
+        self.sampler.hash(state);
+        self.min_bounces.hash(state);
+        self.stop_at_emission.hash(state);
+        self.normal_offset.to_bits().hash(state);
+        self.light_sampling.hash(state);
+        self.direct_only.hash(state);
+        self.ray_differentials.hash(state);
+        match self.mode {
+            RenderMode::Shaded => 0.hash(state),
+            RenderMode::Clay(color) => {
+                1.hash(state);
+                hash_vec(&color, state);
+            }
+            RenderMode::Wireframe { wire, width } => {
+                2.hash(state);
+                hash_vec(&wire, state);
+                width.to_bits().hash(state);
+            }
+            RenderMode::Uv => 3.hash(state),
+        }
+        self.sky
+            .as_ref()
+            .map(|sky| Arc::as_ptr(sky) as *const () as usize)
+            .hash(state);
+        self.filter
+            .as_ref()
+            .map(|filter| Arc::as_ptr(filter) as *const () as usize)
+            .hash(state);
+    }
+
+    /// This config with `world`'s lights gathered, if light sampling needs them.
+    pub(crate) fn with_lights_from(&self, world: &dyn Hittable) -> Config {
+        let mut config = self.clone();
+        if config.get_light_sampling() != LightSampling::Brdf && config.lights.is_none() {
+            let mut lights = HittableList::new();
+            world.collect_lights(&mut lights);
+            config.lights = Some(Arc::new(lights));
+        }
+        config
+    }
+
+    /// The material that stands in for every surface in `RenderMode::Clay`.
+    fn clay(&self) -> Option<Arc<Box<dyn Material>>> {
+        match self.mode {
+            RenderMode::Clay(color) => Some(Arc::new(Box::new(Lambertian::new(color)))),
+            _ => None,
         }
     }
 }
 
-fn ray_color(
-    &r: &Ray,
-    background: &Color,
-    world: &Box<dyn Hittable + Sync>,
-    mut depth: i32,
+// lights keep their own material so emission survives the clay override
+fn material_for(
+    clay: &Option<Arc<Box<dyn Material>>>,
+    rec: &HitRecord,
+    emitted: &Color,
+) -> Arc<Box<dyn Material>> {
+    match clay {
+        Some(clay) if *emitted == Color::new(0, 0, 0) => clay.clone(),
+        _ => rec.get_material(),
+    }
+}
+
+/// Closest hit along a ray from `origin` toward `direction`, no further than `t_max`.
+/// `direction` is normalized, so the record's `t` is the distance from `origin`.
+pub fn raycast(
+    world: &dyn Hittable,
+    origin: Point3,
+    direction: Vec3,
+    t_max: f64,
+) -> Option<HitRecord> {
+    world.hit(&Ray::new(&origin, &direction.unit(), 0.0), 0.001, t_max)
+}
+
+/// Check that every object in `list` has a bounding box over the shutter interval `[0, 1]`,
+/// which `BvhNode::new` needs. Each error names an offender by index and type.
+pub fn validate(list: &HittableList) -> Result<(), Vec<String>> {
+    let errors: Vec<String> = list
+        .get_objects()
+        .iter()
+        .enumerate()
+        .filter(|(_, obj)| obj.bounding_box(0.0, 1.0).is_none())
+        .map(|(i, obj)| format!("object {} ({}) has no bounding box", i, obj.name()))
+        .collect();
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+/// A camera looking at the middle of `world`'s bounds over the shutter interval
+/// `[time0, time1]`, from a little above and in front (+z), backed off far enough that the
+/// bounds' enclosing sphere fits in the frame. `None` if the world has no bounding box.
+pub fn auto_frame(
+    world: &dyn Hittable,
+    aspect_ratio: f64,
+    time0: f64,
+    time1: f64,
+) -> Option<Camera> {
+    let vfov: f64 = 40.0;
+    let bbox = world.bounding_box(time0, time1)?;
+    let center = (*bbox.get_min() + *bbox.get_max()) / 2.0;
+    let radius = f64::max((*bbox.get_max() - center).length(), 1e-3);
+    // the narrower of the two fields of view is the one that limits the fit
+    let half_v = f64::to_radians(vfov) / 2.0;
+    let half_h = f64::atan(aspect_ratio * f64::tan(half_v));
+    let distance = radius / f64::sin(f64::min(half_v, half_h));
+    Some(Camera::orbit(
+        center,
+        distance,
+        -90.0,
+        20.0,
+        vfov,
+        aspect_ratio,
+        0.0,
+        time0,
+        time1,
+    ))
+}
+
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+// light reaching `rec` along a shadow ray aimed at a random point on `lights`, divided by
+// the scattering pdf so the caller can weight it by attenuation like any other bounce
+fn sample_light(
+    lights: &HittableList,
+    world: &dyn Hittable,
+    r_in: &Ray,
+    rec: &HitRecord,
+    material: &Arc<Box<dyn Material>>,
+    config: &Config,
 ) -> Color {
-    // TODO: make this iterative instead of recursive
-    let mut product = Vec3::new(1, 1, 1);
-    let mut output = Vec3::new(0, 0, 0);
+    let black = Color::zero();
+    let Some(direction) = lights.random_direction(rec.get_p()) else {
+        return black;
+    };
+    let aimed = Ray::new(rec.get_p(), &direction, r_in.get_time()).with_kind(RayKind::Shadow);
+    let shadow = offset_origin(&aimed, rec, config.normal_offset);
+    let light_pdf = lights.pdf_value(shadow.get_origin(), &direction);
+    let scatter_pdf = material.scattering_pdf(r_in, rec, &shadow).unwrap_or(0.0);
+    if light_pdf <= 0.0 || scatter_pdf <= 0.0 {
+        return black;
+    }
+    let hit = world.hit(&shadow, 0.001, f64::INFINITY);
+    if let Some(stats) = &config.stats {
+        stats.record_ray(hit.is_some());
+    }
+    let Some(light) = hit else {
+        return black;
+    };
+    let emitted = light.get_material().emitted_toward(
+        light.get_u(),
+        light.get_v(),
+        light.get_p(),
+        &(-direction),
+    );
+    let weight = match config.get_light_sampling() {
+        LightSampling::Mis => power_heuristic(light_pdf, scatter_pdf),
+        _ => 1.0,
+    };
+    weight * scatter_pdf / light_pdf * emitted
+}
+
+// nudge a bounce's origin off the surface it starts on, towards where it's heading
+fn offset_origin(scattered: &Ray, rec: &HitRecord, scale: f64) -> Ray {
+    let p = rec.get_p();
+    let magnitude = p.iter().fold(1.0, |m: f64, x| m.max(x.abs()));
+    let side = match scattered.get_direction().dot(rec.get_normal()) < 0.0 {
+        true => -1.0,
+        false => 1.0,
+    };
+    let origin = *scattered.get_origin() + (side * scale * magnitude) * *rec.get_normal();
+    Ray::new(&origin, scattered.get_direction(), scattered.get_time())
+        .with_kind(scattered.get_kind())
+}
+
+fn ray_color(&r: &Ray, background: &Color, world: &dyn Hittable, config: &Config) -> Color {
+    let mut product = Vec3::one();
+    let mut output = Vec3::zero();
     let mut current_ray = r;
+    let mut depth = config.max_depth;
+    let mut spectral = config.spectral.then(SpectralPath::sample);
+    let mut media = MediumStack::new();
+    // made once per path rather than stored next to `mode`, where the two could disagree
+    let clay = config.clay();
+    let lights = match (&config.lights, config.get_light_sampling(), config.spectral) {
+        (Some(lights), sampling, false) if sampling != LightSampling::Brdf => Some(lights),
+        _ => None,
+    };
+    // BRDF density of the current ray, if it left a surface that also took a light sample
+    let mut bounce_pdf: Option<f64> = None;
+    // segments traced when the path ends at `depth`; running out of depth leaves it at -1
+    let record_path = |depth: i32| {
+        if let Some(stats) = &config.stats {
+            stats.record_path((config.max_depth - depth.max(0)) as usize);
+        }
+    };
 
     loop {
         depth -= 1;
         if depth < 0 {
             break;
         }
-        match world.hit(&current_ray, 0.001, f64::INFINITY) {
-            Some(rec) => match rec.get_material().scatter(&current_ray, &rec) {
-                Some((scattered, attenuation)) => {
-                    let emitted = rec
-                        .get_material()
-                        .emitted(rec.get_u(), rec.get_v(), rec.get_p());
-                    output += emitted * product;
-                    product *= attenuation;
-                    current_ray = scattered;
+        let hit = world.hit(&current_ray, 0.001, f64::INFINITY);
+        if let Some(stats) = &config.stats {
+            stats.record_ray(hit.is_some());
+        }
+        match hit {
+            Some(rec) => {
+                if config.mode == RenderMode::Uv {
+                    record_path(depth);
+                    return Color::new(rec.get_u(), rec.get_v(), 0);
+                }
+                if let RenderMode::Wireframe { wire, width } = config.mode {
+                    let primary = depth == config.max_depth - 1;
+                    if primary && rec.edge_distance().is_some_and(|d| d < width) {
+                        record_path(depth);
+                        return wire;
+                    }
+                }
+                let emitted = rec.get_material().emitted_toward(
+                    rec.get_u(),
+                    rec.get_v(),
+                    rec.get_p(),
+                    &(-*current_ray.get_direction()),
+                );
+                let material = material_for(&clay, &rec, &emitted);
+                if config.trace_paths {
+                    eprintln!(
+                        "  hit t {:.6} at {} normal {} emitted {} throughput {}",
+                        rec.get_t(),
+                        rec.get_p(),
+                        rec.get_normal(),
+                        emitted,
+                        product
+                    );
+                }
+                // light the last bounce's shadow ray may have counted already
+                let weight = match (lights, bounce_pdf) {
+                    (Some(lights), Some(pdf)) if !emitted.near_zero() => {
+                        let light_pdf =
+                            lights.pdf_value(current_ray.get_origin(), current_ray.get_direction());
+                        match config.get_light_sampling() {
+                            LightSampling::Mis => power_heuristic(pdf, light_pdf),
+                            _ if light_pdf > 0.0 => 0.0,
+                            _ => 1.0,
+                        }
+                    }
+                    _ => 1.0,
+                };
+                // with the throughput up to this surface: its own attenuation only applies to
+                // light it scatters, which is added at later bounces
+                match &mut spectral {
+                    Some(path) => path.emit(&emitted),
+                    None => output += weight * emitted * product,
                 }
-                None => {
-                    let emitted = rec
-                        .get_material()
-                        .emitted(rec.get_u(), rec.get_v(), rec.get_p());
-                    output += emitted * product;
+                if config.stop_at_emission && !emitted.near_zero() {
                     break;
                 }
-            },
+                let secondary = |(ray, c): (Ray, Color)| (ray.with_kind(RayKind::Secondary), c);
+                let scattered = match material.medium() {
+                    Some(medium) => {
+                        let id = Arc::as_ptr(&material) as *const () as usize;
+                        let entering = rec.get_front_face();
+                        match media.interface(id, medium, entering) {
+                            Interface::Pass => {
+                                media.cross(id, medium, entering);
+                                // still the same ray as far as visibility goes
+                                let through = Ray::new(
+                                    rec.get_p(),
+                                    current_ray.get_direction(),
+                                    current_ray.get_time(),
+                                )
+                                .with_kind(current_ray.get_kind())
+                                .with_differential(current_ray.get_differential());
+                                Some((through, Color::new(1, 1, 1)))
+                            }
+                            Interface::Refract(ratio) => {
+                                let scattered = material
+                                    .scatter_with_ratio(&current_ray, &rec, ratio)
+                                    .map(secondary);
+                                if let Some((ray, _)) = &scattered {
+                                    // refracted rays end up on the far side of the normal
+                                    if ray.get_direction().dot(rec.get_normal()) < 0.0 {
+                                        media.cross(id, medium, entering);
+                                    }
+                                }
+                                scattered
+                            }
+                        }
+                    }
+                    None => material.scatter(&current_ray, &rec).map(secondary),
+                };
+                match scattered {
+                    Some((scattered, attenuation)) => {
+                        // a degenerate bounce would poison the whole pixel,
+                        // so the sample contributes nothing instead
+                        if !attenuation.is_finite() || !scattered.get_direction().is_finite() {
+                            record_path(depth);
+                            return Color::zero();
+                        }
+                        // no shadow ray at the last bounce, whose scattered ray wouldn't be traced
+                        // either, so both strategies see paths of the same lengths
+                        bounce_pdf = match lights {
+                            Some(lights) if depth > 0 || config.direct_only => {
+                                let pdf = material.scattering_pdf(&current_ray, &rec, &scattered);
+                                if pdf.is_some() {
+                                    output += product
+                                        * attenuation
+                                        * sample_light(
+                                            lights,
+                                            world,
+                                            &current_ray,
+                                            &rec,
+                                            &material,
+                                            config,
+                                        );
+                                }
+                                pdf
+                            }
+                            _ => None,
+                        };
+                        if config.direct_only && bounce_pdf.is_some() {
+                            break;
+                        }
+                        match &mut spectral {
+                            Some(path) => {
+                                let lambdas = *path.get_lambdas();
+                                path.attenuate(
+                                    &lambdas
+                                        .map(|l| material.spectral_attenuation(&attenuation, l)),
+                                );
+                            }
+                            None => product *= attenuation,
+                        }
+                        let bounces = config.max_depth - depth;
+                        if config.min_bounces.is_some_and(|min| bounces >= min) {
+                            // survive in proportion to the light the path can still carry
+                            let carried = match &spectral {
+                                Some(path) => {
+                                    path.get_throughput().iter().cloned().fold(0.0, f64::max)
+                                }
+                                None => product.iter().fold(0.0, f64::max),
+                            };
+                            let survive = f64::min(carried, 1.0);
+                            if rng().gen::<f64>() >= survive {
+                                break;
+                            }
+                            match &mut spectral {
+                                Some(path) => {
+                                    path.attenuate(&[1.0 / survive; spectrum::WAVELENGTHS])
+                                }
+                                None => product /= survive,
+                            }
+                        }
+                        current_ray = offset_origin(&scattered, &rec, config.normal_offset);
+                    }
+                    None => break,
+                }
+            }
             None => {
-                output += product * *background;
+                let sky = match &config.sky {
+                    Some(sky) => sky(&current_ray),
+                    None => *background,
+                };
+                if config.trace_paths {
+                    eprintln!("  miss toward {} sky {}", current_ray.get_direction(), sky);
+                }
+                match &mut spectral {
+                    Some(path) => path.emit(&sky),
+                    None => output += product * sky,
+                }
                 break;
             }
         }
     }
-    output
+    record_path(depth);
+    let color = match spectral {
+        Some(path) => path.to_rgb(),
+        None => output,
+    };
+    if color.is_finite() {
+        color
+    } else {
+        Color::zero()
+    }
+}
+
+/// Knobs for `gen_random_scene_with`. The defaults reproduce the book's cover scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RandomSceneParams {
+    grid: i32,
+    diffuse: f64,
+    metal: f64,
+    moving: f64,
+    radius: (f64, f64),
+    seed: Option<u64>,
+}
+
+impl Default for RandomSceneParams {
+    fn default() -> RandomSceneParams {
+        RandomSceneParams {
+            grid: 11,
+            diffuse: 0.3,
+            metal: 0.3,
+            moving: 0.8,
+            radius: (0.2, 0.2),
+            seed: None,
+        }
+    }
+}
+
+impl RandomSceneParams {
+    /// Scatter small spheres over a `2 * half_extent` square grid of cells, one per cell.
+    pub fn with_grid(mut self, half_extent: i32) -> RandomSceneParams {
+        assert!(half_extent >= 0);
+        self.grid = half_extent;
+        self
+    }
+
+    /// Chance of a small sphere being diffuse or metal; the rest are glass.
+    pub fn with_mix(mut self, diffuse: f64, metal: f64) -> RandomSceneParams {
+        assert!(diffuse >= 0.0 && metal >= 0.0 && diffuse + metal <= 1.0);
+        self.diffuse = diffuse;
+        self.metal = metal;
+        self
+    }
+
+    /// Chance of a small sphere bouncing upward during the shutter interval.
+    pub fn with_moving(mut self, moving: f64) -> RandomSceneParams {
+        assert!((0.0..=1.0).contains(&moving));
+        self.moving = moving;
+        self
+    }
+
+    /// Small sphere radii are drawn from `min..=max`; each sits on the ground.
+    pub fn with_radius(mut self, min: f64, max: f64) -> RandomSceneParams {
+        assert!(min > 0.0 && min <= max);
+        self.radius = (min, max);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> RandomSceneParams {
+        self.seed = Some(seed);
+        self
+    }
 }
 
 fn gen_random_scene() -> Box<dyn Hittable + Sync> {
-    let mut rng = thread_rng();
+    gen_random_scene_with(&RandomSceneParams::default()).into_world(0.0, 10.0)
+}
+
+/// The cover scene's ground, three large spheres and a field of small random ones.
+/// A seed reseeds the calling thread's generator, so the same params give the same scene,
+/// and whatever the caller draws from that generator afterwards follows on from the scene.
+pub fn gen_random_scene_with(params: &RandomSceneParams) -> HittableList {
+    if let Some(seed) = params.seed {
+        mutil::seed(seed);
+    }
+    let mut rng = rng();
     let mut list = HittableList::new();
     let ground: Arc<Box<dyn Material>> =
         Arc::new(Box::new(Lambertian::from_pointer(Arc::new(Box::new(
@@ -104,35 +860,41 @@ fn gen_random_scene() -> Box<dyn Hittable + Sync> {
         1000.0,
         ground,
     ))));
-    for a in -11..11 {
-        for b in -11..11 {
+    let (min_radius, max_radius) = params.radius;
+    for a in -params.grid..params.grid {
+        for b in -params.grid..params.grid {
             let choose_mat = rng.gen::<f64>();
+            let radius = if min_radius < max_radius {
+                rng.gen_range(min_radius..=max_radius)
+            } else {
+                min_radius
+            };
             let center = Vec3::new(
                 a as f64 + 0.9 * rng.gen::<f64>(),
-                0.2,
+                radius,
                 b as f64 + 0.9 * rng.gen::<f64>(),
             );
 
             if (center - Vec3::new(4, 0.2, 0)).length() > 0.9 {
-                let sphere_material: Box<dyn Material> = if choose_mat < 0.3 {
+                let sphere_material: Box<dyn Material> = if choose_mat < params.diffuse {
                     // diffuse
                     let albedo = random() * random();
                     Box::new(Lambertian::new(albedo))
-                } else if choose_mat < 0.6 {
+                } else if choose_mat < params.diffuse + params.metal {
                     let albedo = random_range(0.5, 1.0);
                     let fuzz = rng.gen_range::<f64, std::ops::Range<f64>>(0.0..0.5);
                     Box::new(Metal::new(albedo, fuzz))
                 } else {
                     Box::new(Dielectric::new(1.5))
                 };
-                if choose_mat < 0.8 {
+                if choose_mat < params.moving {
                     let center2 = center + Vec3::new(0, 5, 0);
                     list.add(Arc::new(Box::new(MovingSphere::new(
                         center,
                         center2,
                         0.0,
                         10.0,
-                        0.2,
+                        radius,
                         Arc::new(sphere_material),
                     ))));
                     continue;
@@ -140,7 +902,7 @@ fn gen_random_scene() -> Box<dyn Hittable + Sync> {
 
                 list.add(Arc::new(Box::new(Sphere::new(
                     center,
-                    0.2,
+                    radius,
                     Arc::new(sphere_material),
                 ))));
             }
@@ -158,17 +920,12 @@ fn gen_random_scene() -> Box<dyn Hittable + Sync> {
         m2,
     ))));
     list.add(Arc::new(Box::new(Sphere::new(Vec3::new(4, 1, 0), 1.0, m3))));
-
-    let bvhnode = BvhNode::from_list(&list, 0.0, 10.0);
-
-    let world: Box<dyn Hittable + Sync> = Box::new(bvhnode);
-    //let world: Box<dyn Hittable + Sync> = Box::new(list);
-    world
+    list
 }
 
 fn gen_random_scene_moving() -> Box<dyn Hittable + Sync> {
     let max_time = 100.0;
-    let mut rng = thread_rng();
+    let mut rng = rng();
     let mut list = HittableList::new();
     let ground: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::from_pointer(Arc::new(
         Box::new(SolidColor::new(&Color::new(0.8, 0.8, 0.8))),
@@ -189,7 +946,7 @@ fn gen_random_scene_moving() -> Box<dyn Hittable + Sync> {
             let choose_mat = rng.gen::<f64>();
             let center = Vec3::new(
                 a as f64 + 0.9 * rng.gen::<f64>(),
-                1.7 + thread_rng().gen_range(0.0..2.0),
+                1.7 + rng.gen_range(0.0..2.0),
                 b as f64 + 0.9 * rng.gen::<f64>(),
             );
 
@@ -236,11 +993,7 @@ fn gen_random_scene_moving() -> Box<dyn Hittable + Sync> {
     ))));
     list.add(Arc::new(Box::new(Sphere::new(Vec3::new(4, 1, 0), 1.0, m3))));
 
-    let bvhnode = BvhNode::from_list(&list, 0.0, max_time);
-
-    let world: Box<dyn Hittable + Sync> = Box::new(bvhnode);
-    //let world: Box<dyn Hittable + Sync> = Box::new(list);
-    world
+    list.into_world(0.0, max_time)
 }
 
 fn gen_checkered_sphere() -> Box<dyn Hittable + Sync> {
@@ -284,10 +1037,10 @@ fn gen_two_perlin() -> Box<dyn Hittable + Sync> {
     Box::new(list)
 }
 
-fn earth() -> Box<dyn Hittable + Sync> {
+fn earth(texture: &str) -> Result<Box<dyn Hittable + Sync>, ImageError> {
     let mut list = HittableList::new();
     let ground: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::from_pointer(Arc::new(
-        Box::new(Image::from_ppm("earthshit.ppm")),
+        Box::new(Image::from_ppm(texture)?),
     ))));
     list.add(Arc::new(Box::new(Sphere::new(
         Vec3::new(0, -1000, 0),
@@ -301,7 +1054,7 @@ fn earth() -> Box<dyn Hittable + Sync> {
         ground,
     ))));
 
-    Box::new(list)
+    Ok(Box::new(list))
 }
 
 fn gen_simple_light() -> Box<dyn Hittable + Sync> {
@@ -341,24 +1094,70 @@ fn gen_simple_light() -> Box<dyn Hittable + Sync> {
     Box::new(list)
 }
 
-fn cornell_box() -> Box<dyn Hittable + Sync> {
+/// Walls and light for `cornell`. The defaults are the classic box's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CornellParams {
+    left: Color,
+    right: Color,
+    white: Color,
+    light: (f64, f64, f64, f64),
+    intensity: Color,
+}
+
+impl Default for CornellParams {
+    fn default() -> CornellParams {
+        CornellParams {
+            left: Color::new(0.12, 0.45, 0.15),
+            right: Color::new(0.65, 0.05, 0.05),
+            white: Color::splat(0.73),
+            light: (213.0, 343.0, 227.0, 332.0),
+            intensity: Color::splat(15),
+        }
+    }
+}
+
+impl CornellParams {
+    /// Albedos of the left (x = 555) and right (x = 0) walls, as seen from the usual
+    /// camera at z = -800, and of the floor, ceiling and back wall.
+    pub fn with_walls(mut self, left: Color, right: Color, white: Color) -> CornellParams {
+        self.left = left;
+        self.right = right;
+        self.white = white;
+        self
+    }
+
+    /// The ceiling light spans `x0..x1` by `z0..z1`, just below the ceiling.
+    pub fn with_light(mut self, x0: f64, x1: f64, z0: f64, z1: f64) -> CornellParams {
+        assert!(x0 < x1 && z0 < z1);
+        self.light = (x0, x1, z0, z1);
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: Color) -> CornellParams {
+        self.intensity = intensity;
+        self
+    }
+}
+
+/// An empty 555-unit Cornell box; `contents` adds what goes inside, given the white material.
+pub fn cornell(
+    params: &CornellParams,
+    contents: impl FnOnce(&mut HittableList, &Arc<Box<dyn Material>>),
+) -> HittableList {
     let mut list = HittableList::new();
-    let red: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(Lambertian::new(Color::new(0.65, 0.05, 0.05))));
-    let white: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))));
-    let green: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(Lambertian::new(Color::new(0.12, 0.45, 0.15))));
-    let light: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(DiffuseLight::new(&Color::new(15, 15, 15))));
+    let left: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(params.left)));
+    let right: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(params.right)));
+    let white: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(params.white)));
+    let light: Arc<Box<dyn Material>> = Arc::new(Box::new(DiffuseLight::new(&params.intensity)));
+    let (x0, x1, z0, z1) = params.light;
     list.add(Arc::new(Box::new(YzRect::new(
-        0.0, 555.0, 0.0, 555.0, 555.0, green,
+        0.0, 555.0, 0.0, 555.0, 555.0, left,
     ))));
     list.add(Arc::new(Box::new(YzRect::new(
-        0.0, 555.0, 0.0, 555.0, 0.0, red,
+        0.0, 555.0, 0.0, 555.0, 0.0, right,
     ))));
     list.add(Arc::new(Box::new(XzRect::new(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
+        x0, x1, z0, z1, 554.0, light,
     ))));
     list.add(Arc::new(Box::new(XzRect::new(
         0.0,
@@ -384,130 +1183,89 @@ fn cornell_box() -> Box<dyn Hittable + Sync> {
         555.0,
         white.clone(),
     ))));
+    contents(&mut list, &white);
+    list
+}
 
-    list.add(Arc::new(Box::new(Translate::new(
-        &Vec3::new(265, 0, 295),
+// one of the two white boxes, `height` tall, turned by `angle` degrees and moved to `offset`
+fn cornell_prism(
+    white: &Arc<Box<dyn Material>>,
+    height: i32,
+    angle: f64,
+    offset: Vec3,
+) -> Translate {
+    Translate::new(
+        &offset,
         Arc::new(Box::new(RotateY::new(
-            15.0,
+            angle,
             Arc::new(Box::new(RectPrism::new(
                 &Point3::new(0, 0, 0),
-                &Point3::new(165, 330, 165),
+                &Point3::new(165, height, 165),
                 white.clone(),
             ))),
         ))),
-    ))));
+    )
+}
 
-    list.add(Arc::new(Box::new(Translate::new(
-        &Vec3::new(130, 0, 65),
-        Arc::new(Box::new(RotateY::new(
+fn cornell_box() -> Box<dyn Hittable + Sync> {
+    Box::new(cornell(&CornellParams::default(), |list, white| {
+        list.add(Arc::new(Box::new(cornell_prism(
+            white,
+            330,
+            15.0,
+            Vec3::new(265, 0, 295),
+        ))));
+        list.add(Arc::new(Box::new(cornell_prism(
+            white,
+            165,
             -18.0,
-            Arc::new(Box::new(RectPrism::new(
-                &Point3::new(0, 0, 0),
-                &Point3::new(165, 165, 165),
-                white.clone(),
-            ))),
-        ))),
-    ))));
-
-    Box::new(list)
+            Vec3::new(130, 0, 65),
+        ))));
+    }))
 }
 
 fn cornell_smoke() -> Box<dyn Hittable + Sync> {
-    let mut list = HittableList::new();
-    let red: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(Lambertian::new(Color::new(0.65, 0.05, 0.05))));
-    let white: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))));
-    let green: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(Lambertian::new(Color::new(0.12, 0.45, 0.15))));
-    let light: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(DiffuseLight::new(&Color::new(15, 15, 15))));
-    list.add(Arc::new(Box::new(YzRect::new(
-        0.0, 555.0, 0.0, 555.0, 555.0, green,
-    ))));
-    list.add(Arc::new(Box::new(YzRect::new(
-        0.0, 555.0, 0.0, 555.0, 0.0, red,
-    ))));
-    list.add(Arc::new(Box::new(XzRect::new(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
-    ))));
-    list.add(Arc::new(Box::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        0.0,
-        white.clone(),
-    ))));
-    list.add(Arc::new(Box::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    ))));
-    list.add(Arc::new(Box::new(XyRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white.clone(),
-    ))));
-
-    list.add(Arc::new(Box::new(ConstantMedium::from_color(
-        &Color::new(0, 0, 0),
-        0.01,
-        Arc::new(Box::new(Translate::new(
-            &Vec3::new(265, 0, 295),
-            Arc::new(Box::new(RotateY::new(
+    Box::new(cornell(&CornellParams::default(), |list, white| {
+        list.add(Arc::new(Box::new(ConstantMedium::from_color(
+            &Color::new(0, 0, 0),
+            0.01,
+            Arc::new(Box::new(cornell_prism(
+                white,
+                330,
                 15.0,
-                Arc::new(Box::new(RectPrism::new(
-                    &Point3::new(0, 0, 0),
-                    &Point3::new(165, 330, 165),
-                    white.clone(),
-                ))),
+                Vec3::new(265, 0, 295),
             ))),
-        ))),
-    ))));
-
-    list.add(Arc::new(Box::new(ConstantMedium::from_color(
-        &Color::new(1, 1, 1),
-        0.01,
-        Arc::new(Box::new(Translate::new(
-            &Vec3::new(130, 0, 65),
-            Arc::new(Box::new(RotateY::new(
+        ))));
+        list.add(Arc::new(Box::new(ConstantMedium::from_color(
+            &Color::new(1, 1, 1),
+            0.01,
+            Arc::new(Box::new(cornell_prism(
+                white,
+                165,
                 -18.0,
-                Arc::new(Box::new(RectPrism::new(
-                    &Point3::new(0, 0, 0),
-                    &Point3::new(165, 165, 165),
-                    white.clone(),
-                ))),
+                Vec3::new(130, 0, 65),
             ))),
-        ))),
-    ))));
-
-    Box::new(list)
+        ))));
+    }))
 }
 
-fn final_scene() -> Box<dyn Hittable + Sync> {
+fn final_scene() -> Result<Box<dyn Hittable + Sync>, ImageError> {
     let mut list = HittableList::new();
     let mut boxes1 = HittableList::new();
+    let w = 100.0;
     let ground: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::from_pointer(Arc::new(
-        Box::new(SolidColor::new(&Color::new(0.48, 0.83, 0.53))),
+        Box::new(CellJitter::new(&Color::new(0.48, 0.83, 0.53), w, 0.3)),
     ))));
     let boxes_per_side = 20;
     for i in 0..boxes_per_side {
         for j in 0..boxes_per_side {
             let i = i as f64;
             let j = j as f64;
-            let w = 100.0;
             let x0 = -1000.0 + i * w;
             let z0 = -1000.0 + j * w;
             let y0 = 0.0;
             let x1 = x0 + w;
-            let y1 = thread_rng().gen_range(1.0..101.0);
+            let y1 = rng().gen_range(1.0..101.0);
             let z1 = z0 + w;
             boxes1.add(Arc::new(Box::new(RectPrism::new(
                 &Point3::new(x0, y0, z0),
@@ -577,7 +1335,7 @@ fn final_scene() -> Box<dyn Hittable + Sync> {
     ))));
 
     let ground: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::from_pointer(Arc::new(
-        Box::new(Image::from_ppm("earthshit.ppm")),
+        Box::new(Image::from_ppm("earthshit.ppm")?),
     ))));
     list.add(Arc::new(Box::new(Sphere::new(
         Vec3::new(400, 200, 400),
@@ -612,7 +1370,7 @@ fn final_scene() -> Box<dyn Hittable + Sync> {
         ))),
     ))));
 
-    Box::new(list)
+    Ok(Box::new(list))
 }
 
 fn gen_moving_test() -> Box<dyn Hittable + Sync> {
@@ -639,11 +1397,7 @@ fn gen_moving_test() -> Box<dyn Hittable + Sync> {
         1.0,
         Arc::new(sphere_material),
     ))));
-    let bvhnode = BvhNode::from_list(&list, 0.0, 10.0);
-
-    let world: Box<dyn Hittable + Sync> = Box::new(bvhnode);
-    //let world: Box<dyn Hittable + Sync> = Box::new(list);
-    world
+    list.into_world(0.0, 10.0)
 }
 
 fn benchmark_test_scene() -> Box<dyn Hittable + Sync> {
@@ -678,11 +1432,12 @@ fn triangle_test() -> Box<dyn Hittable + Sync> {
     Box::new(list)
 }
 
-fn stanford_dragon() -> Box<dyn Hittable + Sync> {
+fn stanford_dragon() -> Result<Box<dyn Hittable + Sync>, ImageError> {
     let mut list = HittableList::new();
 
-    let dragon = TriangleModel::load_from_file("./models/dragon_recon/dragon_vrip_res2.ply", 100.0)
-        .to_hittable();
+    let dragon =
+        TriangleModel::load_from_file("./models/dragon_recon/dragon_vrip_res2.ply", 100.0)?
+            .to_hittable();
     //let dragon = TriangleModel::load_from_file("./models/sphere.ply").to_hittable();
     let dragon = BvhNode::from_list(&dragon, 0.0, 1.0);
 
@@ -747,19 +1502,17 @@ fn stanford_dragon() -> Box<dyn Hittable + Sync> {
     list.add(Arc::new(Box::new(ceiling_light)));
     //list.add(Arc::new(Box::new(backwall)));
 
-    Box::new(list)
+    Ok(Box::new(list))
 }
 
 fn triangular_prism() -> Box<dyn Hittable + Sync> {
     let mut list = HittableList::new();
     let red: Arc<Box<dyn Material>> =
         Arc::new(Box::new(Lambertian::new(Color::new(0.65, 0.05, 0.05))));
-    let white: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))));
+    let white: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.73))));
     let green: Arc<Box<dyn Material>> =
         Arc::new(Box::new(Lambertian::new(Color::new(0.12, 0.45, 0.15))));
-    let light: Arc<Box<dyn Material>> =
-        Arc::new(Box::new(DiffuseLight::new(&Color::new(15, 15, 15))));
+    let light: Arc<Box<dyn Material>> = Arc::new(Box::new(DiffuseLight::new(&Color::splat(15))));
     list.add(Arc::new(Box::new(YzRect::new(
         0.0, 555.0, 0.0, 555.0, 555.0, green,
     ))));
@@ -873,7 +1626,13 @@ fn triangular_prism() -> Box<dyn Hittable + Sync> {
     Box::new(list)
 }
 
-pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<Camera>, Color) {
+/// Scene ids `get_world_cam` has its own arm for; anything higher falls back to the random scene.
+pub const SCENE_COUNT: usize = 13;
+
+/// A world, the camera looking at it and the background color rays escape to.
+pub type Scene = (Arc<Box<dyn Hittable + Sync>>, Arc<Camera>, Color);
+
+pub fn get_world_cam(config_num: usize) -> Result<Scene, ImageError> {
     // TODO: do something smart, load from file maybe?
     let aspect_ratio: f64 = 16.0 / 9.0;
     let background = Color::new(0.7, 0.8, 1);
@@ -897,7 +1656,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 1.0,
             ));
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
         1 => {
             let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(gen_two_perlin());
@@ -918,10 +1677,10 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 1.0,
             ));
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
         2 => {
-            let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(earth());
+            let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(earth("earthshit.ppm")?);
             // camera
             let lookfrom = Vec3::new(13, 2, 3);
             let lookat = Vec3::new(0, 0, 0);
@@ -939,7 +1698,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 1.0,
             ));
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
 
         3 => {
@@ -962,7 +1721,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 1.0,
             ));
             let background = Color::new(0, 0, 0);
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
         4 => {
             let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(cornell_box());
@@ -983,7 +1742,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 1.0,
             ));
-            return (world, cam, Color::new(0, 0, 0));
+            return Ok((world, cam, Color::new(0, 0, 0)));
         }
         5 => {
             let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(cornell_smoke());
@@ -1004,10 +1763,10 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 1.0,
             ));
-            return (world, cam, Color::new(0, 0, 0));
+            return Ok((world, cam, Color::new(0, 0, 0)));
         }
         6 => {
-            let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(final_scene());
+            let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(final_scene()?);
             // camera
             let lookfrom = Vec3::new(478, 278, -600);
             let lookat = Vec3::new(278, 278, 0);
@@ -1025,7 +1784,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 1.0,
             ));
-            return (world, cam, Color::new(0, 0, 0));
+            return Ok((world, cam, Color::new(0, 0, 0)));
         }
         7 => {
             let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(gen_moving_test());
@@ -1046,7 +1805,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 2.0,
                 2.5,
             ));
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
         8 => {
             let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(gen_random_scene_moving());
@@ -1067,7 +1826,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 10.0,
             ));
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
         9 => {
             let world = Arc::new(benchmark_test_scene());
@@ -1088,7 +1847,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 10.0,
             ));
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
         10 => {
             let world = Arc::new(triangle_test());
@@ -1109,10 +1868,10 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 10.0,
             ));
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
         11 => {
-            let world = Arc::new(stanford_dragon());
+            let world = Arc::new(stanford_dragon()?);
             // camera
             let lookfrom = Vec3::new(0, 20, 20);
             let lookat = Vec3::new(0, 11, 0);
@@ -1130,7 +1889,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 10.0,
             ));
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
 
         12 => {
@@ -1152,7 +1911,7 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 1.0,
             ));
-            return (world, cam, Color::new(0, 0, 0));
+            return Ok((world, cam, Color::new(0, 0, 0)));
         }
         _ => {
             let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(gen_random_scene());
@@ -1173,77 +1932,641 @@ pub fn get_world_cam(config_num: usize) -> (Arc<Box<dyn Hittable + Sync>>, Arc<C
                 0.0,
                 10.0,
             ));
-            return (world, cam, background);
+            return Ok((world, cam, background));
         }
     }
 }
 
-pub fn render_scene(
+/// Render to a display-ready screen: post effects applied and encoded to 0-255.
+pub fn render_to_screen(
     world: Arc<Box<dyn Hittable + Sync>>,
     cam: Arc<Camera>,
     background: Vec3,
-    config: Config,
-) {
-    let (sender, receiver) = channel();
+    config: &Config,
+) -> Screen {
+    let mut hdr = render_hdr(world, cam, background, config);
+    if config.exposure != 1.0 {
+        post::expose(&mut hdr, config.exposure);
+    }
+    if let Some(bloom) = config.bloom {
+        bloom.apply(&mut hdr);
+    }
+    if let Some(vignette) = config.vignette {
+        vignette.apply(&mut hdr);
+    }
+    let mut image = match config.color_space {
+        ColorSpace::Srgb => post::encode_with_depth(&hdr, &config.tone_map, config.bit_depth),
+        ColorSpace::Linear => post::encode_linear(&hdr, config.bit_depth),
+    };
+    if let Some(dither) = config.dither {
+        dither.apply(&mut image);
+    }
+    image
+}
+
+/// Render straight to RGBA8 bytes (row-major, top-left first) for embedding, e.g. in a GUI.
+pub fn render(
+    world: Arc<Box<dyn Hittable + Sync>>,
+    cam: Arc<Camera>,
+    background: Vec3,
+    config: &Config,
+) -> Vec<u8> {
+    render_to_screen(world, cam, background, config).to_rgba8()
+}
 
+/// A warning if `cam`'s viewport doesn't have the image's shape, which would stretch
+/// the picture. Off by less than a pixel of width is just the height being rounded.
+fn aspect_warning(cam: &Camera, image_width: i32, image_height: i32) -> Option<String> {
+    let expected = cam.get_aspect_ratio() * image_height as f64;
+    if (expected - image_width as f64).abs() <= 1.0 {
+        return None;
+    }
+    Some(format!(
+        "warning: camera aspect ratio {:.3} doesn't match the {}x{} image ({:.3}), so it will look stretched",
+        cam.get_aspect_ratio(),
+        image_width,
+        image_height,
+        image_width as f64 / image_height as f64
+    ))
+}
+
+/// Render to a linear buffer holding each pixel's average radiance.
+pub fn render_hdr(
+    world: Arc<Box<dyn Hittable + Sync>>,
+    cam: Arc<Camera>,
+    background: Vec3,
+    config: &Config,
+) -> Screen {
+    let config = &config.with_lights_from(world.as_ref().as_ref());
+    if config.path_histogram {
+        let stats = config.stats.clone().unwrap_or_default();
+        let mut counted = config.clone().with_stats(stats.clone());
+        counted.path_histogram = false;
+        let hdr = render_hdr(world, cam, background, &counted);
+        eprint!("{}", stats.path_length_histogram());
+        return hdr;
+    }
     // image
     let aspect_ratio = config.aspect_ratio;
     let image_width = config.image_width;
     let image_height: i32 = (image_width as f64 / aspect_ratio) as i32;
     let samples_per_pixel = config.samples_per_pixel;
-    let max_depth = config.max_depth;
+
+    if let Some(factor) = config.preview {
+        let mut small = config.clone();
+        small.preview = None;
+        small.image_width = i32::max(2, (image_width as f64 * factor).round() as i32);
+        small.samples_per_pixel = i32::max(1, (samples_per_pixel as f64 * factor).round() as i32);
+        return render_hdr(world, cam, background, &small)
+            .resize_nearest(image_width as usize, image_height as usize);
+    }
+    if let Some(warning) = aspect_warning(&cam, image_width, image_height) {
+        eprintln!("{}", warning);
+    }
+
+    let deadline = config.deadline.map(|d| Instant::now() + d);
+    if config.edge_aware && samples_per_pixel > 1 {
+        // split progress between the passes by how many samples each takes
+        let share = 1.0 / samples_per_pixel as f64;
+        let report = progress_fn(config);
+        let mut first = config.clone();
+        first.samples_per_pixel = 1;
+        first.progress = Some(Arc::new({
+            let report = report.clone();
+            move |f| report(f * share)
+        }));
+        let coarse = render_pass(
+            world.clone(),
+            cam.clone(),
+            background,
+            &first,
+            None,
+            deadline,
+        );
+
+        let counts = edge_sample_counts(&coarse, samples_per_pixel as usize);
+        let extra: Vec<usize> = counts.iter().map(|n| n - 1).collect();
+        let mut second = config.clone();
+        // pick up each pixel's sample sequence where the first pass left it, seeded or not
+        second.first_sample = first.samples_per_pixel as usize;
+        second.progress = Some(Arc::new(move |f| report(share + f * (1.0 - share))));
+        let refined = render_pass(
+            world,
+            cam,
+            background,
+            &second,
+            Some(Arc::new(extra)),
+            deadline,
+        );
+
+        let mut screen = coarse;
+        for j in 0..screen.get_height() {
+            for i in 0..screen.get_width() {
+                let n = counts[j * screen.get_width() + i] as f64;
+                let c = (*screen.get(j, i) + (n - 1.0) * *refined.get(j, i)) / n;
+                screen.update(j, i, c);
+            }
+        }
+        return screen;
+    }
+
+    render_pass(world, cam, background, config, None, deadline)
+}
+
+/// Per-pixel sample counts that add up to roughly `samples_per_pixel` per pixel overall.
+/// Half of the extra budget is spread evenly and half goes to pixels in proportion
+/// to their largest luminance difference with a 4-neighbour; every pixel gets at least one.
+pub fn edge_sample_counts(coarse: &Screen, samples_per_pixel: usize) -> Vec<usize> {
+    let (width, height) = (coarse.get_width(), coarse.get_height());
+    let lum = |j: usize, i: usize| post::luminance(coarse.get(j, i));
+    let mut gradient = vec![0.0; width * height];
+    for j in 0..height {
+        for i in 0..width {
+            let mut g: f64 = 0.0;
+            if i > 0 {
+                g = g.max((lum(j, i) - lum(j, i - 1)).abs());
+            }
+            if i + 1 < width {
+                g = g.max((lum(j, i) - lum(j, i + 1)).abs());
+            }
+            if j > 0 {
+                g = g.max((lum(j, i) - lum(j - 1, i)).abs());
+            }
+            if j + 1 < height {
+                g = g.max((lum(j, i) - lum(j + 1, i)).abs());
+            }
+            gradient[j * width + i] = g;
+        }
+    }
+
+    let extra = (samples_per_pixel - 1) as f64;
+    let total: f64 = gradient.iter().sum();
+    gradient
+        .iter()
+        .map(|g| {
+            let share = if total > 0.0 {
+                0.5 * extra + 0.5 * extra * (width * height) as f64 * g / total
+            } else {
+                extra
+            };
+            1 + share.round() as usize
+        })
+        .collect()
+}
+
+/// Radiance along the `n`th camera sample through pixel `(i, j)`.
+pub(crate) fn sample_pixel(
+    world: &dyn Hittable,
+    cam: &Camera,
+    background: &Color,
+    config: &Config,
+    i: usize,
+    j: usize,
+    n: usize,
+) -> Color {
+    camera_sample(world, cam, background, config, i, j, n).1
+}
+
+/// Like `sample_pixel`, also giving where the sample landed, in pixels from the image's
+/// bottom-left corner; pixel `(i, j)` covers `[i, i + 1) x [j, j + 1)`.
+fn camera_sample(
+    world: &dyn Hittable,
+    cam: &Camera,
+    background: &Color,
+    config: &Config,
+    i: usize,
+    j: usize,
+    n: usize,
+) -> ((f64, f64), Color) {
+    let n = n + config.first_sample;
+    if let Some(seed) = config.seed {
+        mutil::seed_sample(seed, i, j, n);
+    }
+    let image_width = config.image_width;
+    let image_height: i32 = (image_width as f64 / config.aspect_ratio) as i32;
+    let (x, y, r) = match config.sampler {
+        Sampler::Random => {
+            let x = i as f64 + rng().gen::<f64>();
+            let y = j as f64 + rng().gen::<f64>();
+            let r = cam.get_ray(x / (image_width - 1) as f64, y / (image_height - 1) as f64);
+            (x, y, r)
+        }
+        Sampler::Halton => {
+            let p = config.sampler.point(n, i, j, config.seed.unwrap_or(0));
+            let (x, y) = (i as f64 + p[0], j as f64 + p[1]);
+            let r = cam.get_ray_with(
+                x / (image_width - 1) as f64,
+                y / (image_height - 1) as f64,
+                (p[2], p[3]),
+                p[4],
+            );
+            (x, y, r)
+        }
+    };
+    let r = match config.ray_differentials {
+        true => r.with_differential(Some(cam.differential(
+            1.0 / (image_width - 1) as f64,
+            1.0 / (image_height - 1) as f64,
+        ))),
+        false => r,
+    };
+    if let Some(stats) = &config.stats {
+        stats.record_sample();
+    }
+    ((x, y), ray_color(&r, background, world, config))
+}
+
+/// The value `render_hdr` gives pixel `(i, j)` (column, row from the bottom): the average of
+/// `samples_per_pixel` samples, each printed with its path under `Config::with_path_trace`.
+/// With a seed it's the very same value; `edge_aware`, `preview` and `filter` are ignored.
+pub fn debug_pixel(
+    world: &dyn Hittable,
+    cam: &Camera,
+    background: Color,
+    config: &Config,
+    i: usize,
+    j: usize,
+) -> Color {
+    let config = &config.with_lights_from(world);
+    let samples = config.samples_per_pixel.max(1) as usize;
+    // summed as the full render sums them, so the two agree exactly
+    let sum = (0..SAMPLE_CHUNKS)
+        .map(|c| {
+            sum_chunk(samples, c, |n| {
+                if config.trace_paths {
+                    eprintln!("sample {} of pixel ({}, {})", n, i, j);
+                }
+                let color = sample_pixel(world, cam, &background, config, i, j, n);
+                if config.trace_paths {
+                    eprintln!("  = {}", color);
+                }
+                color
+            })
+        })
+        .fold(Color::zero(), |total, sum| total + sum);
+    sum / samples as f64
+}
+
+fn progress_fn(config: &Config) -> Progress {
+    match &config.progress {
+        Some(progress) => progress.clone(),
+        None => Arc::new(|f| {
+            eprint!("\rDone {:.0}%", f * 100.0);
+            if f >= 1.0 {
+                eprintln!();
+            }
+        }),
+    }
+}
+
+/// One multithreaded pass. With `counts`, pixel `(i, j)` takes `counts[j * width + i]` samples
+/// instead of `samples_per_pixel`; pixels given no samples come back black.
+/// Workers stop taking samples once `deadline` passes.
+fn render_pass(
+    world: Arc<Box<dyn Hittable + Sync>>,
+    cam: Arc<Camera>,
+    background: Vec3,
+    config: &Config,
+    counts: Option<Arc<Vec<usize>>>,
+    deadline: Option<Instant>,
+) -> Screen {
+    let image_width = config.image_width;
+    let image_height: i32 = (image_width as f64 / config.aspect_ratio) as i32;
+    let samples_per_pixel = config.samples_per_pixel;
+
+    if let Some(filter) = &config.filter {
+        return render_pass_filtered(world, cam, background, config, counts, deadline, filter);
+    }
+    // too few rows to go round, but plenty of samples: deal out single pixels instead
+    if deadline.is_none()
+        && (image_height as usize) < config.threads
+        && samples_per_pixel as usize >= config.threads
+    {
+        return render_pass_by_samples(world, cam, background, config, counts);
+    }
 
     let mut screen = Screen::new(image_width as usize, image_height as usize);
+    let flush_size = config.flush_size.unwrap_or(image_width as usize);
+    let progress = progress_fn(config);
+    let total = image_height as usize * image_width as usize;
+    // pixels finished so far, and the highest whole percent a worker has claimed to report;
+    // claiming first keeps reports rising without holding anything during the callback
+    let done = AtomicUsize::new(0);
+    let reported = AtomicUsize::new(0);
+    let report = |pixels: usize| {
+        let now = done.fetch_add(pixels, Ordering::Relaxed) + pixels;
+        let percent = now * 100 / total;
+        if now < total && reported.fetch_max(percent, Ordering::Relaxed) < percent {
+            progress(now as f64 / total as f64);
+        }
+    };
+    let world = world.as_ref().as_ref();
+    let samples_at = |i: usize, j: usize| match &counts {
+        Some(counts) => counts[j * image_width as usize + i],
+        None => samples_per_pixel as usize,
+    };
+    let sample =
+        |i: usize, j: usize, n: usize| sample_pixel(world, &cam, &background, config, i, j, n);
+
+    // each worker fills in its own band of rows, spread so the remainder rows aren't dropped
+    thread::scope(|scope| {
+        for mut band in screen.bands_mut(config.threads) {
+            let (start, end) = (band.get_rows().start, band.get_rows().end);
+            let (report, samples_at, sample) = (&report, &samples_at, &sample);
+            scope.spawn(move || {
+                if let Some(deadline) = deadline {
+                    // one sample per pixel per round, so stopping early still leaves every
+                    // row that was reached evenly sampled
+                    let width = image_width as usize;
+                    let mut sums = vec![Vec3::new(0, 0, 0); (end - start) * width];
+                    let mut taken = vec![0usize; (end - start) * width];
+                    let rounds = (start..end)
+                        .flat_map(|j| (0..width).map(move |i| (i, j)))
+                        .map(|(i, j)| samples_at(i, j))
+                        .max()
+                        .unwrap_or(0);
+                    'rounds: for round in 0..rounds {
+                        for j in start..end {
+                            if Instant::now() >= deadline {
+                                break 'rounds;
+                            }
+                            for i in 0..width {
+                                if round < samples_at(i, j) {
+                                    let k = (j - start) * width + i;
+                                    sums[k] += sample(i, j, round);
+                                    taken[k] += 1;
+                                }
+                            }
+                        }
+                    }
+                    for j in start..end {
+                        for i in 0..width {
+                            let k = (j - start) * width + i;
+                            band.update(
+                                j,
+                                i,
+                                match taken[k] {
+                                    0 => sums[k],
+                                    n => sums[k] / n as f64,
+                                },
+                            );
+                            if (i + 1) % flush_size == 0 || i == width - 1 {
+                                report(i % flush_size + 1);
+                            }
+                        }
+                    }
+                    return;
+                }
 
-    let chunk_size = image_height as usize / config.threads;
+                for j in start..end {
+                    for i in 0..image_width as usize {
+                        let samples = samples_at(i, j);
+                        let pixel = (0..SAMPLE_CHUNKS)
+                            .map(|c| sum_chunk(samples, c, |n| sample(i, j, n)))
+                            .fold(Vec3::zero(), |total, sum| total + sum);
+                        band.update(
+                            j,
+                            i,
+                            if samples > 0 {
+                                pixel / samples as f64
+                            } else {
+                                pixel
+                            },
+                        );
+                        if (i + 1) % flush_size == 0 || i == image_width as usize - 1 {
+                            report(i % flush_size + 1);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    progress(1.0);
 
-    for t in 0..config.threads {
-        let start = t * chunk_size;
-        let end = usize::min(t * chunk_size + chunk_size, image_height as usize);
-        let send_clone = sender.clone();
-        let shared_world: Arc<Box<dyn Hittable + Sync>> = world.clone();
-        let shared_cam = cam.clone();
+    screen
+}
+
+// samples `n * c / SAMPLE_CHUNKS..n * (c + 1) / SAMPLE_CHUNKS` of a pixel taking `n`, summed
+fn sum_chunk(n: usize, c: usize, sample: impl Fn(usize) -> Vec3) -> Vec3 {
+    (n * c / SAMPLE_CHUNKS..n * (c + 1) / SAMPLE_CHUNKS)
+        .map(sample)
+        .fold(Vec3::zero(), |sum, s| sum + s)
+}
+
+// worker `t` takes every `threads`th chunk of every pixel's samples from `t` on and hands back
+// their sums, which are added back up in chunk order
+fn render_pass_by_samples(
+    world: Arc<Box<dyn Hittable + Sync>>,
+    cam: Arc<Camera>,
+    background: Vec3,
+    config: &Config,
+    counts: Option<Arc<Vec<usize>>>,
+) -> Screen {
+    let width = config.image_width as usize;
+    let height = (config.image_width as f64 / config.aspect_ratio) as usize;
+    let threads = config.threads;
+    let samples_at = |i: usize, j: usize| match &counts {
+        Some(counts) => counts[j * width + i],
+        None => config.samples_per_pixel as usize,
+    };
+    let workers: Vec<_> = (0..threads)
+        .map(|t| {
+            let world = world.clone();
+            let cam = cam.clone();
+            let config = config.clone();
+            let counts = counts.clone();
+            thread::spawn(move || {
+                (t..width * height * SAMPLE_CHUNKS)
+                    .step_by(threads)
+                    .map(|k| {
+                        let (pixel, c) = (k / SAMPLE_CHUNKS, k % SAMPLE_CHUNKS);
+                        let (i, j) = (pixel % width, pixel / width);
+                        let samples = match &counts {
+                            Some(counts) => counts[pixel],
+                            None => config.samples_per_pixel as usize,
+                        };
+                        let sum = sum_chunk(samples, c, |n| {
+                            sample_pixel(
+                                world.as_ref().as_ref(),
+                                &cam,
+                                &background,
+                                &config,
+                                i,
+                                j,
+                                n,
+                            )
+                        });
+                        (k, sum)
+                    })
+                    .collect::<Vec<(usize, Vec3)>>()
+            })
+        })
+        .collect();
+
+    let progress = progress_fn(config);
+    let mut chunks = vec![Vec3::zero(); width * height * SAMPLE_CHUNKS];
+    for (done, worker) in workers.into_iter().enumerate() {
+        for (k, sum) in worker.join().unwrap() {
+            chunks[k] = sum;
+        }
+        if done + 1 < threads {
+            progress((done + 1) as f64 / threads as f64);
+        }
+    }
+    progress(1.0);
+    let totals: Vec<Vec3> = chunks
+        .chunks(SAMPLE_CHUNKS)
+        .map(|sums| sums.iter().fold(Vec3::zero(), |total, &sum| total + sum))
+        .collect();
+
+    let mut screen = Screen::new(width, height);
+    for j in 0..height {
+        for i in 0..width {
+            let total = totals[j * width + i];
+            let color = match samples_at(i, j) {
+                0 => total,
+                n => total / n as f64,
+            };
+            screen.update(j, i, color);
+        }
+    }
+    screen
+}
 
+// rows are dealt out round-robin, and each one's splats (which reach the rows around it) are
+// added in row order, so seeded results don't depend on the thread count. Rows not started
+// by `deadline` are skipped, leaving black where none of their neighbours reached.
+fn render_pass_filtered(
+    world: Arc<Box<dyn Hittable + Sync>>,
+    cam: Arc<Camera>,
+    background: Vec3,
+    config: &Config,
+    counts: Option<Arc<Vec<usize>>>,
+    deadline: Option<Instant>,
+    filter: &Arc<dyn PixelFilter>,
+) -> Screen {
+    let width = config.image_width as usize;
+    let height = (config.image_width as f64 / config.aspect_ratio) as usize;
+    let threads = config.threads;
+    let radius = filter.radius();
+    // pixel centers sit half a pixel in, so a sample in row `j` reaches this many rows either way
+    let reach = (radius + 0.5).ceil() as usize;
+
+    let (sender, receiver) = channel();
+    for t in 0..threads {
+        let sender = sender.clone();
+        let world = world.clone();
+        let cam = cam.clone();
+        let config = config.clone();
+        let counts = counts.clone();
+        let filter = filter.clone();
         thread::spawn(move || {
-            for j in start..end {
-                for i in 0..image_width {
-                    let mut pixel = Vec3::new(0, 0, 0);
-                    for _ in 0..samples_per_pixel {
-                        let u = (i as f64 + thread_rng().gen::<f64>()) / (image_width - 1) as f64;
-                        let v = (j as f64 + thread_rng().gen::<f64>()) / (image_height - 1) as f64;
-                        let r = shared_cam.get_ray(u, v);
-                        pixel += ray_color(&r, &background, shared_world.as_ref(), max_depth);
+            for j in (t..height).step_by(threads) {
+                let low = j.saturating_sub(reach);
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    sender.send((j, low, vec![], vec![])).unwrap();
+                    continue;
+                }
+                let rows = usize::min(height, j + reach + 1) - low;
+                let mut sums = vec![Color::zero(); rows * width];
+                let mut weights = vec![0.0; rows * width];
+                for i in 0..width {
+                    let samples = match &counts {
+                        Some(counts) => counts[j * width + i],
+                        None => config.samples_per_pixel as usize,
+                    };
+                    for n in 0..samples {
+                        let ((x, y), color) = camera_sample(
+                            world.as_ref().as_ref(),
+                            &cam,
+                            &background,
+                            &config,
+                            i,
+                            j,
+                            n,
+                        );
+                        // pixels whose centers are within the radius
+                        let covered = |at: f64, size: usize| {
+                            let first = (at - 0.5 - radius).ceil().max(0.0) as usize;
+                            let end = (at + 0.5 + radius).floor().clamp(0.0, size as f64);
+                            first..end as usize
+                        };
+                        for py in covered(y, height) {
+                            for px in covered(x, width) {
+                                let w = filter.weight(x - px as f64 - 0.5, y - py as f64 - 0.5);
+                                let k = (py - low) * width + px;
+                                sums[k] += w * color;
+                                weights[k] += w;
+                            }
+                        }
                     }
-                    send_clone
-                        .send((
-                            j as usize,
-                            i as usize,
-                            pixel.get_normalized_color(samples_per_pixel as u32),
-                        ))
-                        .unwrap();
                 }
+                sender.send((j, low, sums, weights)).unwrap();
             }
         });
     }
     drop(sender);
-    let mut loops = 0;
-    let total = image_height * image_width;
-    loop {
-        loops += 1;
-        match receiver.recv() {
-            Ok((j, i, color)) => {
-                screen.update(j, i, color);
+
+    let progress = progress_fn(config);
+    let mut sums = vec![Color::zero(); width * height];
+    let mut weights = vec![0.0; width * height];
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    for (j, low, row_sums, row_weights) in receiver {
+        pending.insert(j, (low, row_sums, row_weights));
+        while let Some((low, row_sums, row_weights)) = pending.remove(&next) {
+            let offset = low * width;
+            for (k, (c, w)) in row_sums.into_iter().zip(row_weights).enumerate() {
+                sums[offset + k] += c;
+                weights[offset + k] += w;
             }
-            Err(_) => {
-                break;
+            next += 1;
+            // every whole percent
+            if (next - 1) * 100 / height != next * 100 / height && next < height {
+                progress(next as f64 / height as f64);
             }
         }
-        if (loops % 10000) == 0 {
-            eprintln!("\rDone {} many loops out of {}", loops, total);
+    }
+    progress(1.0);
+
+    let mut screen = Screen::new(width, height);
+    for j in 0..height {
+        for i in 0..width {
+            let k = j * width + i;
+            let color = match weights[k] {
+                w if w != 0.0 => sums[k] / w,
+                _ => sums[k],
+            };
+            screen.update(j, i, color);
         }
     }
+    screen
+}
 
-    screen.write_to_ppm();
+pub fn render_scene(
+    world: Arc<Box<dyn Hittable + Sync>>,
+    cam: Arc<Camera>,
+    background: Vec3,
+    config: Config,
+) {
+    if let Some(seed) = config.seed {
+        mutil::seed(seed);
+    }
+    render_to_screen(world, cam, background, &config).write_to_ppm();
+}
+
+/// Render the same world from several cameras, writing view `k` to `paths[k]` as PPM.
+pub fn render_views(
+    world: Arc<Box<dyn Hittable + Sync>>,
+    cams: Vec<Camera>,
+    paths: &[&str],
+    background: Vec3,
+    config: &Config,
+) {
+    assert_eq!(cams.len(), paths.len(), "need one output path per camera");
+    for (cam, path) in cams.into_iter().zip(paths) {
+        render_to_screen(world.clone(), Arc::new(cam), background, config).write_to_ppm_file(path);
+    }
 }
 
 pub fn render_scene_with_time(t0: f64, t1: f64, path: &str, world: Arc<Box<dyn Hittable + Sync>>) {
@@ -1255,6 +2578,13 @@ pub fn render_scene_with_time(t0: f64, t1: f64, path: &str, world: Arc<Box<dyn H
     let image_height = (image_width as f64 / aspect_ratio) as i32;
     let samples_per_pixel = 500;
     let max_depth = 50;
+    let config = Config::new(
+        aspect_ratio,
+        image_width,
+        samples_per_pixel as i32,
+        max_depth,
+        THREADS,
+    );
     // camera
     let lookfrom = Vec3::new(13, 2, 3);
     let lookat = Vec3::new(0, 0, 0);
@@ -1286,16 +2616,18 @@ pub fn render_scene_with_time(t0: f64, t1: f64, path: &str, world: Arc<Box<dyn H
         let send_clone = sender.clone();
         let shared_world: Arc<Box<dyn Hittable + Sync>> = world.clone();
         let shared_cam = cam.clone();
+        let config = config.clone();
 
         thread::spawn(move || {
             for j in start..end {
                 for i in 0..image_width {
                     let mut pixel = Vec3::new(0, 0, 0);
                     for _ in 0..samples_per_pixel {
-                        let u = (i as f64 + thread_rng().gen::<f64>()) / (image_width - 1) as f64;
-                        let v = (j as f64 + thread_rng().gen::<f64>()) / (image_height - 1) as f64;
+                        let u = (i as f64 + rng().gen::<f64>()) / (image_width - 1) as f64;
+                        let v = (j as f64 + rng().gen::<f64>()) / (image_height - 1) as f64;
                         let r = shared_cam.get_ray(u, v);
-                        pixel += ray_color(&r, &background, shared_world.as_ref(), max_depth);
+                        pixel +=
+                            ray_color(&r, &background, shared_world.as_ref().as_ref(), &config);
                     }
                     send_clone
                         .send((
@@ -1328,3 +2660,1191 @@ pub fn render_scene_with_time(t0: f64, t1: f64, path: &str, world: Arc<Box<dyn H
 
     screen.write_to_ppm_file(path);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single_sphere(material: Box<dyn Material>) -> Box<dyn Hittable + Sync> {
+        let mut list = HittableList::new();
+        list.add(Arc::new(Box::new(Sphere::new(
+            Point3::new(0, 0, 0),
+            1.0,
+            Arc::new(material),
+        ))));
+        Box::new(list)
+    }
+
+    fn toward_origin() -> Ray {
+        Ray::new(&Point3::new(0, 0, 5), &Vec3::new(0, 0, -1), 0.0)
+    }
+
+    #[test]
+    fn clay_mode_renders_mirror_diffuse() {
+        let world = single_sphere(Box::new(Metal::new(Color::new(1, 1, 1), 0.0)));
+        let background = Color::new(0.7, 0.8, 1);
+        let shaded = Config::new(1.0, 10, 1, 10, 1);
+        let clay = shaded
+            .clone()
+            .with_mode(RenderMode::Clay(Color::new(0.5, 0.5, 0.5)));
+
+        // a perfect white mirror just shows the background
+        assert_eq!(
+            ray_color(&toward_origin(), &background, world.as_ref(), &shaded),
+            background
+        );
+        // a diffuse bounce off a convex sphere always escapes, attenuated by the clay albedo
+        for _ in 0..100 {
+            assert_eq!(
+                ray_color(&toward_origin(), &background, world.as_ref(), &clay),
+                0.5 * background
+            );
+        }
+    }
+
+    #[test]
+    fn wireframe_mode_marks_triangle_edges() {
+        let mut list = HittableList::new();
+        list.add(Arc::new(Box::new(Triangle::new(
+            Point3::new(0, 0, 0),
+            Point3::new(1, 0, 0),
+            Point3::new(0, 1, 0),
+            Arc::new(Box::new(DiffuseLight::new(&Color::new(1, 0, 0)))),
+        ))));
+        let world: Box<dyn Hittable + Sync> = Box::new(list);
+        let wire = Color::new(0, 1, 0);
+        let config =
+            Config::new(1.0, 10, 1, 10, 1).with_mode(RenderMode::Wireframe { wire, width: 0.05 });
+        let down_at = |x: f64, y: f64| Ray::new(&Point3::new(x, y, 1), &Vec3::new(0, 0, -1), 0.0);
+        let black = Color::new(0, 0, 0);
+
+        assert_eq!(
+            ray_color(&down_at(0.5, 0.01), &black, world.as_ref(), &config),
+            wire
+        );
+        assert_eq!(
+            ray_color(&down_at(0.3, 0.3), &black, world.as_ref(), &config),
+            Color::new(1, 0, 0)
+        );
+    }
+
+    #[test]
+    fn uv_mode_shows_texture_coordinates() {
+        let config = Config::new(1.0, 10, 1, 10, 1).with_mode(RenderMode::Uv);
+        let black = Color::new(0, 0, 0);
+        // the sphere's +z pole is a quarter of the way around and halfway up
+        let sphere = single_sphere(Box::new(Lambertian::new(Color::new(1, 1, 1))));
+        crate::assert_vec3_eq!(
+            ray_color(&toward_origin(), &black, sphere.as_ref(), &config),
+            Color::new(0.25, 0.5, 0)
+        );
+
+        let mut list = HittableList::new();
+        list.add(Arc::new(Box::new(Triangle::new(
+            Point3::new(0, 0, 0),
+            Point3::new(1, 0, 0),
+            Point3::new(0, 1, 0),
+            Arc::new(Box::new(Lambertian::new(Color::new(1, 1, 1)))),
+        ))));
+        let down_at = |x: f64, y: f64| Ray::new(&Point3::new(x, y, 1), &Vec3::new(0, 0, -1), 0.0);
+        for (x, y) in [(0.1, 0.1), (0.6, 0.2), (0.2, 0.7)] {
+            crate::assert_vec3_eq!(
+                ray_color(&down_at(x, y), &black, &list, &config),
+                Color::new(x, y, 0)
+            );
+        }
+        assert_eq!(ray_color(&down_at(2.0, 2.0), &black, &list, &config), black);
+    }
+
+    #[test]
+    fn missing_texture_fails_scene_build() {
+        // a fresh directory can't hold the texture, whatever the working tree has in it
+        let dir = std::env::temp_dir().join(format!("missing_texture_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("earthshit.ppm");
+        let result = earth(path.to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            matches!(result, Err(ImageError::NotFound(name)) if name == path.to_str().unwrap())
+        );
+    }
+
+    #[test]
+    fn light_through_overlapping_glass_and_water_meets_both_iors() {
+        // glass and water overlap on the x axis, with a lamp behind them; head-on, nothing
+        // bends and each interface only reflects its Fresnel share back into the dark
+        mutil::seed(7);
+        let mut list = HittableList::new();
+        let glass: Arc<Box<dyn Material>> = Arc::new(Box::new(Dielectric::new(1.5)));
+        let water: Arc<Box<dyn Material>> = Arc::new(Box::new(Dielectric::new(1.33)));
+        list.add(Arc::new(Box::new(Sphere::new(
+            Point3::new(0, 0, 0),
+            1.0,
+            glass,
+        ))));
+        list.add(Arc::new(Box::new(Sphere::new(
+            Point3::new(1, 0, 0),
+            1.0,
+            water,
+        ))));
+        let lamp = DiffuseLight::new(&Color::new(1, 1, 1));
+        list.add(Arc::new(Box::new(YzRect::new(
+            -5.0,
+            5.0,
+            -5.0,
+            5.0,
+            5.0,
+            Arc::new(Box::new(lamp)),
+        ))));
+        let config = Config::new(1.0, 10, 1, 50, 1);
+        let r = Ray::new(&Point3::new(-5, 0, 0), &Vec3::new(1, 0, 0), 0.0);
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += ray_color(&r, &Color::new(0, 0, 0), &list, &config).get_x();
+        }
+
+        // air to glass, glass to water, then water to air; the glass exit is inside the water
+        let passed = |a: f64, b: f64| 1.0 - ((a - b) / (a + b)).powi(2);
+        let nested = passed(1.0, 1.5) * passed(1.5, 1.33) * passed(1.33, 1.0);
+        // treating each sphere as if it sat in air would cross four air interfaces instead
+        let unnested = passed(1.0, 1.5).powi(2) * passed(1.0, 1.33).powi(2);
+        let mean = sum / n as f64;
+        assert!((mean - nested).abs() < 0.01, "{} vs {}", mean, nested);
+        assert!((mean - unnested).abs() > 0.03);
+    }
+
+    #[test]
+    fn clay_mode_keeps_lights() {
+        let world = single_sphere(Box::new(DiffuseLight::new(&Color::new(4, 4, 4))));
+        let config =
+            Config::new(1.0, 10, 1, 10, 1).with_mode(RenderMode::Clay(Color::new(0.5, 0.5, 0.5)));
+        assert_eq!(
+            ray_color(
+                &toward_origin(),
+                &Color::new(0, 0, 0),
+                world.as_ref(),
+                &config
+            ),
+            Color::new(4, 4, 4)
+        );
+    }
+
+    #[test]
+    fn edge_pixels_get_more_samples() {
+        // left half black, right half white: the edge sits between columns 7 and 8
+        let mut coarse = Screen::new(16, 8);
+        for j in 0..8 {
+            for i in 8..16 {
+                coarse.update(j, i, Color::new(1, 1, 1));
+            }
+        }
+        let counts = edge_sample_counts(&coarse, 16);
+        let at = |j: usize, i: usize| counts[j * 16 + i];
+
+        for j in 0..8 {
+            assert!(at(j, 7) > at(j, 2));
+            assert!(at(j, 8) > at(j, 13));
+            assert!(at(j, 0) >= 1);
+        }
+        // the edge-heavy split still spends about the uniform budget
+        let total: usize = counts.iter().sum();
+        assert!((total as f64 - 16.0 * 16.0 * 8.0).abs() < 16.0 * 8.0);
+    }
+
+    #[test]
+    fn edge_aware_refinement_continues_the_halton_sequence() {
+        // unseeded, so both passes share the Halton shift; only the sample index tells them apart
+        let world = single_sphere(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let (world, cam) = (world.as_ref(), get_world_cam(4).unwrap().1);
+        let background = Color::new(0, 0, 0);
+        let first = Config::new(1.0, 8, 4, 2, 1).with_sampler(Sampler::Halton);
+        let mut second = first.clone();
+        second.first_sample = 1;
+        let at = |config: &Config, n| camera_sample(world, &cam, &background, config, 3, 5, n).0;
+        assert_ne!(at(&second, 0), at(&first, 0));
+        assert_eq!(at(&second, 0), at(&first, 1));
+    }
+
+    #[test]
+    fn flat_image_samples_uniformly() {
+        let counts = edge_sample_counts(&Screen::new(4, 4), 9);
+        assert!(counts.iter().all(|&n| n == 9));
+    }
+
+    #[test]
+    fn spectral_filters_multiply_as_spectra() {
+        // a ray bounces off two tinted mirrors, floor then wall, and escapes to a white sky
+        let yellow = Color::new(0.95, 0.9, 0.05);
+        let cyan = Color::new(0.05, 0.9, 0.95);
+        let mut list = HittableList::new();
+        list.add(Arc::new(Box::new(XzRect::new(
+            -0.5,
+            0.5,
+            -1.0,
+            1.0,
+            0.0,
+            Arc::new(Box::new(Metal::new(yellow, 0.0))),
+        ))));
+        list.add(Arc::new(Box::new(YzRect::new(
+            0.5,
+            1.5,
+            -1.0,
+            1.0,
+            1.0,
+            Arc::new(Box::new(Metal::new(cyan, 0.0))),
+        ))));
+        let world: Box<dyn Hittable + Sync> = Box::new(list);
+        let white = Color::new(1, 1, 1);
+        let r = Ray::new(&Point3::new(-1, 1, 0), &Vec3::new(1, -1, 0), 0.0);
+
+        let steps = 2000;
+        let mut expected = Color::new(0, 0, 0);
+        for s in 0..steps {
+            let span = spectrum::LAMBDA_MAX - spectrum::LAMBDA_MIN;
+            let l = spectrum::LAMBDA_MIN + (s as f64 + 0.5) / steps as f64 * span;
+            let product = spectrum::reflectance(&yellow, l) * spectrum::reflectance(&cyan, l);
+            expected += spectrum::to_rgb(l, product);
+        }
+        expected /= steps as f64;
+
+        let rgb = ray_color(&r, &white, world.as_ref(), &Config::new(1.0, 10, 1, 10, 1));
+        assert_eq!(rgb, yellow * cyan);
+
+        mutil::seed(11);
+        let config = Config::new(1.0, 10, 1, 10, 1).with_spectral();
+        let samples = 2000;
+        let mut spectral = Color::new(0, 0, 0);
+        for _ in 0..samples {
+            spectral += ray_color(&r, &white, world.as_ref(), &config);
+        }
+        spectral /= samples as f64;
+
+        assert!((spectral - expected).length() * 4.0 < (rgb - expected).length());
+    }
+
+    #[test]
+    fn stats_count_every_sample_across_workers() {
+        let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(single_sphere(Box::new(
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        )));
+        let cam = Arc::new(Camera::new(
+            Point3::new(0, 0, 4),
+            Point3::new(0, 0, 0),
+            Vec3::new(0, 1, 0),
+            40.0,
+            2.0,
+            0.0,
+            4.0,
+            0.0,
+            1.0,
+        ));
+        let stats = Arc::new(RenderStats::new());
+        let config = Config::new(2.0, 20, 3, 5, 2).with_stats(stats.clone());
+        render_hdr(world, cam, Color::new(0.7, 0.8, 1), &config);
+
+        assert_eq!(stats.get_samples(), 20 * 10 * 3);
+        assert_eq!(stats.get_rays(), stats.get_hits() + stats.get_escapes());
+        assert!(stats.get_rays() >= stats.get_samples());
+        assert!(stats.get_hits() > 0);
+    }
+
+    #[test]
+    fn escaped_rays_use_sky_closure() {
+        let world = single_sphere(Box::new(Metal::new(Color::new(0.5, 0.5, 0.5), 0.0)));
+        let up = Color::new(0, 0, 1);
+        let down = Color::new(1, 0, 0);
+        let sky: Sky = Arc::new(move |r: &Ray| match r.get_direction().get_y() > 0.0 {
+            true => up,
+            false => down,
+        });
+        let config = Config::new(1.0, 10, 1, 10, 1).with_sky(sky);
+        let black = Color::new(0, 0, 0);
+        let from = |y: f64| Ray::new(&Point3::new(0, 3, 0), &Vec3::new(0, y, 0), 0.0);
+
+        assert_eq!(ray_color(&from(1.0), &black, world.as_ref(), &config), up);
+        // bounces off the top of the mirror sphere and escapes upward
+        assert_eq!(
+            ray_color(&from(-1.0), &black, world.as_ref(), &config),
+            0.5 * up
+        );
+        let sideways = Ray::new(&Point3::new(0, 3, 0), &Vec3::new(1, -0.1, 0), 0.0);
+        assert_eq!(ray_color(&sideways, &black, world.as_ref(), &config), down);
+    }
+
+    #[test]
+    fn raycast_returns_surface_distance() {
+        let world = single_sphere(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let rec = raycast(
+            world.as_ref(),
+            Point3::new(0, 0, 5),
+            Vec3::new(0, 0, -3),
+            100.0,
+        )
+        .unwrap();
+        assert!((rec.get_t() - 4.0).abs() < 1e-9);
+        crate::assert_vec3_eq!(*rec.get_p(), Point3::new(0, 0, 1));
+
+        assert!(raycast(
+            world.as_ref(),
+            Point3::new(0, 0, 5),
+            Vec3::new(0, 0, -1),
+            3.0
+        )
+        .is_none());
+        assert!(raycast(
+            world.as_ref(),
+            Point3::new(0, 0, 5),
+            Vec3::new(0, 1, 0),
+            100.0
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn render_returns_rgba_bytes() {
+        let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(Box::new(HittableList::new()));
+        let cam = Arc::new(Camera::new(
+            Point3::new(0, 0, 4),
+            Point3::new(0, 0, 0),
+            Vec3::new(0, 1, 0),
+            40.0,
+            2.0,
+            0.0,
+            4.0,
+            0.0,
+            1.0,
+        ));
+        let config = Config::new(2.0, 8, 2, 3, 1);
+        let bytes = render(world, cam, Color::new(0.25, 0.25, 1), &config);
+
+        assert_eq!(bytes.len(), 8 * 4 * 4);
+        // gamma 2 and the 255.9 scale: 255.9 * sqrt(0.25) truncates to 127
+        for pixel in bytes.chunks(4) {
+            assert_eq!(pixel, &[127, 127, 255, 255]);
+        }
+    }
+
+    #[test]
+    fn emissive_mesh_lights_the_floor() {
+        let path = std::env::temp_dir().join("emissive_quad.ply");
+        std::fs::write(
+            &path,
+            "ply\nformat ascii 1.0\nelement vertex 4\nelement face 2\nend_header\n\
+             -1 0 -1\n1 0 -1\n1 0 1\n-1 0 1\n3 0 1 2\n3 0 2 3\n",
+        )
+        .unwrap();
+        let model = TriangleModel::load_from_file(path.to_str().unwrap(), 1.0).unwrap();
+        let scene = |mesh_mat: Box<dyn Material>| -> HittableList {
+            let mesh: Arc<Box<dyn Hittable + Send + Sync>> =
+                Arc::new(Box::new(model.to_hittable_with(Arc::new(mesh_mat))));
+            let mut world = HittableList::new();
+            world.add(Arc::new(Box::new(Translate::new(
+                &Vec3::new(0, 1, 0),
+                mesh,
+            ))));
+            world.add(Arc::new(Box::new(XzRect::new(
+                -5.0,
+                5.0,
+                -5.0,
+                5.0,
+                0.0,
+                Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+            ))));
+            world
+        };
+        let lit = scene(Box::new(DiffuseLight::new(&Color::new(4, 4, 4))));
+        let dark = scene(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+
+        let mut lights = HittableList::new();
+        lit.collect_lights(&mut lights);
+        assert_eq!(lights.leaf_count(), 2);
+        let mut none = HittableList::new();
+        dark.collect_lights(&mut none);
+        assert!(none.get_objects().is_empty());
+
+        // with a black background the floor under the mesh is lit only by the mesh
+        mutil::seed(7);
+        let config = Config::new(1.0, 10, 1, 2, 1);
+        let black = Color::new(0, 0, 0);
+        let down = Ray::new(&Point3::new(0, 0.5, 0), &Vec3::new(0, -1, 0), 0.0);
+        let average = |world: &HittableList| {
+            let total = (0..200).fold(Color::new(0, 0, 0), |acc, _| {
+                acc + ray_color(&down, &black, world, &config)
+            });
+            post::luminance(&total) / 200.0
+        };
+        assert!(average(&lit) > 0.5);
+        assert_eq!(average(&dark), 0.0);
+    }
+
+    #[test]
+    fn progress_rises_to_one() {
+        let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(single_sphere(Box::new(
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        )));
+        let cam = Arc::new(Camera::new(
+            Point3::new(0, 0, 5),
+            Point3::new(0, 0, 0),
+            Vec3::new(0, 1, 0),
+            40.0,
+            2.0,
+            0.0,
+            5.0,
+            0.0,
+            1.0,
+        ));
+        let base = Config::new(2.0, 40, 4, 4, 2)
+            .with_seed(1)
+            .with_flush_size(5);
+        for config in [base.clone(), base.with_edge_aware()] {
+            let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let sink = seen.clone();
+            let config = config.with_progress(Arc::new(move |f| sink.lock().unwrap().push(f)));
+            render_hdr(world.clone(), cam.clone(), Color::new(0.7, 0.8, 1), &config);
+            let seen = seen.lock().unwrap();
+            assert!(seen.len() > 2);
+            assert!(seen.windows(2).all(|w| w[0] < w[1]));
+            assert!((seen.last().unwrap() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    struct NanAttenuation;
+
+    impl Material for NanAttenuation {
+        fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+            let scattered = Ray::new(rec.get_p(), rec.get_normal(), r_in.get_time());
+            Some((scattered, Color::new(f64::NAN, 0.5, 0.5)))
+        }
+    }
+
+    #[test]
+    fn nan_attenuation_leaves_pixel_finite() {
+        let world: Arc<Box<dyn Hittable + Sync>> =
+            Arc::new(single_sphere(Box::new(NanAttenuation)));
+        let background = Color::new(0.7, 0.8, 1);
+        let config = Config::new(1.0, 10, 4, 10, 1).with_seed(2);
+        let color = ray_color(
+            &toward_origin(),
+            &background,
+            world.as_ref().as_ref(),
+            &config,
+        );
+        assert_eq!(color, Color::new(0, 0, 0));
+
+        let cam = Arc::new(Camera::new(
+            Point3::new(0, 0, 5),
+            Point3::new(0, 0, 0),
+            Vec3::new(0, 1, 0),
+            40.0,
+            1.0,
+            0.0,
+            5.0,
+            0.0,
+            1.0,
+        ));
+        let hdr = render_hdr(world, cam, background, &config);
+        // the centre pixel sees the sphere; the corners see the sky
+        assert!(hdr.get(5, 5).is_finite());
+        assert_eq!(*hdr.get(5, 5), Color::new(0, 0, 0));
+        assert_eq!(*hdr.get(0, 0), background);
+    }
+
+    #[test]
+    fn roulette_waits_for_min_bounces() {
+        // inside a closed sphere every bounce hits again, so only roulette can end a path early
+        let world = single_sphere(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let center = Ray::new(&Point3::new(0, 0, 0), &Vec3::new(0, 0, 1), 0.0);
+        let rays_with = |min_bounces: i32| {
+            let stats = Arc::new(RenderStats::new());
+            let config = Config::new(1.0, 10, 1, 8, 1)
+                .with_min_bounces(min_bounces)
+                .with_stats(stats.clone());
+            mutil::seed(5);
+            for _ in 0..100 {
+                ray_color(&center, &Color::new(0, 0, 0), world.as_ref(), &config);
+            }
+            stats.get_rays()
+        };
+        assert_eq!(rays_with(8), 800);
+        assert!(rays_with(2) < 800);
+    }
+
+    #[test]
+    fn auto_frame_fits_sphere() {
+        let sphere = Sphere::new(
+            Point3::new(3, 1, -2),
+            2.0,
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+        );
+        for aspect_ratio in [0.5, 1.0, 16.0 / 9.0] {
+            let cam = auto_frame(&sphere, aspect_ratio, 0.0, 1.0).unwrap();
+            assert!(sphere
+                .hit(&cam.get_ray(0.5, 0.5), 0.001, f64::INFINITY)
+                .is_some());
+            // all of the sphere is inside the frame: rays around the border miss it
+            for k in 0..=20 {
+                let f = k as f64 / 20.0;
+                for (s, t) in [(f, 0.0), (f, 1.0), (0.0, f), (1.0, f)] {
+                    let r = cam.get_ray(s, t);
+                    assert!(sphere.hit(&r, 0.001, f64::INFINITY).is_none());
+                }
+            }
+            // and it isn't tiny: across the narrower side it spans more than a third of the frame
+            let (s, t) = if aspect_ratio < 1.0 {
+                (0.3, 0.5)
+            } else {
+                (0.5, 0.3)
+            };
+            assert!(sphere
+                .hit(&cam.get_ray(s, t), 0.001, f64::INFINITY)
+                .is_some());
+        }
+        assert!(auto_frame(&HittableList::new(), 1.0, 0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn auto_frame_follows_the_shutter_interval() {
+        // by the time the shutter opens the sphere has moved well away from where it started
+        let sphere = MovingSphere::new(
+            Point3::new(0, 0, 0),
+            Point3::new(10, 0, 0),
+            0.0,
+            1.0,
+            6.0,
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+        );
+        let cam = auto_frame(&sphere, 1.0, 2.0, 3.0).unwrap();
+        for _ in 0..20 {
+            let r = cam.get_ray(0.5, 0.5);
+            assert!((2.0..=3.0).contains(&r.get_time()));
+            assert!(sphere.hit(&r, 0.001, f64::INFINITY).is_some());
+        }
+        let early = auto_frame(&sphere, 1.0, 0.0, 1.0).unwrap();
+        let r = early.get_ray(0.5, 0.5);
+        let late = Ray::new(r.get_origin(), r.get_direction(), 2.5);
+        assert!(sphere.hit(&late, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn grazing_bounce_on_huge_sphere_escapes() {
+        let radius = 1.0e7;
+        let center = Point3::new(0, -radius, 0);
+        let mut list = HittableList::new();
+        list.add(Arc::new(Box::new(Sphere::new(
+            center,
+            radius,
+            Arc::new(Box::new(Metal::new(Color::new(0.5, 0.5, 0.5), 0.0))),
+        ))));
+        let background = Color::new(0.7, 0.8, 1);
+        // a mirror bounce that skims the surface at a 1e-7 angle
+        let spoiled_bounces = |config: &Config| {
+            (0..200)
+                .filter(|&k| {
+                    let a = 1e-3 * k as f64;
+                    let normal = Vec3::new(f64::sin(a), f64::cos(a), 0).unit();
+                    let dir = -1.0 * normal.cross(&Vec3::new(0, 0, 1)) - 1e-7 * normal;
+                    let r = Ray::new(&(center + radius * normal - 50.0 * dir), &dir, 0.0);
+                    ray_color(&r, &background, &list, config) != 0.5 * background
+                })
+                .count()
+        };
+        let config = Config::new(1.0, 10, 1, 10, 1);
+        assert!(spoiled_bounces(&config.clone().with_normal_offset(0.0)) > 0);
+        assert_eq!(spoiled_bounces(&config), 0);
+    }
+
+    #[test]
+    fn mis_beats_either_strategy_on_cornell_box() {
+        let world = cornell_box();
+        let black = Color::new(0, 0, 0);
+        // the middle of the floor, lit directly from far across the room, and the strip of
+        // ceiling just above the light, which sits almost on top of a huge emitter
+        let eye = Point3::new(278, 278, -800);
+        let rays = [
+            Ray::new(&eye, &(Point3::new(278, 0, 278) - eye), 0.0),
+            Ray::new(&Point3::new(278, 554.5, 280), &Vec3::new(0.3, 1, 0.2), 0.0),
+        ];
+        let relative_variance = |strategy: LightSampling| {
+            let config = Config::new(1.0, 10, 1, 3, 1)
+                .with_light_sampling(strategy)
+                .with_lights_from(world.as_ref());
+            mutil::seed(7);
+            let n = 2000;
+            rays.iter()
+                .map(|r| {
+                    let xs: Vec<f64> = (0..n)
+                        .map(|_| post::luminance(&ray_color(r, &black, world.as_ref(), &config)))
+                        .collect();
+                    let mean = xs.iter().sum::<f64>() / n as f64;
+                    let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+                    var / (mean * mean)
+                })
+                .sum::<f64>()
+        };
+        let brdf = relative_variance(LightSampling::Brdf);
+        let lights = relative_variance(LightSampling::Lights);
+        let mis = relative_variance(LightSampling::Mis);
+        assert!(mis * 10.0 < brdf, "mis {} brdf {}", mis, brdf);
+        assert!(mis * 10.0 < lights, "mis {} lights {}", mis, lights);
+    }
+
+    #[test]
+    fn shadowless_blocker_lets_light_through() {
+        use crate::hit::Visibility;
+        let gray: Arc<Box<dyn Material>> =
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let scene = |blocker: Box<dyn Hittable + Send + Sync>| {
+            let mut list = HittableList::new();
+            list.add(Arc::new(Box::new(XzRect::new(
+                -10.0,
+                10.0,
+                -10.0,
+                10.0,
+                0.0,
+                gray.clone(),
+            ))));
+            list.add(Arc::new(Box::new(XzRect::new(
+                -1.0,
+                1.0,
+                -1.0,
+                1.0,
+                4.0,
+                Arc::new(Box::new(DiffuseLight::new(&Color::new(4, 4, 4)))),
+            ))));
+            list.add(Arc::new(blocker));
+            list
+        };
+        let blocker = || XzRect::new(-2.0, 2.0, -2.0, 2.0, 2.0, gray.clone());
+        let plain = scene(Box::new(blocker()));
+        let shadowless = scene(Box::new(
+            Visibility::new(Arc::new(Box::new(blocker()))).with_shadow(false),
+        ));
+
+        // the camera still sees the blocker
+        let at_blocker = Ray::new(&Point3::new(3, 3, 0), &Vec3::new(-1, -0.5, 0), 0.0);
+        let rec = shadowless.hit(&at_blocker, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.get_p().get_y() - 2.0).abs() < 1e-9);
+
+        // but the floor underneath it is lit as if it weren't there
+        let at_floor = Ray::new(&Point3::new(5, 1, 0), &Vec3::new(-5, -1, 0), 0.0);
+        let black = Color::new(0, 0, 0);
+        let config = Config::new(1.0, 10, 1, 2, 1)
+            .with_light_sampling(LightSampling::Lights)
+            .with_lights_from(&plain);
+        let mean = |world: &HittableList| {
+            mutil::seed(2);
+            (0..500)
+                .map(|_| post::luminance(&ray_color(&at_floor, &black, world, &config)))
+                .sum::<f64>()
+                / 500.0
+        };
+        assert_eq!(mean(&plain), 0.0);
+        assert!(mean(&shadowless) > 0.01);
+    }
+
+    #[test]
+    fn mismatched_camera_aspect_warns() {
+        let camera = |aspect_ratio| {
+            Camera::new(
+                Point3::new(0, 0, 5),
+                Point3::new(0, 0, 0),
+                Vec3::new(0, 1, 0),
+                40.0,
+                aspect_ratio,
+                0.0,
+                5.0,
+                0.0,
+                1.0,
+            )
+        };
+        // 16:9 at 400 wide truncates to 225 rows exactly, 3:2 to 266 rows
+        assert!(aspect_warning(&camera(16.0 / 9.0), 400, 225).is_none());
+        assert!(aspect_warning(&camera(1.5), 400, 266).is_none());
+        let warning = aspect_warning(&camera(1.0), 400, 225).unwrap();
+        assert!(warning.contains("400x225"), "{}", warning);
+    }
+
+    #[test]
+    fn mirror_paths_outlast_diffuse_ones() {
+        // a corridor between two facing walls, open at the sides
+        let corridor = |material: Arc<Box<dyn Material>>| {
+            let mut list = HittableList::new();
+            list.add(Arc::new(Box::new(XyRect::new(
+                -2.0,
+                2.0,
+                -2.0,
+                2.0,
+                -1.0,
+                material.clone(),
+            ))));
+            list.add(Arc::new(Box::new(XyRect::new(
+                -2.0, 2.0, -2.0, 2.0, 1.0, material,
+            ))));
+            list
+        };
+        let mean_length = |world: &HittableList| {
+            let stats = Arc::new(RenderStats::new());
+            let config = Config::new(1.0, 10, 1, 20, 1).with_stats(stats.clone());
+            mutil::seed(4);
+            let r = Ray::new(&Point3::new(0, 0, 0), &Vec3::new(0.05, 0.02, 1), 0.0);
+            for _ in 0..500 {
+                ray_color(&r, &Color::new(1, 1, 1), world, &config);
+            }
+            assert_eq!(stats.get_path_lengths().iter().sum::<u64>(), 500);
+            stats.get_mean_path_length()
+        };
+        let mirrors = mean_length(&corridor(Arc::new(Box::new(Metal::new(
+            Color::new(1, 1, 1),
+            0.0,
+        )))));
+        let diffuse = mean_length(&corridor(Arc::new(Box::new(Lambertian::new(Color::new(
+            0.5, 0.5, 0.5,
+        ))))));
+        assert!(
+            mirrors > diffuse + 5.0,
+            "mirrors {} diffuse {}",
+            mirrors,
+            diffuse
+        );
+    }
+
+    #[test]
+    fn few_pixels_split_samples_across_threads() {
+        use std::collections::HashSet;
+        use std::sync::Mutex;
+        use std::thread::ThreadId;
+
+        struct ThreadsSeen {
+            inner: Box<dyn Hittable + Sync>,
+            seen: Arc<Mutex<HashSet<ThreadId>>>,
+        }
+        impl Hittable for ThreadsSeen {
+            fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+                self.seen.lock().unwrap().insert(thread::current().id());
+                self.inner.hit(r, t_min, t_max)
+            }
+            fn bounding_box(&self, time0: f64, time1: f64) -> Option<crate::aabb::Aabb> {
+                self.inner.bounding_box(time0, time1)
+            }
+        }
+
+        let render = |threads: usize| {
+            let seen = Arc::new(Mutex::new(HashSet::new()));
+            let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(Box::new(ThreadsSeen {
+                inner: single_sphere(Box::new(Lambertian::new(Color::splat(0.5)))),
+                seen: seen.clone(),
+            }));
+            let cam = Arc::new(Camera::new(
+                Point3::new(0, 0, 3),
+                Point3::new(0, 0, 0),
+                Vec3::new(0, 1, 0),
+                40.0,
+                1.0,
+                0.0,
+                3.0,
+                0.0,
+                1.0,
+            ));
+            let config = Config::new(1.0, 2, 1000, 4, threads)
+                .with_seed(5)
+                .with_progress(Arc::new(|_| ()));
+            let hdr = render_hdr(world, cam, Color::new(0.7, 0.8, 1), &config);
+            let threads_seen = seen.lock().unwrap().len();
+            (hdr, threads_seen)
+        };
+
+        // four pixels between eight threads, so each pixel's samples have to be shared
+        let (serial, _) = render(1);
+        let (parallel, threads_seen) = render(8);
+        assert_eq!(threads_seen, 8);
+        for j in 0..2 {
+            for i in 0..2 {
+                assert_eq!(serial.get(j, i), parallel.get(j, i), "pixel {} {}", j, i);
+            }
+        }
+    }
+
+    #[test]
+    fn merged_partials_match_a_longer_render() {
+        let world: Arc<Box<dyn Hittable + Sync>> =
+            Arc::new(single_sphere(Box::new(Lambertian::new(Color::splat(0.5)))));
+        let cam = Arc::new(Camera::new(
+            Point3::new(0, 0, 3),
+            Point3::new(0, 0, 0),
+            Vec3::new(0, 1, 0),
+            40.0,
+            2.0,
+            0.0,
+            3.0,
+            0.0,
+            1.0,
+        ));
+        let background = Color::new(0.7, 0.8, 1);
+        let render = |samples: i32, seed: u64| {
+            let config = Config::new(2.0, 8, samples, 5, 1)
+                .with_seed(seed)
+                .with_progress(Arc::new(|_| ()));
+            render_hdr(world.clone(), cam.clone(), background, &config)
+        };
+        let mut merged = render(100, 1);
+        merged.merge(&render(100, 2), 100, 100);
+        let full = render(200, 3);
+
+        let mut total_error = 0.0;
+        for j in 0..4 {
+            for i in 0..8 {
+                let error = (*merged.get(j, i) - *full.get(j, i)).length();
+                assert!(error < 0.1, "pixel {} {}", j, i);
+                total_error += error;
+            }
+        }
+        assert!(total_error / 32.0 < 0.03, "{}", total_error / 32.0);
+    }
+
+    #[test]
+    fn direct_only_leaves_bounce_lit_ceiling_dark() {
+        // a light tucked under a shade: the floor sees it, the ceiling only sees the floor
+        let gray: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.7))));
+        let mut world = HittableList::new();
+        for (half, k) in [(20.0, 0.0), (20.0, 10.0), (3.0, 2.0)] {
+            world.add(Arc::new(Box::new(XzRect::new(
+                -half,
+                half,
+                -half,
+                half,
+                k,
+                gray.clone(),
+            ))));
+        }
+        world.add(Arc::new(Box::new(XzRect::new(
+            -1.0,
+            1.0,
+            -1.0,
+            1.0,
+            1.0,
+            Arc::new(Box::new(DiffuseLight::new(&Color::splat(10)))),
+        ))));
+        let at_ceiling = Ray::new(&Point3::new(0, 5, 0), &Vec3::new(0.1, 1, 0), 0.0);
+        let at_floor = Ray::new(&Point3::new(2, 0.5, 0), &Vec3::new(0, -1, 0), 0.0);
+        let black = Color::zero();
+        let mean = |r: &Ray, config: &Config| {
+            let config = config.with_lights_from(&world);
+            mutil::seed(6);
+            (0..2000)
+                .map(|_| post::luminance(&ray_color(r, &black, &world, &config)))
+                .sum::<f64>()
+                / 2000.0
+        };
+
+        let full = Config::new(1.0, 10, 1, 6, 1);
+        let direct = full.clone().with_direct_only();
+        assert!(mean(&at_ceiling, &full) > 0.01);
+        assert_eq!(mean(&at_ceiling, &direct), 0.0);
+        assert!(mean(&at_floor, &direct) > 0.1);
+    }
+
+    #[test]
+    fn random_scene_params_are_reproducible() {
+        let params = RandomSceneParams::default().with_grid(3).with_seed(21);
+        let count = |params: &RandomSceneParams| gen_random_scene_with(params).len();
+        // where every sphere sits, over the scene's shutter interval
+        let bounds = |params: &RandomSceneParams| -> Vec<(Vec3, Vec3)> {
+            gen_random_scene_with(params)
+                .get_objects()
+                .iter()
+                .map(|object| {
+                    let bbox = object.bounding_box(0.0, 10.0).unwrap();
+                    (*bbox.get_min(), *bbox.get_max())
+                })
+                .collect()
+        };
+        let first = bounds(&params);
+        // draws in between don't leak into a seeded scene
+        rng().gen::<f64>();
+        assert!(first == bounds(&params));
+        assert!(first != bounds(&params.with_seed(22)));
+
+        // small grids stay clear of the big metal sphere, so every cell gets one
+        // sphere on top of the ground and the three big ones
+        for half_extent in 0..=3 {
+            let n = (2 * half_extent as usize).pow(2);
+            assert_eq!(count(&params.with_grid(half_extent)), n + 4);
+        }
+        assert!(count(&RandomSceneParams::default().with_seed(21)) < 22 * 22 + 4);
+    }
+
+    #[test]
+    fn bigger_cornell_light_has_more_emissive_area() {
+        let emissive_area = |params: &CornellParams| {
+            let mut lights = HittableList::new();
+            cornell(params, |_, _| ()).collect_lights(&mut lights);
+            lights
+                .get_objects()
+                .iter()
+                .map(|light| {
+                    let bbox = light.bounding_box(0.0, 1.0).unwrap();
+                    let size = *bbox.get_max() - *bbox.get_min();
+                    size.get_x() * size.get_z()
+                })
+                .sum::<f64>()
+        };
+        let classic = emissive_area(&CornellParams::default());
+        assert!((classic - 130.0 * 105.0).abs() < 1e-6);
+        let big = CornellParams::default().with_light(113.0, 443.0, 127.0, 432.0);
+        assert!(emissive_area(&big) > 7.0 * classic);
+    }
+
+    #[test]
+    fn seeded_renders_are_bit_identical() {
+        let (world, cam, background) = get_world_cam(4).unwrap();
+        let render = |config: &Config| {
+            let config = config.clone().with_progress(Arc::new(|_| ()));
+            let screen = render_hdr(world.clone(), cam.clone(), background, &config);
+            let mut bits = vec![];
+            for j in 0..screen.get_height() {
+                for i in 0..screen.get_width() {
+                    bits.extend(screen.get(j, i).iter().map(f64::to_bits));
+                }
+            }
+            bits
+        };
+        let rows = Config::new(1.0, 24, 6, 5, 3)
+            .with_seed(13)
+            .with_light_sampling(LightSampling::Mis);
+        // fewer rows than threads, so some workers get none
+        let samples = Config::new(1.0, 4, 32, 5, 8).with_seed(13);
+        for config in [rows.clone(), rows.with_edge_aware(), samples] {
+            let first = render(&config);
+            for _ in 0..3 {
+                assert!(first == render(&config));
+            }
+        }
+    }
+
+    #[test]
+    fn linear_color_space_writes_radiance_as_is() {
+        let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(Box::new(HittableList::new()));
+        let (_, cam, _) = get_world_cam(4).unwrap();
+        let sky: Sky = Arc::new(|_: &Ray| Color::splat(0.25));
+        let config = Config::new(1.0, 4, 1, 2, 1)
+            .with_sky(sky)
+            .with_progress(Arc::new(|_| ()));
+        let pixel = |config: &Config| {
+            *render_to_screen(world.clone(), cam.clone(), Color::zero(), config).get(0, 0)
+        };
+        assert_eq!(pixel(&config), Color::splat(127));
+        assert_eq!(
+            pixel(&config.with_color_space(ColorSpace::Linear)),
+            Color::splat(63)
+        );
+    }
+
+    #[test]
+    fn validate_reports_objects_without_bounding_boxes() {
+        let mut list = HittableList::new();
+        let grey: Arc<Box<dyn Material>> = Arc::new(Box::new(Lambertian::new(Color::splat(0.5))));
+        list.add(Arc::new(Box::new(Sphere::new(
+            Point3::new(0, 0, 0),
+            1.0,
+            grey.clone(),
+        ))));
+        assert!(validate(&list).is_ok());
+
+        list.add(Arc::new(Box::new(HittableList::new())));
+        list.add(Arc::new(Box::new(Sphere::new(
+            Point3::new(3, 0, 0),
+            1.0,
+            grey,
+        ))));
+        let errors = validate(&list).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("object 1"), "{}", errors[0]);
+        assert!(errors[0].contains("HittableList"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn debug_pixel_matches_the_full_render() {
+        let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(single_sphere(Box::new(
+            Lambertian::new(Color::new(0.8, 0.4, 0.2)),
+        )));
+        let cam = Arc::new(Camera::new(
+            Point3::new(0, 0, 4),
+            Point3::new(0, 0, 0),
+            Vec3::new(0, 1, 0),
+            40.0,
+            1.0,
+            0.0,
+            4.0,
+            0.0,
+            1.0,
+        ));
+        let background = Color::new(0.7, 0.8, 1);
+        let config = Config::new(1.0, 9, 400, 10, 1)
+            .with_seed(21)
+            .with_progress(Arc::new(|_| ()));
+        let hdr = render_hdr(world.clone(), cam.clone(), background, &config);
+        // the middle of the sphere, its lower edge, and the sky beside it
+        for (i, j) in [(4, 4), (4, 2), (0, 4)] {
+            let debugged = debug_pixel(world.as_ref().as_ref(), &cam, background, &config, i, j);
+            assert_eq!(debugged, *hdr.get(j, i));
+        }
+        crate::assert_vec3_eq!(
+            debug_pixel(world.as_ref().as_ref(), &cam, background, &config, 0, 4),
+            background
+        );
+    }
+
+    #[test]
+    fn tiny_worlds_skip_the_bvh() {
+        let scene = |n: usize| {
+            let mut list = HittableList::new();
+            for k in 0..n {
+                list.add(Arc::new(Box::new(Sphere::new(
+                    Point3::new(k as f64 * 2.5 - 1.25, 0, 0),
+                    1.0,
+                    Arc::new(Box::new(Lambertian::new(Color::new(0.8, 0.3, 0.2)))),
+                ))));
+            }
+            list
+        };
+        let cam = Arc::new(Camera::new(
+            Point3::new(0, 1, 8),
+            Point3::new(0, 0, 0),
+            Vec3::new(0, 1, 0),
+            40.0,
+            1.0,
+            0.0,
+            8.0,
+            0.0,
+            1.0,
+        ));
+        let config = Config::new(1.0, 12, 4, 5, 1)
+            .with_seed(8)
+            .with_progress(Arc::new(|_| ()));
+        let pixels = |world: Box<dyn Hittable + Sync>| {
+            let hdr = render_hdr(
+                Arc::new(world),
+                cam.clone(),
+                Color::new(0.7, 0.8, 1),
+                &config,
+            );
+            (0..hdr.get_height())
+                .flat_map(|j| (0..hdr.get_width()).map(move |i| (j, i)))
+                .map(|(j, i)| *hdr.get(j, i))
+                .collect::<Vec<Color>>()
+        };
+
+        let small = scene(2).into_world(0.0, 1.0);
+        assert!(small.name().ends_with("HittableList"), "{}", small.name());
+        let tree: Box<dyn Hittable + Sync> = Box::new(BvhNode::from_list(&scene(2), 0.0, 1.0));
+        assert!(pixels(small) == pixels(tree));
+
+        let big = scene(HittableList::BVH_THRESHOLD).into_world(0.0, 1.0);
+        assert!(big.name().ends_with("BvhNode"), "{}", big.name());
+    }
+
+    #[test]
+    fn seeded_renders_match_at_any_thread_count() {
+        let (world, cam, background) = get_world_cam(4).unwrap();
+        let render = |threads: usize| {
+            // 13 rows, which no thread count here divides evenly
+            let config = Config::new(1.0, 13, 8, 5, threads)
+                .with_seed(29)
+                .with_progress(Arc::new(|_| ()));
+            let screen = render_hdr(world.clone(), cam.clone(), background, &config);
+            (0..screen.get_height())
+                .flat_map(|j| (0..screen.get_width()).map(move |i| (j, i)))
+                .map(|(j, i)| *screen.get(j, i))
+                .collect::<Vec<Color>>()
+        };
+        let single = render(1);
+        assert!(single.iter().any(|c| !c.near_zero()));
+        for threads in [2, 3, 8, 16] {
+            assert!(render(threads) == single, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn glowing_enclosure_matches_the_geometric_series() {
+        // inside a closed surface that emits `glow` and reflects `albedo`, every path hits it
+        // at every bounce, so a path of `depth` hits carries exactly
+        // glow * (1 + albedo + ... + albedo^(depth - 1))
+        let glow = Color::new(0.2, 0.4, 0.6);
+        let albedo = 0.7;
+        let depth = 8;
+        let expected = glow * (1.0 - f64::powi(albedo, depth)) / (1.0 - albedo);
+        let material: Arc<Box<dyn Material>> = Arc::new(Box::new(
+            Lambertian::new(Color::splat(albedo)).with_emission(&glow),
+        ));
+        let world: Box<dyn Hittable + Sync> =
+            Box::new(Sphere::new(Point3::new(0, 0, 0), 2.0, material));
+        let cam = Arc::new(Camera::new(
+            Point3::new(0.1, 0.2, 0.3),
+            Point3::new(0, 0, -1),
+            Vec3::new(0, 1, 0),
+            90.0,
+            1.0,
+            0.0,
+            1.0,
+            0.0,
+            1.0,
+        ));
+        let config = Config::new(1.0, 6, 4, depth, 1)
+            .with_seed(3)
+            .with_progress(Arc::new(|_| ()));
+        let hdr = render_hdr(Arc::new(world), cam, Color::zero(), &config);
+        for j in 0..hdr.get_height() {
+            for i in 0..hdr.get_width() {
+                crate::assert_vec3_eq!(*hdr.get(j, i), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn filtered_renders_normalize_and_stay_seeded() {
+        use crate::filter::{BoxFilter, GaussianFilter, MitchellFilter, TentFilter};
+        let (world, cam, background) = get_world_cam(4).unwrap();
+        let pixels = |screen: &Screen| {
+            (0..screen.get_height())
+                .flat_map(|j| (0..screen.get_width()).map(move |i| (j, i)))
+                .map(|(j, i)| *screen.get(j, i))
+                .collect::<Vec<Color>>()
+        };
+        let config = Config::new(1.0, 9, 6, 5, 1)
+            .with_seed(31)
+            .with_progress(Arc::new(|_| ()));
+
+        // a half-pixel box keeps every sample to its own pixel, with weight one
+        let plain = pixels(&render_hdr(world.clone(), cam.clone(), background, &config));
+        let boxed = config.clone().with_filter(Arc::new(BoxFilter::new(0.5)));
+        assert!(pixels(&render_hdr(world.clone(), cam.clone(), background, &boxed)) == plain);
+
+        let filters: [Arc<dyn PixelFilter>; 3] = [
+            Arc::new(TentFilter::new(1.5)),
+            Arc::new(GaussianFilter::new(2.0, 1.0)),
+            Arc::new(MitchellFilter::default()),
+        ];
+        for filter in filters {
+            // splats are added in row order whichever thread traced them
+            let render = |threads: usize| {
+                let mut config = config.clone().with_filter(filter.clone());
+                config.threads = threads;
+                pixels(&render_hdr(world.clone(), cam.clone(), background, &config))
+            };
+            let single = render(1);
+            assert!(single != plain);
+            assert!(render(4) == single);
+
+            // normalizing by the gathered weight gives back a flat sky exactly, negative lobes
+            // and image borders included
+            let empty: Arc<Box<dyn Hittable + Sync>> = Arc::new(Box::new(HittableList::new()));
+            let sky = Color::new(0.3, 0.5, 0.7);
+            let config = config.clone().with_filter(filter.clone());
+            let flat = render_hdr(empty, cam.clone(), sky, &config);
+            for color in pixels(&flat) {
+                crate::assert_vec3_eq!(color, sky);
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{assert_renders_equal, Scene};
+use ray_tracing_series_rust::aabb::Aabb;
+use ray_tracing_series_rust::bvh::BvhNode;
+use ray_tracing_series_rust::camera::Camera;
+use ray_tracing_series_rust::hit::{HitRecord, Hittable, HittableList, Lambertian, Metal, Sphere};
+use ray_tracing_series_rust::ray::Ray;
+use ray_tracing_series_rust::vec3::{Color, Point3, Vec3};
+use ray_tracing_series_rust::world::Config;
+use std::sync::Arc;
+
+struct IterativeBvh(Arc<BvhNode>);
+
+impl Hittable for IterativeBvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.0.hit_iterative(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.0.bounding_box(time0, time1)
+    }
+}
+
+struct RecursiveBvh(Arc<BvhNode>);
+
+impl Hittable for RecursiveBvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.0.hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.0.bounding_box(time0, time1)
+    }
+}
+
+fn sphere_grid() -> BvhNode {
+    let mut list = HittableList::new();
+    list.add(Arc::new(Box::new(Sphere::new(
+        Point3::new(0, -1000, 0),
+        1000.0,
+        Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+    ))));
+    for a in -3..3 {
+        for b in -3..3 {
+            let albedo = Color::new(0.1 * (a + 3) as f64, 0.1 * (b + 3) as f64, 0.5);
+            list.add(Arc::new(Box::new(Sphere::new(
+                Point3::new(a as f64 * 1.5, 0.5, b as f64 * 1.5),
+                0.5,
+                if (a + b) % 2 == 0 {
+                    Arc::new(Box::new(Lambertian::new(albedo)))
+                } else {
+                    Arc::new(Box::new(Metal::new(albedo, 0.2)))
+                },
+            ))));
+        }
+    }
+    BvhNode::from_list(&list, 0.0, 1.0)
+}
+
+fn camera() -> Arc<Camera> {
+    Arc::new(Camera::new(
+        Point3::new(8, 4, 8),
+        Point3::new(0, 0, 0),
+        Vec3::new(0, 1, 0),
+        40.0,
+        1.0,
+        0.0,
+        10.0,
+        0.0,
+        1.0,
+    ))
+}
+
+#[test]
+fn iterative_bvh_renders_like_recursive() {
+    let bvh = Arc::new(sphere_grid());
+    let background = Color::new(0.7, 0.8, 1);
+    let recursive: Scene = (
+        Arc::new(Box::new(RecursiveBvh(bvh.clone()))),
+        camera(),
+        background,
+    );
+    let iterative: Scene = (Arc::new(Box::new(IterativeBvh(bvh))), camera(), background);
+    let config = Config::new(1.0, 32, 4, 8, 2).with_seed(7);
+
+    assert_renders_equal(recursive, iterative, &config, 0.0);
+}
@@ -0,0 +1,36 @@
+#![allow(dead_code)]
+
+use ray_tracing_series_rust::screen::Screen;
+pub use ray_tracing_series_rust::world::Scene;
+use ray_tracing_series_rust::world::{render_to_screen, Config};
+
+pub fn max_pixel_difference(a: &Screen, b: &Screen) -> f64 {
+    assert_eq!(a.get_width(), b.get_width());
+    assert_eq!(a.get_height(), b.get_height());
+    let mut worst: f64 = 0.0;
+    for j in 0..a.get_height() {
+        for i in 0..a.get_width() {
+            let (pa, pb) = (a.get(j, i), b.get(j, i));
+            for (x, y) in pa.iter().zip(pb.iter()) {
+                worst = f64::max(worst, f64::abs(x - y));
+            }
+        }
+    }
+    worst
+}
+
+/// Render both scenes with `config` (which should carry a seed) and assert that no
+/// channel of any pixel differs by more than `tolerance`.
+pub fn assert_renders_equal(scene_a: Scene, scene_b: Scene, config: &Config, tolerance: f64) {
+    let (world_a, cam_a, background_a) = scene_a;
+    let (world_b, cam_b, background_b) = scene_b;
+    let a = render_to_screen(world_a, cam_a, background_a, config);
+    let b = render_to_screen(world_b, cam_b, background_b, config);
+    let worst = max_pixel_difference(&a, &b);
+    assert!(
+        worst <= tolerance,
+        "renders differ by {} (tolerance {})",
+        worst,
+        tolerance
+    );
+}
@@ -0,0 +1,62 @@
+use ray_tracing_series_rust::aabb::Aabb;
+use ray_tracing_series_rust::camera::Camera;
+use ray_tracing_series_rust::hit::{HitRecord, Hittable, Lambertian, Sphere};
+use ray_tracing_series_rust::ray::Ray;
+use ray_tracing_series_rust::vec3::{Color, Point3, Vec3};
+use ray_tracing_series_rust::world::{render_hdr, Config};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A sphere that takes a while to intersect, so a full render is slow.
+struct Slow(Sphere);
+
+impl Hittable for Slow {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        std::thread::sleep(Duration::from_micros(200));
+        self.0.hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.0.bounding_box(time0, time1)
+    }
+}
+
+#[test]
+fn short_deadline_returns_partial_image() {
+    let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(Box::new(Slow(Sphere::new(
+        Point3::new(0, 0, 0),
+        1.0,
+        Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+    ))));
+    let cam = Arc::new(Camera::new(
+        Point3::new(0, 0, 4),
+        Point3::new(0, 0, 0),
+        Vec3::new(0, 1, 0),
+        40.0,
+        2.0,
+        0.0,
+        4.0,
+        0.0,
+        1.0,
+    ));
+    // about 6400 samples at 0.2ms or more each: over a second without the deadline
+    let config = Config::new(2.0, 40, 8, 4, 2)
+        .with_seed(5)
+        .with_deadline(Duration::from_millis(100));
+
+    let started = Instant::now();
+    let hdr = render_hdr(world, cam, Color::new(0.7, 0.8, 1), &config);
+    assert!(started.elapsed() < Duration::from_millis(800));
+
+    assert_eq!((hdr.get_width(), hdr.get_height()), (40, 20));
+    let mut sampled = 0;
+    for j in 0..20 {
+        for i in 0..40 {
+            let c = hdr.get(j, i);
+            assert!(c.get_x().is_finite() && c.get_y().is_finite() && c.get_z().is_finite());
+            if *c != Color::new(0, 0, 0) {
+                sampled += 1;
+            }
+        }
+    }
+    assert!(sampled > 0);
+}
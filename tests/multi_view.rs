@@ -0,0 +1,54 @@
+use ray_tracing_series_rust::camera::Camera;
+use ray_tracing_series_rust::hit::{Hittable, HittableList, Lambertian, Sphere};
+use ray_tracing_series_rust::vec3::{Color, Point3, Vec3};
+use ray_tracing_series_rust::world::{render_views, Config};
+use std::fs;
+use std::sync::Arc;
+
+fn camera_at(lookfrom: Point3) -> Camera {
+    Camera::new(
+        lookfrom,
+        Point3::new(0, 0, 0),
+        Vec3::new(0, 1, 0),
+        40.0,
+        1.0,
+        0.0,
+        10.0,
+        0.0,
+        1.0,
+    )
+}
+
+#[test]
+fn two_cameras_write_two_different_views() {
+    let mut list = HittableList::new();
+    list.add(Arc::new(Box::new(Sphere::new(
+        Point3::new(1, 0, 0),
+        1.0,
+        Arc::new(Box::new(Lambertian::new(Color::new(0.9, 0.1, 0.1)))),
+    ))));
+    let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(Box::new(list));
+
+    let dir = std::env::temp_dir();
+    let left = dir.join("multi_view_left.ppm");
+    let right = dir.join("multi_view_right.ppm");
+    let paths = [left.to_str().unwrap(), right.to_str().unwrap()];
+
+    render_views(
+        world,
+        vec![
+            camera_at(Point3::new(0, 0, 6)),
+            camera_at(Point3::new(0, 0, -6)),
+        ],
+        &paths,
+        Color::new(0.7, 0.8, 1),
+        &Config::new(1.0, 16, 2, 4, 1).with_seed(1),
+    );
+
+    let a = fs::read_to_string(&left).unwrap();
+    let b = fs::read_to_string(&right).unwrap();
+    assert!(a.starts_with("P3\n16 16\n255"));
+    assert_ne!(a, b);
+    fs::remove_file(left).unwrap();
+    fs::remove_file(right).unwrap();
+}
@@ -0,0 +1,65 @@
+use ray_tracing_series_rust::aabb::Aabb;
+use ray_tracing_series_rust::camera::Camera;
+use ray_tracing_series_rust::hit::{HitRecord, Hittable, Lambertian, Sphere};
+use ray_tracing_series_rust::ray::Ray;
+use ray_tracing_series_rust::vec3::{Color, Point3, Vec3};
+use ray_tracing_series_rust::world::{render_to_screen, Config};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Counting {
+    inner: Sphere,
+    calls: Arc<AtomicUsize>,
+}
+
+impl Hittable for Counting {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.inner.hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.inner.bounding_box(time0, time1)
+    }
+}
+
+fn scene(calls: Arc<AtomicUsize>) -> Arc<Box<dyn Hittable + Sync>> {
+    Arc::new(Box::new(Counting {
+        inner: Sphere::new(
+            Point3::new(0, 0, 0),
+            1.0,
+            Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+        ),
+        calls,
+    }))
+}
+
+#[test]
+fn preview_is_full_size_but_cheaper() {
+    let cam = Arc::new(Camera::new(
+        Point3::new(0, 0, 4),
+        Point3::new(0, 0, 0),
+        Vec3::new(0, 1, 0),
+        40.0,
+        2.0,
+        0.5,
+        4.0,
+        0.0,
+        1.0,
+    ));
+    let background = Color::new(0.7, 0.8, 1);
+    let config = Config::new(2.0, 40, 8, 4, 1).with_seed(3);
+
+    let full_calls = Arc::new(AtomicUsize::new(0));
+    let full = render_to_screen(scene(full_calls.clone()), cam.clone(), background, &config);
+    let preview_calls = Arc::new(AtomicUsize::new(0));
+    let preview = render_to_screen(
+        scene(preview_calls.clone()),
+        cam,
+        background,
+        &config.with_preview(0.25),
+    );
+
+    assert_eq!(preview.get_width(), full.get_width());
+    assert_eq!(preview.get_height(), full.get_height());
+    assert!(preview_calls.load(Ordering::Relaxed) * 10 < full_calls.load(Ordering::Relaxed));
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::max_pixel_difference;
+use ray_tracing_series_rust::camera::Camera;
+use ray_tracing_series_rust::hit::{Hittable, HittableList, Lambertian, Metal, Sphere};
+use ray_tracing_series_rust::vec3::{Color, Point3, Vec3};
+use ray_tracing_series_rust::world::{render_to_screen, Config};
+use std::sync::Arc;
+
+#[test]
+fn batched_rows_match_per_pixel_sends() {
+    let mut list = HittableList::new();
+    list.add(Arc::new(Box::new(Sphere::new(
+        Point3::new(0, -100.5, 0),
+        100.0,
+        Arc::new(Box::new(Lambertian::new(Color::new(0.4, 0.6, 0.2)))),
+    ))));
+    list.add(Arc::new(Box::new(Sphere::new(
+        Point3::new(0, 0, 0),
+        0.5,
+        Arc::new(Box::new(Metal::new(Color::new(0.8, 0.8, 0.8), 0.3))),
+    ))));
+    let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(Box::new(list));
+    let cam = Arc::new(Camera::new(
+        Point3::new(0, 0.5, 3),
+        Point3::new(0, 0, 0),
+        Vec3::new(0, 1, 0),
+        50.0,
+        1.5,
+        0.0,
+        3.0,
+        0.0,
+        1.0,
+    ));
+    let background = Color::new(0.7, 0.8, 1);
+    let config = Config::new(1.5, 30, 3, 5, 2).with_seed(11);
+
+    let rows = render_to_screen(world.clone(), cam.clone(), background, &config);
+    let pixels = render_to_screen(
+        world.clone(),
+        cam.clone(),
+        background,
+        &config.clone().with_flush_size(1),
+    );
+    let odd_runs = render_to_screen(world, cam, background, &config.with_flush_size(7));
+
+    assert_eq!(max_pixel_difference(&rows, &pixels), 0.0);
+    assert_eq!(max_pixel_difference(&rows, &odd_runs), 0.0);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::max_pixel_difference;
+use ray_tracing_series_rust::camera::Camera;
+use ray_tracing_series_rust::hit::{Hittable, Lambertian, Sphere};
+use ray_tracing_series_rust::stats::RenderStats;
+use ray_tracing_series_rust::tiles::TileCache;
+use ray_tracing_series_rust::vec3::{Color, Point3, Vec3};
+use ray_tracing_series_rust::world::Config;
+use std::sync::Arc;
+
+fn camera(x: f64) -> Arc<Camera> {
+    Arc::new(Camera::new(
+        Point3::new(x, 0, 4),
+        Point3::new(0, 0, 0),
+        Vec3::new(0, 1, 0),
+        40.0,
+        2.0,
+        0.0,
+        4.0,
+        0.0,
+        1.0,
+    ))
+}
+
+#[test]
+fn unchanged_rerender_reuses_every_tile() {
+    let world: Arc<Box<dyn Hittable + Sync>> = Arc::new(Box::new(Sphere::new(
+        Point3::new(0, 0, 0),
+        1.0,
+        Arc::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+    )));
+    let background = Color::new(0.7, 0.8, 1);
+    let stats = Arc::new(RenderStats::new());
+    let config = Config::new(2.0, 30, 2, 4, 2)
+        .with_seed(9)
+        .with_stats(stats.clone());
+    let mut cache = TileCache::new(8);
+
+    let first = cache.render(world.clone(), camera(0.0), background, &config, 1);
+    assert_eq!(stats.get_samples(), 30 * 15 * 2);
+    assert_eq!(cache.len(), 4 * 2);
+
+    let second = cache.render(world.clone(), camera(0.0), background, &config, 1);
+    assert_eq!(stats.get_samples(), 30 * 15 * 2);
+    assert_eq!(max_pixel_difference(&first, &second), 0.0);
+
+    // a new camera or a new scene key invalidates the tiles
+    cache.render(world.clone(), camera(0.1), background, &config, 1);
+    assert_eq!(stats.get_samples(), 2 * 30 * 15 * 2);
+    cache.render(world, camera(0.1), background, &config, 2);
+    assert_eq!(stats.get_samples(), 3 * 30 * 15 * 2);
+    assert_eq!(cache.len(), 4 * 2);
+}